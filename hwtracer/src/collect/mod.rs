@@ -12,7 +12,7 @@ pub(crate) mod perf;
 #[cfg(all(collector_perf, feature = "yk_testing"))]
 pub use perf::PerfTrace;
 #[cfg(collector_perf)]
-pub(crate) use perf::PerfTraceCollector;
+pub(crate) use perf::{perf_permitted, PerfTraceCollector};
 
 const PERF_DFLT_DATA_BUFSIZE: size_t = 64;
 static PERF_DFLT_AUX_BUFSIZE: LazyLock<size_t> = LazyLock::new(|| {
@@ -88,7 +88,7 @@ pub(crate) trait ThreadTraceCollector {
 }
 
 /// Kinds of collector that hwtracer supports (in order of "auto-selection preference").
-#[derive(Debug, EnumIter)]
+#[derive(Clone, Copy, Debug, EnumIter)]
 pub enum TraceCollectorKind {
     /// The `perf` subsystem, as found on Linux.
     Perf,
@@ -96,12 +96,25 @@ pub enum TraceCollectorKind {
 
 impl TraceCollectorKind {
     /// Finds a suitable `TraceCollectorKind` for the current hardware/OS.
-    fn default_for_platform() -> Option<Self> {
-        TraceCollectorKind::iter().find(|kind| Self::match_platform(kind).is_ok())
+    pub fn default_for_platform() -> Option<Self> {
+        Self::probe_platform().ok()
+    }
+
+    /// Finds a suitable `TraceCollectorKind` for the current hardware/OS. If there is none, returns
+    /// the reason that each kind of collector was rejected.
+    pub fn probe_platform() -> Result<Self, Vec<(Self, HWTracerError)>> {
+        let mut rejected = Vec::new();
+        for kind in Self::iter() {
+            match kind.match_platform() {
+                Ok(()) => return Ok(kind),
+                Err(e) => rejected.push((kind, e)),
+            }
+        }
+        Err(rejected)
     }
 
     /// Returns `Ok` if the this collector is appropriate for the current platform.
-    fn match_platform(&self) -> Result<(), HWTracerError> {
+    pub fn match_platform(&self) -> Result<(), HWTracerError> {
         match self {
             Self::Perf => {
                 #[cfg(not(collector_perf))]
@@ -113,7 +126,7 @@ impl TraceCollectorKind {
                             "Intel PT not supported by CPU".into(),
                         ));
                     }
-                    Ok(())
+                    perf_permitted()
                 }
             }
         }
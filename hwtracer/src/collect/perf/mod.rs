@@ -52,30 +52,33 @@ impl PerfTraceCollector {
             )));
         }
 
-        // Check we have permissions to collect a PT trace using perf.
-        //
-        // Note that root always has permission.
-        //
-        // FIXME: We just assume that we are collecting a PT trace.
-        // https://github.com/ykjit/hwtracer/issues/100
-        if !unsafe { geteuid() } == 0 {
-            let mut f = File::open(PERF_PERMS_PATH)?;
-            let mut buf = String::new();
-            f.read_to_string(&mut buf)?;
-            let perm = buf.trim().parse::<i8>()?;
-            if perm != -1 {
-                let msg = format!(
-                    "Tracing not permitted: you must be root or {} must contain -1",
-                    PERF_PERMS_PATH
-                );
-                return Err(HWTracerError::Permissions(msg));
-            }
-        }
-
         Ok(Self { config })
     }
 }
 
+/// Check we have permissions to collect a PT trace using perf.
+///
+/// Note that root always has permission.
+///
+/// FIXME: We just assume that we are collecting a PT trace.
+/// https://github.com/ykjit/hwtracer/issues/100
+pub(crate) fn perf_permitted() -> Result<(), HWTracerError> {
+    if unsafe { geteuid() } != 0 {
+        let mut f = File::open(PERF_PERMS_PATH)?;
+        let mut buf = String::new();
+        f.read_to_string(&mut buf)?;
+        let perm = buf.trim().parse::<i8>()?;
+        if perm != -1 {
+            let msg = format!(
+                "Tracing not permitted: you must be root or {} must contain -1",
+                PERF_PERMS_PATH
+            );
+            return Err(HWTracerError::Permissions(msg));
+        }
+    }
+    Ok(())
+}
+
 impl TraceCollectorImpl for PerfTraceCollector {
     unsafe fn thread_collector(&self) -> Box<dyn ThreadTraceCollector> {
         Box::new(PerfThreadTraceCollector::new(self.config.clone()))
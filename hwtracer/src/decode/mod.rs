@@ -1,8 +1,6 @@
 //! Trace decoders.
 
 use crate::{errors::HWTracerError, Block, Trace};
-#[cfg(feature = "yk_testing")]
-use std::env;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
@@ -27,11 +25,24 @@ impl TraceDecoderKind {
     /// Returns the default kind of decoder for the current platform or `None` if this platform
     /// does not support tracing.
     pub fn default_for_platform() -> Option<Self> {
-        Self::iter().find(|&kind| Self::match_platform(&kind).is_ok())
+        Self::probe_platform().ok()
+    }
+
+    /// Returns the default kind of decoder for the current platform. If there is none, returns the
+    /// reason that each kind of decoder was rejected.
+    pub fn probe_platform() -> Result<Self, Vec<(Self, HWTracerError)>> {
+        let mut rejected = Vec::new();
+        for kind in Self::iter() {
+            match kind.match_platform() {
+                Ok(()) => return Ok(kind),
+                Err(e) => rejected.push((kind, e)),
+            }
+        }
+        Err(rejected)
     }
 
     /// Returns `Ok` if the this decoder kind is appropriate for the current platform.
-    fn match_platform(&self) -> Result<(), HWTracerError> {
+    pub fn match_platform(&self) -> Result<(), HWTracerError> {
         match self {
             Self::LibIPT => {
                 #[cfg(decoder_libipt)]
@@ -47,15 +58,6 @@ impl TraceDecoderKind {
             }
         }
     }
-
-    #[cfg(feature = "yk_testing")]
-    fn from_str(name: &str) -> Self {
-        match name {
-            "libipt" => Self::LibIPT,
-            "ykpt" => Self::YkPT,
-            _ => panic!(),
-        }
-    }
 }
 
 pub trait TraceDecoder {
@@ -93,13 +95,7 @@ impl TraceDecoderBuilder {
     ///
     /// An error is returned if the requested decoder is inappropriate for the platform or the
     /// requested decoder was not compiled in to hwtracer.
-    pub fn build(mut self) -> Result<Box<dyn TraceDecoder>, HWTracerError> {
-        #[cfg(feature = "yk_testing")]
-        {
-            if let Ok(val) = env::var("YKD_FORCE_TRACE_DECODER") {
-                self.kind = TraceDecoderKind::from_str(&val);
-            }
-        }
+    pub fn build(self) -> Result<Box<dyn TraceDecoder>, HWTracerError> {
         self.kind.match_platform()?;
         match self.kind {
             TraceDecoderKind::LibIPT => {
@@ -0,0 +1,54 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     jit-state: start-tracing
+//     i=6
+//     jit-state: start-tracing
+//     i=5
+//     jit-state: stop-tracing
+//     i=4
+//     jit-state: enter-jit-code
+//     i=3
+//     i=2
+//     i=1
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//   stdout:
+//     exit
+
+// Check that the interpreter can abort a trace, and that the Location being
+// traced is then traced again without being penalised.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 6;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    if (i == 6)
+      yk_mt_abort_tracing(mt);
+    i--;
+  }
+
+  YkMTStats stats = yk_mt_stats(mt);
+  assert(stats.traces_started == 2);
+  assert(stats.traces_aborted == 1);
+  assert(stats.traces_compiled == 1);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
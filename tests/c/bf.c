@@ -1,5 +1,5 @@
 // Run-time:
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     ...
@@ -1,7 +1,7 @@
 // ignore: static strings: https://github.com/ykjit/yk/issues/382
 // Compiler:
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
+//   env-var: YK_PRINT_IR=jit-pre-opt
 //   stderr:
 //    ...
 //    ...call i32 (i8*, ...) @printf...
@@ -1,7 +1,7 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
+//   env-var: YK_PRINT_IR=jit-pre-opt
 //   env-var: YKD_PRINT_JITSTATE=1
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   stderr:
 //     ...
 //     --- Begin jit-pre-opt ---
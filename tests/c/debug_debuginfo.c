@@ -1,10 +1,14 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   stderr:
 //     ...
 //     define ptr @__yk_compiled_trace_0(...
 //     entry:
+//       ...
+//       br label %loop
+//
+//     loop:
 //       ; main() c/debug_debuginfo.c:27:5
 //       ...
 
@@ -1,7 +1,7 @@
 // ignore: Requires function calls in stopgap interpreter.
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -13,8 +13,6 @@
 //     3:21
 //     jit-state: enter-jit-code
 //     2:21
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     1:21
 //     jit-state: stopgap
 //     ...
@@ -0,0 +1,61 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     jit-state: start-tracing
+//     i=4, v=30
+//     jit-state: stop-tracing
+//     i=3, v=30
+//     jit-state: enter-jit-code
+//     i=2, v=30
+//     i=1, v=30
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     lookups=3
+//   stdout:
+//     exit
+
+// Check that a call to an idempotent function whose argument is constant in
+// the trace is executed when the trace is compiled, rather than by the trace.
+
+#include <assert.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int consts[] = {10, 20, 30, 40};
+
+// Counts how often `lookup` is called. Idempotent functions shouldn't have
+// side effects: we only use this one to check that the trace doesn't call
+// `lookup`.
+int lookups = 0;
+
+YK_IDEMPOTENT int lookup(uintptr_t idx) {
+  lookups++;
+  return consts[idx];
+}
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  uintptr_t idx = 2;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  NOOPT_VAL(idx);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    int v = lookup(yk_promote(idx));
+    fprintf(stderr, "i=%d, v=%d\n", i, v);
+    i--;
+  }
+  fprintf(stderr, "lookups=%d\n", lookups);
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
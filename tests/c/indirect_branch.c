@@ -1,6 +1,6 @@
 // ignore: guards for indirect branches not implemented.
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
+//   env-var: YK_PRINT_IR=jit-pre-opt
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     ...
@@ -0,0 +1,76 @@
+// Run-time:
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     i=4
+//     i=3
+//     i=2
+//     i=1
+//     start-tracing
+//     stop-tracing
+//     trace-compiled
+//     enter-jit-code
+//     deoptimise
+//     exit-jit-code
+//   stdout:
+//     exit
+
+// Check that YKD_LOG writes one JSON record per JIT event.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+#define EVENT_PREFIX "{\"event\":\""
+
+int main(int argc, char **argv) {
+  char log_path[] = "/tmp/yk_jit_log_XXXXXX";
+  int fd = mkstemp(log_path);
+  assert(fd != -1);
+  close(fd);
+  setenv("YKD_LOG", log_path, 1);
+
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+
+  // Print the name of each logged event, checking that the records carry the
+  // details we expect.
+  FILE *f = fopen(log_path, "r");
+  assert(f != NULL);
+  char line[4096];
+  while (fgets(line, sizeof(line), f) != NULL) {
+    assert(strncmp(line, EVENT_PREFIX, strlen(EVENT_PREFIX)) == 0);
+    assert(line[strlen(line) - 2] == '}');
+    assert(strstr(line, "\"time_ns\":") != NULL);
+    assert(strstr(line, "\"thread\":") != NULL);
+    assert(strstr(line, "\"trace_id\":0") != NULL);
+    char *event = line + strlen(EVENT_PREFIX);
+    *strchr(event, '"') = '\0';
+    if (strcmp(event, "trace-compiled") == 0) {
+      assert(strstr(event + strlen(event) + 1, "\"compile_ns\":") != NULL);
+    } else if (strcmp(event, "deoptimise") == 0) {
+      assert(strstr(event + strlen(event) + 1, "\"guard\":") != NULL);
+    }
+    fprintf(stderr, "%s\n", event);
+  }
+  fclose(f);
+  unlink(log_path);
+
+  printf("exit");
+  return (EXIT_SUCCESS);
+}
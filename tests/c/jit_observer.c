@@ -0,0 +1,79 @@
+// Run-time:
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     observer: start-tracing
+//     i=4
+//     observer: stop-tracing
+//     i=3
+//     observer: enter-jit-code
+//     i=2
+//     i=1
+//     observer: deoptimise
+//     observer: exit-jit-code
+//   stdout:
+//     exit
+
+// Check that a JIT observer registered via the C API is notified of JIT
+// events.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+static void observer(YkJitEvent event, const char *reason, void *data) {
+  assert(reason == NULL);
+  *(int *)data += 1;
+  switch (event) {
+  case YK_JIT_EVENT_START_TRACING:
+    fprintf(stderr, "observer: start-tracing\n");
+    break;
+  case YK_JIT_EVENT_STOP_TRACING:
+    fprintf(stderr, "observer: stop-tracing\n");
+    break;
+  case YK_JIT_EVENT_ENTER_JIT_CODE:
+    fprintf(stderr, "observer: enter-jit-code\n");
+    break;
+  case YK_JIT_EVENT_EXIT_JIT_CODE:
+    fprintf(stderr, "observer: exit-jit-code\n");
+    break;
+  case YK_JIT_EVENT_DEOPTIMISE:
+    fprintf(stderr, "observer: deoptimise\n");
+    break;
+  default:
+    abort(); // unreachable.
+  }
+}
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  int num_events = 0;
+  yk_mt_jit_observer_set(mt, observer, &num_events);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+  assert(num_events == 5);
+
+  // Once unregistered, the observer must not be notified.
+  yk_mt_jit_observer_set(mt, NULL, NULL);
+  i = 4;
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    i--;
+  }
+  assert(num_events == 5);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
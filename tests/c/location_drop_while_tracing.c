@@ -0,0 +1,56 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     jit-state: start-tracing
+//     i=4
+//     jit-state: start-tracing
+//     i=3
+//     jit-state: stop-tracing
+//     i=2
+//     jit-state: enter-jit-code
+//     i=1
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     traces_aborted=1
+//   stdout:
+//     exit
+
+// Check that a location can be dropped while it is being traced: the trace
+// is discarded, and tracing can start again at another location.
+
+#include <assert.h>
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    if (i == 4) {
+      // Replace the location being traced, as an interpreter might when it
+      // frees the bytecode for a loop.
+      yk_location_drop(loc);
+      loc = yk_location_new();
+    }
+    i--;
+  }
+
+  YkMTStats stats = yk_mt_stats(mt);
+  fprintf(stderr, "traces_aborted=%" PRIu64 "\n", stats.traces_aborted);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
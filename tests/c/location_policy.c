@@ -0,0 +1,52 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     i=8
+//     i=7
+//     i=6
+//     i=5
+//     i=4
+//     i=3
+//     jit-state: start-tracing
+//     i=2
+//     jit-state: stop-tracing
+//     i=1
+//   stdout:
+//     exit
+
+// Check that a Location's own hot threshold overrides the meta-tracer's, and
+// that a Location marked as not to be traced is never traced.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+  yk_location_set_threshold(&loc, 2);
+  YkLocation dont_trace_loc = yk_location_new();
+  yk_location_set_dont_trace(&dont_trace_loc);
+
+  // The first four iterations use `dont_trace_loc`, which would otherwise be
+  // traced immediately, and the rest use `loc`.
+  int i = 8;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(dont_trace_loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, i > 4 ? &dont_trace_loc : &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_location_drop(dont_trace_loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
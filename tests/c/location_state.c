@@ -0,0 +1,72 @@
+// Run-time:
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     i=5: counting 0
+//     i=4: counting 1
+//     i=3: tracing
+//     i=2: compiling
+//     i=1: compiled 0
+//     after loop: compiled 0
+//     dont_trace: dont-trace
+//   stdout:
+//     exit
+
+// Check that a location's state can be queried.
+
+#include <assert.h>
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+void print_state(const char *prefix, YkLocation *loc) {
+  YkLocationState state = yk_location_state(loc);
+  assert(state.trace_failures == 0);
+  switch (state.kind) {
+  case YK_LOCATION_COUNTING:
+    fprintf(stderr, "%s: counting %" PRIu32 "\n", prefix, state.count);
+    break;
+  case YK_LOCATION_TRACING:
+    fprintf(stderr, "%s: tracing\n", prefix);
+    break;
+  case YK_LOCATION_COMPILING:
+    fprintf(stderr, "%s: compiling\n", prefix);
+    break;
+  case YK_LOCATION_COMPILED:
+    fprintf(stderr, "%s: compiled %" PRIu64 "\n", prefix, state.trace_id);
+    break;
+  case YK_LOCATION_DONT_TRACE:
+    fprintf(stderr, "%s: dont-trace\n", prefix);
+    break;
+  }
+}
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 1);
+  YkLocation loc = yk_location_new();
+  YkLocation dont_trace = yk_location_new();
+  yk_location_set_dont_trace(&dont_trace);
+
+  char prefix[16];
+  int i = 5;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  print_state("i=5", &loc);
+  i--;
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    snprintf(prefix, sizeof(prefix), "i=%d", i);
+    print_state(prefix, &loc);
+    i--;
+  }
+  print_state("after loop", &loc);
+  print_state("dont_trace", &dont_trace);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_location_drop(dont_trace);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
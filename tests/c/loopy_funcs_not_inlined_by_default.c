@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_SERIALISE_COMPILATION=1
-//   env-var: YKD_PRINT_IR=aot,jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot,jit-pre-opt
 //   stderr:
 //     --- Begin aot ---
 //     ...
@@ -10,7 +10,7 @@
 //     ...
 //     --- Begin jit-pre-opt ---
 //     ...
-//     define ptr @__yk_compiled_trace_0(ptr %0, ptr %1, i64 %2, ptr %3, ptr %4) {
+//     define ptr @__yk_compiled_trace_0(ptr %0, ptr %1, ptr %2, ptr %3) {
 //       ...
 //       call void @never_inline_into_trace(i32 noundef ...
 //       ...
@@ -1,7 +1,7 @@
 // ignore: needs a non-exiting stop-gap interpreter.
 // Run-time:
-//   env-var: YKD_PRINT_IR=aot
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     ...
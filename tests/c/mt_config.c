@@ -0,0 +1,61 @@
+// Run-time:
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   env-var: YK_HOT_THRESHOLD=1000
+//   stderr:
+//     line 2: Unknown option 'hot_thresold'
+//     line 1: '-1' is not a valid value for max_trace_length
+//     max_worker_threads must be >= 1
+//     i=4
+//     i=3
+//     i=2
+//     i=1
+//     traces_started=1
+//   stdout:
+//     exit
+
+// Check that yk_mt_new_with_config rejects invalid configurations, and that
+// options in the configuration take precedence over the environment.
+
+#include <assert.h>
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+void expect_error(const char *config) {
+  char *err_msg = NULL;
+  YkMT *mt = yk_mt_new_with_config(config, &err_msg);
+  assert(mt == NULL);
+  assert(err_msg != NULL);
+  fprintf(stderr, "%s\n", err_msg);
+  free(err_msg);
+}
+
+int main(int argc, char **argv) {
+  expect_error("# A comment\nhot_thresold = 0\n");
+  expect_error("max_trace_length = -1");
+  expect_error("max_worker_threads = 0");
+
+  // Without the configuration, `YK_HOT_THRESHOLD` would stop the loop below
+  // from being traced.
+  YkMT *mt = yk_mt_new_with_config("hot_threshold = 0", NULL);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+
+  YkMTStats stats = yk_mt_stats(mt);
+  fprintf(stderr, "traces_started=%" PRIu64 "\n", stats.traces_started);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt,aot
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt,aot
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     ...
@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=aot,jit-pre-opt,jit-post-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot,jit-pre-opt,jit-post-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     ...
@@ -26,8 +26,6 @@
 //     ...
 //     jit-state: enter-jit-code
 //     Can't JIT this!
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     Can't JIT this!
 //     jit-state: exit-jit-code
 //     ...
@@ -1,11 +1,11 @@
 // Run-time:
-//   env-var: YKD_SERIALISE_COMPILATION=1
-//   env-var: YKD_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
 //   stderr:
 //     ...
 //     --- Begin jit-pre-opt ---
 //     ...
-//     define i8 @__yk_compiled_trace_0(ptr %0, ptr %1, i64 %2, ptr %3, ptr %4) {
+//     define i8 @__yk_compiled_trace_0(ptr %0, ptr %1, ptr %2, ptr %3) {
 //       ...
 //       %{{12}} = tail call i32 @call_me(i32...
 //       ...
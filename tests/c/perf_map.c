@@ -0,0 +1,59 @@
+// Run-time:
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   env-var: YK_PERF_MAP=1
+//   stderr:
+//     i=4
+//     i=3
+//     i=2
+//     i=1
+//     __yk_compiled_trace_0 (root trace of Location {{loc}} in main)
+//   stdout:
+//     exit
+
+// Check that YK_PERF_MAP writes an entry for each compiled trace to the perf
+// map.
+
+#include <assert.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+
+  // Print the name and origin of each entry, checking that the entry's address
+  // range is non-empty.
+  char map_path[64];
+  snprintf(map_path, sizeof(map_path), "/tmp/perf-%d.map", getpid());
+  FILE *f = fopen(map_path, "r");
+  assert(f != NULL);
+  uintptr_t start, size;
+  char name[4096];
+  while (fscanf(f, "%" SCNxPTR " %" SCNxPTR " %4095[^\n]\n", &start, &size,
+                name) == 3) {
+    assert(start != 0 && size != 0);
+    fprintf(stderr, "%s\n", name);
+  }
+  fclose(f);
+  unlink(map_path);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
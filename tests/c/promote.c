@@ -0,0 +1,48 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     jit-state: start-tracing
+//     i=4, y=12
+//     jit-state: stop-tracing
+//     i=3, y=9
+//     jit-state: enter-jit-code
+//     i=2, y=6
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     i=1, y=4
+//   stdout:
+//     exit
+
+// Check that a trace is specialised on a value passed to `yk_promote`, and
+// that when a different value is passed, the trace's guard fails and the
+// interpreter continues with the new value.
+
+#include <assert.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int xs[] = {0, 4, 3, 3, 3};
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(xs);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    uintptr_t x = yk_promote(xs[i]);
+    fprintf(stderr, "i=%d, y=%d\n", i, (int)x * i);
+    i--;
+  }
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
@@ -1,5 +1,5 @@
 // Run-time:
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -11,8 +11,6 @@
 //     jit-state: enter-jit-code
 //     i=4
 //     1 3 4 6 2 5 end
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     i=5
 //     1 2 3 4 6 5 end
 //     jit-state: deoptimise
@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=aot,jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot,jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -8,7 +8,7 @@
 //     ...
 //     %{{1}} = call ptr @__ykrt_control_point(ptr %{{2}}, ptr %{{3}}, ptr %{{4}}, ptr %{{retval}})...
 //     ...
-//     define {{ty2}} @__yk_compiled_trace_0(ptr %0, ptr %1, i64 %2, ptr %3, ptr %4) {
+//     define {{ty2}} @__yk_compiled_trace_0(ptr %0, ptr %1, ptr %2, ptr %3) {
 //     ...
 //     jit-state: enter-jit-code
 //     ...
@@ -0,0 +1,45 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   stderr:
+//     jit-state: start-tracing
+//     i=4
+//     jit-state: stop-tracing
+//     i=3
+//     jit-state: enter-jit-code
+//     i=2
+//     i=1
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//   stdout:
+//     exit
+
+// Check that serialised compilation can be enabled at run-time, in which case
+// a trace is compiled as soon as tracing stops, and executed the next time its
+// location is reached.
+
+#include <assert.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  yk_mt_serialise_compilation_set(mt, true);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
@@ -0,0 +1,64 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     jit-state: start-tracing
+//     even 10
+//     jit-state: stop-tracing
+//     odd 9
+//     jit-state: enter-jit-code
+//     even 8
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     odd 7
+//     jit-state: enter-jit-code
+//     even 6
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     jit-state: start-side-tracing
+//     odd 5
+//     jit-state: stop-side-tracing
+//     even 4
+//     jit-state: enter-jit-code
+//     odd 3
+//     even 2
+//     odd 1
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//   stdout:
+//     exit
+
+// Check that a guard which fails often gets a side trace, and that once the
+// side trace has been compiled, the guard no longer deoptimises: instead, the
+// side trace returns to its root trace without leaving JIT code.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  yk_mt_side_trace_threshold_set(mt, 2);
+  YkLocation loc = yk_location_new();
+
+  int i = 10;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    if (i % 2 == 0) {
+      fprintf(stderr, "even %d\n", i);
+    } else {
+      fprintf(stderr, "odd %d\n", i);
+      fflush(stderr);
+    }
+    i--;
+  }
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
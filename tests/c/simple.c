@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -8,7 +8,7 @@
 //     jit-state: stop-tracing
 //     --- Begin jit-pre-opt ---
 //     ...
-//     define {{rtnty}} @__yk_compiled_trace_0(ptr %0, ptr %1, i64 %2, ptr %3, ptr %4) {
+//     define {{rtnty}} @__yk_compiled_trace_0(ptr %0, ptr %1, ptr %2, ptr %3) {
 //        ...
 //        %{{fptr}} = getelementptr %YkCtrlPointVars, ptr %0, i32 0, i32 0...
 //        %{{load}} = load...
@@ -19,7 +19,6 @@
 //        br i1 %{{cond}}, label %{{guard-succ-bb}}, label %{{guard-fail-bb}}
 //
 //     {{guard-fail-bb}}:...
-//       %{{al}} = alloca...
 //       ...
 //       %{{cprtn}} = call {{rtnty}} (...) @llvm.experimental.deoptimize.p0(...
 //       ret {{rtnty}} %{{cprtn}}
@@ -29,15 +28,12 @@
 //        %{{fptr2}} = getelementptr %YkCtrlPointVars, ptr %0, i32 0, i32 0...
 //        store...
 //        ...
-//        ret {{rtnty}} null
-//     }
+//        ...label %loop...
 //     ...
 //     --- End jit-pre-opt ---
 //     i=3
 //     jit-state: enter-jit-code
 //     i=2
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     i=1
 //     jit-state: deoptimise
 //     ...
@@ -1,7 +1,7 @@
 // ignore: Requires stopgap support for global variables.
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -43,8 +43,6 @@
 //     pc=1, mem=5
 //     pc=2, mem=4
 //     pc=3, mem=3
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     pc=0, mem=3
 //     pc=1, mem=2
 //     pc=2, mem=1
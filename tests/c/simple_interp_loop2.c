@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -11,7 +11,7 @@
 //     jit-state: stop-tracing
 //     --- Begin jit-pre-opt ---
 //     ...
-//     define ptr @__yk_compiled_trace_0(ptr %0, ptr %1, i64 %2, ptr %3, ptr %4) {
+//     define ptr @__yk_compiled_trace_0(ptr %0, ptr %1, ptr %2, ptr %3) {
 //       ...
 //       %{{fptr}} = getelementptr %YkCtrlPointVars, ptr %0, i32 0, i32 0...
 //       %{{load}} = load...
@@ -33,8 +33,7 @@
 //       %{{fptr2}} = getelementptr %YkCtrlPointVars, ptr %0, i32 0, i32 0...
 //       store...
 //       ...
-//       ret ptr null
-//     }
+//       ...label %loop...
 //     ...
 //     --- End jit-pre-opt ---
 //     pc=0, mem=3
@@ -46,8 +45,6 @@
 //     pc=1, mem=2
 //     pc=2, mem=2
 //     pc=3, mem=1
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     pc=0, mem=1
 //     pc=1, mem=1
 //     pc=2, mem=1
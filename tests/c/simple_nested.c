@@ -1,7 +1,7 @@
 // ignore: dont
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -18,7 +18,6 @@
 //        br i1 %{{cond}}, label %{{guard-succ-bb}}, label %{{guard-fail-bb}}
 //
 //     {{guard-fail-bb}}:...
-//       %{{al}} = alloca...
 //       ...
 //       %{{cprtn}} = call {{rtnty}} (...) @llvm.experimental.deoptimize.{{rtnty}}(...
 //       ret {{rtnty}} %{{cprtn}}
@@ -35,8 +34,6 @@
 //     i=4
 //     jit-state: enter-jit-code
 //     i=3
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     i=2
 //     jit-state: deoptimise
 //     ...
@@ -0,0 +1,62 @@
+// Run-time:
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     i=4
+//     i=3
+//     i=2
+//     i=1
+//     traces_started=1
+//     traces_completed=1
+//     traces_aborted=0
+//     traces_compiled=1
+//     traces_compile_failed=0
+//     deopts=1
+//     compile_queue_len=0
+//   stdout:
+//     exit
+
+// Check that the meta-tracer's statistics count tracing, compilation, and
+// deoptimisation.
+
+#include <assert.h>
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  YkMTStats stats = yk_mt_stats(mt);
+  assert(stats.traces_started == 0);
+  assert(stats.tracing_time_ns == 0);
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+
+  stats = yk_mt_stats(mt);
+  fprintf(stderr, "traces_started=%" PRIu64 "\n", stats.traces_started);
+  fprintf(stderr, "traces_completed=%" PRIu64 "\n", stats.traces_completed);
+  fprintf(stderr, "traces_aborted=%" PRIu64 "\n", stats.traces_aborted);
+  fprintf(stderr, "traces_compiled=%" PRIu64 "\n", stats.traces_compiled);
+  fprintf(stderr, "traces_compile_failed=%" PRIu64 "\n",
+          stats.traces_compile_failed);
+  fprintf(stderr, "deopts=%" PRIu64 "\n", stats.deopts);
+  fprintf(stderr, "compile_queue_len=%zu\n", stats.compile_queue_len);
+  assert(stats.tracing_time_ns > 0);
+  assert(stats.compiling_time_ns > 0);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
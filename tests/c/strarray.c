@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=aot
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -18,8 +18,6 @@
 //     cucumber
 //     jit-state: enter-jit-code
 //     tomato
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     banana
 //     jit-state: deoptimise
 //     ...
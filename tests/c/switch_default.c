@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=jit-pre-opt
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -20,8 +20,6 @@
 //     i=3
 //     jit-state: enter-jit-code
 //     i=2
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     i=1
 //     jit-state: deoptimise
 //     ...
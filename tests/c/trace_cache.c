@@ -0,0 +1,115 @@
+// Run-time:
+//   env-var: YK_PRINT_IR=jit-pre-opt
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     i=4
+//     --- Begin jit-pre-opt ---
+//     ...
+//     --- End jit-pre-opt ---
+//     i=3
+//     i=2
+//     i=1
+//     cached=1
+//     i=4
+//     i=3
+//     i=2
+//     i=1
+//     cached=1, reused=1
+//   stdout:
+//     exit
+
+// Check that, with a trace cache, a compiled trace is cached, and that when the
+// same trace is recorded again (here by a second meta-tracer, at a different
+// `Location`) its machine code is loaded from the cache rather than compiled
+// and cached again, without its JIT module being built.
+
+#define _XOPEN_SOURCE 700
+#include <assert.h>
+#include <dirent.h>
+#include <ftw.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+__attribute__((noinline)) void loop(YkMT *mt, YkLocation *loc) {
+  int i = 4;
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+}
+
+// Return the number of objects cached in `cache`, setting `*ino` to the inode
+// of the last one found.
+int cached(char *cache, ino_t *ino) {
+  int n = 0;
+  DIR *d = opendir(cache);
+  assert(d != NULL);
+  struct dirent *e;
+  while ((e = readdir(d)) != NULL) {
+    if (e->d_name[0] == '.')
+      continue;
+    // Each binary and version of yk has its own subdirectory.
+    char subdir[4096];
+    snprintf(subdir, sizeof(subdir), "%s/%s", cache, e->d_name);
+    DIR *sd = opendir(subdir);
+    assert(sd != NULL);
+    struct dirent *se;
+    while ((se = readdir(sd)) != NULL) {
+      char *ext = strrchr(se->d_name, '.');
+      if (ext != NULL && strcmp(ext, ".o") == 0) {
+        n++;
+        *ino = se->d_ino;
+      }
+    }
+    closedir(sd);
+  }
+  closedir(d);
+  return n;
+}
+
+int rm(const char *path, const struct stat *sb, int flag, struct FTW *ftw) {
+  return remove(path);
+}
+
+YkMT *mk_mt(char *cache) {
+  char config[4096];
+  snprintf(config, sizeof(config), "hot_threshold = 0\ntrace_cache = %s",
+           cache);
+  YkMT *mt = yk_mt_new_with_config(config, NULL);
+  assert(mt != NULL);
+  return mt;
+}
+
+int main(int argc, char **argv) {
+  char cache[] = "/tmp/yk_trace_cache_XXXXXX";
+  assert(mkdtemp(cache) != NULL);
+
+  YkMT *mt = mk_mt(cache);
+  YkLocation loc = yk_location_new();
+  NOOPT_VAL(loc);
+  loop(mt, &loc);
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  ino_t ino1 = 0;
+  fprintf(stderr, "cached=%d\n", cached(cache, &ino1));
+
+  mt = mk_mt(cache);
+  YkLocation loc2 = yk_location_new();
+  NOOPT_VAL(loc2);
+  loop(mt, &loc2);
+  yk_location_drop(loc2);
+  yk_mt_drop(mt);
+  ino_t ino2 = 0;
+  int n = cached(cache, &ino2);
+  fprintf(stderr, "cached=%d, reused=%d\n", n, ino1 == ino2);
+
+  nftw(cache, rm, 16, FTW_DEPTH | FTW_PHYS);
+  printf("exit");
+  return (EXIT_SUCCESS);
+}
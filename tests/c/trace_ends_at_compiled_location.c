@@ -0,0 +1,123 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     pc=0, inner=0, outer=3
+//     pc=1, inner=4, outer=3
+//     jit-state: start-tracing
+//     pc=1, inner=3, outer=3
+//     jit-state: stop-tracing
+//     pc=1, inner=2, outer=3
+//     jit-state: enter-jit-code
+//     pc=1, inner=1, outer=3
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     pc=2, inner=0, outer=3
+//     jit-state: start-tracing
+//     pc=0, inner=0, outer=2
+//     jit-state: stop-tracing
+//     jit-state: enter-jit-code
+//     pc=1, inner=4, outer=2
+//     pc=1, inner=3, outer=2
+//     pc=1, inner=2, outer=2
+//     pc=1, inner=1, outer=2
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     pc=2, inner=0, outer=2
+//     jit-state: enter-jit-code
+//     pc=0, inner=0, outer=1
+//     pc=1, inner=4, outer=1
+//     pc=1, inner=3, outer=1
+//     pc=1, inner=2, outer=1
+//     pc=1, inner=1, outer=1
+//     jit-state: deoptimise
+//     jit-state: exit-jit-code
+//     pc=2, inner=0, outer=1
+//     pc=3, inner=0, outer=0
+//   stdout:
+//     exit
+
+// Check that when a thread which is tracing an outer loop reaches the control
+// point of an inner loop which has already been compiled, the outer loop's
+// trace ends there and, once compiled, jumps straight to the inner loop's
+// trace.
+
+#include <assert.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+// The mutable memory cells of the interpreter.
+int inner = 0;
+int outer = 3;
+
+// The bytecodes accepted by the interpreter.
+#define RESET_INNER 0
+#define DEC_INNER 1
+#define DEC_OUTER 2
+#define EXIT 3
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 1);
+
+  // A hard-coded program to execute.
+  int prog[] = {RESET_INNER, DEC_INNER, DEC_OUTER, EXIT};
+  size_t prog_len = sizeof(prog) / sizeof(prog[0]);
+
+  YkLocation outer_loc = yk_location_new();
+  YkLocation inner_loc = yk_location_new();
+  YkLocation *locs[] = {&outer_loc, &inner_loc, NULL, NULL};
+
+  // The program counter.
+  int pc = 0;
+
+  NOOPT_VAL(pc);
+  NOOPT_VAL(prog);
+  NOOPT_VAL(prog_len);
+  NOOPT_VAL(inner);
+  NOOPT_VAL(outer);
+  NOOPT_VAL(locs);
+
+  // interpreter loop.
+  while (true) {
+    assert(pc < prog_len);
+    yk_mt_control_point(mt, locs[pc]);
+    int bc = prog[pc];
+    fprintf(stderr, "pc=%d, inner=%d, outer=%d\n", pc, inner, outer);
+    switch (bc) {
+    case RESET_INNER:
+      inner = 4;
+      pc++;
+      break;
+    case DEC_INNER:
+      inner--;
+      if (inner > 0)
+        pc = 1;
+      else
+        pc++;
+      break;
+    case DEC_OUTER:
+      outer--;
+      if (outer > 0)
+        pc = 0;
+      else
+        pc++;
+      break;
+    case EXIT:
+      goto done;
+    default:
+      abort(); // unreachable.
+    }
+  }
+
+done:
+  printf("exit");
+  yk_location_drop(outer_loc);
+  yk_location_drop(inner_loc);
+  yk_mt_drop(mt);
+
+  return (EXIT_SUCCESS);
+}
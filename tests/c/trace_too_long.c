@@ -0,0 +1,57 @@
+// Run-time:
+//   env-var: YKD_PRINT_JITSTATE=1
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   stderr:
+//     jit-state: start-tracing
+//     i=8
+//     jit-state: start-tracing
+//     i=7
+//     jit-state: start-tracing
+//     i=6
+//     jit-state: start-tracing
+//     i=5
+//     jit-state: start-tracing
+//     i=4
+//     jit-state: start-tracing
+//     i=3
+//     i=2
+//     i=1
+//   stdout:
+//     exit
+
+// Check that traces which grow beyond the maximum trace length are abandoned,
+// and that a Location whose traces are abandoned too often is no longer traced.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  // No trace can be shorter than one control point, so every trace is
+  // abandoned.
+  yk_mt_max_trace_length_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 8;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "i=%d\n", i);
+    i--;
+  }
+
+  YkMTStats stats = yk_mt_stats(mt);
+  assert(stats.traces_started == 6);
+  assert(stats.traces_aborted == 6);
+  assert(stats.traces_completed == 0);
+
+  printf("exit");
+  yk_location_drop(loc);
+  yk_mt_drop(mt);
+  return (EXIT_SUCCESS);
+}
@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_SERIALISE_COMPILATION=1
-//   env-var: YKD_PRINT_IR=aot
+//   env-var: YK_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot
 //   stderr:
 //     ...
 //     --- Begin aot ---
@@ -1,6 +1,6 @@
 // Run-time:
-//   env-var: YKD_PRINT_IR=aot
-//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YK_PRINT_IR=aot
+//   env-var: YK_SERIALISE_COMPILATION=1
 //   env-var: YKD_PRINT_JITSTATE=1
 //   stderr:
 //     jit-state: start-tracing
@@ -16,8 +16,6 @@
 //     i=6
 //     jit-state: enter-jit-code
 //     i=6
-//     jit-state: exit-jit-code
-//     jit-state: enter-jit-code
 //     i=6
 //     jit-state: deoptimise
 //     jit-state: exit-jit-code
@@ -12,6 +12,7 @@ use std::{
 use tempfile::TempDir;
 use tests::{mk_compiler, EXTRA_LINK};
 use ykbuild::{CCGenerator, CCLang};
+use yktrace::{TracingBackend, TracingKind};
 
 const COMMENT: &str = "//";
 
@@ -20,6 +21,9 @@ fn run_suite(opt: &'static str, force_decoder: &'static str) {
         "Running C tests with opt level {} and forcing the {} decoder...",
         opt, force_decoder
     );
+    // Forcing a decoder implies hardware tracing, so we only do so if hardware tracing is
+    // available.
+    let hw_tracing = TracingBackend::probe().kind() == TracingKind::HardwareTracing;
 
     // Tests with the filename prefix `debug_` are only run in debug builds.
     #[cfg(cargo_profile = "release")]
@@ -89,7 +93,9 @@ fn run_suite(opt: &'static str, force_decoder: &'static str) {
                 true,
             );
             let mut runtime = Command::new(exe.clone());
-            runtime.env("YKD_FORCE_TRACE_DECODER", force_decoder);
+            if hw_tracing {
+                runtime.env("YK_TRACE_DECODER", force_decoder);
+            }
             vec![("Compiler", compiler), ("Run-time", runtime)]
         })
         .fm_options(|_, _, fmb| {
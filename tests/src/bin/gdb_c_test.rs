@@ -12,7 +12,7 @@ struct Args {
     /// The test to attach gdb to.
     test_file: PathBuf,
 
-    /// Run the test with `YKD_PRINT_IR` set to the specified value.
+    /// Run the test with `YK_PRINT_IR` set to the specified value.
     #[arg(short, long)]
     print_ir: Option<String>,
 
@@ -20,7 +20,7 @@ struct Args {
     #[arg(short = 'j', long)]
     print_jitstate: bool,
 
-    /// Run the test with `YKD_SERIALISE_COMPILATION=1`
+    /// Run the test with `YK_SERIALISE_COMPILATION=1`
     #[arg(short, long)]
     serialise_compilation: bool,
 
@@ -62,10 +62,10 @@ fn main() {
     // Now we have a test binary in a temporary directory, prepare an invocation of gdb, setting
     // environment variables as necessary.
     let mut gdb = Command::new("gdb");
-    gdb.arg(&binpath).env("YKD_TRACE_DEBUGINFO", "1");
+    gdb.arg(&binpath).env("YK_TRACE_DEBUGINFO", "1");
 
     if args.serialise_compilation {
-        gdb.env("YKD_SERIALISE_COMPILATION", "1");
+        gdb.env("YK_SERIALISE_COMPILATION", "1");
     }
 
     if args.print_jitstate {
@@ -73,7 +73,7 @@ fn main() {
     }
 
     if let Some(irs) = args.print_ir {
-        gdb.env("YKD_PRINT_IR", irs);
+        gdb.env("YK_PRINT_IR", irs);
     }
 
     if let Some(num_breaks) = args.num_breaks {
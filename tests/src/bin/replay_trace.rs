@@ -0,0 +1,41 @@
+//! A tool to replay a trace saved with `YKD_SAVE_TRACES` through the trace compiler.
+
+use clap::Parser;
+use std::{path::PathBuf, process};
+use ykrt::MTBuilder;
+use yktrace::SavedTrace;
+
+/// Compile a trace saved with `YKD_SAVE_TRACES`.
+#[derive(Parser, Debug)]
+#[command(about, long_about = None)]
+struct Args {
+    /// The saved trace to compile.
+    trace_file: PathBuf,
+
+    /// Read the bitcode from this binary, rather than from the binary the trace was collected
+    /// from. Its bitcode must be the same as that of the original binary.
+    #[arg(short, long)]
+    binary: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let saved = SavedTrace::load(&args.trace_file).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let binary = args.binary.unwrap_or_else(|| saved.binary().to_path_buf());
+    // The trace is compiled with the options that a meta-tracer would use (e.g. `YK_PRINT_IR`).
+    let res = MTBuilder::from_env().and_then(|b| {
+        let bc = saved.bitcode(&binary)?;
+        saved.trace().compile_for_replay(&bc, &b.compile_options())
+    });
+    match res {
+        Ok(()) => println!("{}: compiled", args.trace_file.display()),
+        Err(e) => {
+            eprintln!("{}: {}", args.trace_file.display(), e);
+            process::exit(1);
+        }
+    }
+}
@@ -4,6 +4,7 @@
 //! `trace_compiler` directory of this crate.
 
 use std::{collections::HashMap, convert::TryInto, env, error::Error, ffi::CString, fs::File};
+use ykrt::MTBuilder;
 use yktrace::{IRBlock, IRTrace};
 
 const BBS_ENV: &str = "YKT_TRACE_BBS";
@@ -37,6 +38,11 @@ fn main() -> Result<(), String> {
     }
     let trace = IRTrace::new(bbs, HashMap::new());
 
+    // The trace is compiled with the options that a meta-tracer would use (e.g. `YK_PRINT_IR`).
+    let opts = MTBuilder::from_env()
+        .map_err(|e| e.to_string())?
+        .compile_options();
+
     // Map the `.ll` file into the address space so that we can give a pointer to it to the trace
     // compiler. Normally (i.e. outside of testing), the trace compiler wouldn't deal with textual
     // bitcode format, but it just so happens that LLVM's module loading APIs accept either format.
@@ -44,7 +50,7 @@ fn main() -> Result<(), String> {
     let ll_file = File::open(ll_path).unwrap();
     let mmap = unsafe { memmap2::Mmap::map(&ll_file).unwrap() };
 
-    unsafe { trace.compile_for_tc_tests(mmap.as_ptr(), mmap.len().try_into().unwrap()) };
+    unsafe { trace.compile_for_tc_tests(mmap.as_ptr(), mmap.len().try_into().unwrap(), &opts) };
 
     Ok(())
 }
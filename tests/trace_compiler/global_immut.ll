@@ -1,14 +1,18 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
 ;      ...
 ;      define {{type}} @__yk_compiled_trace_0(...
 ;      entry:
+;        br label %loop
+;
+;      loop:
 ;        %{{0}} = load i32, ptr @g, align 4
 ;        %{{1}} = add i32 %{{0}}, 1
-;        ret {{type}}...
+;        ...
+;        br label %loop
 ;      }
 ;      ...
 ;      --- End jit-pre-opt ---
@@ -1,13 +1,17 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
 ;      ...
 ;      define {{type}} @__yk_compiled_trace_0(...
 ;      entry:
+;        br label %loop
+;
+;      loop:
 ;        store i32 1, ptr @g, align 4
-;        ret {{type}}...
+;        ...
+;        br label %loop
 ;      }
 ;      ...
 ;      --- End jit-pre-opt ---
@@ -1,5 +1,5 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0,main:1
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
@@ -7,6 +7,9 @@
 ;      define {{type}} @__yk_compiled_trace_0(...
 ;      entry:
 ;        %{{0}} = alloca i32, align 4
+;        br label %loop
+;
+;      loop:
 ;        %{{1}} = icmp eq i32 1, 1
 ;        br i1 %{{1}}, label %{{true}}, label %guardfail
 ;
@@ -17,8 +20,9 @@
 ;
 ;      {{true}}:...
 ;        store i32 1, ptr %{{0}}, align 4
-;        ret {{type}} null
-;      }
+;        ...
+;        br label %loop
+;      ...
 ;
 ;      declare {{type}} @llvm.experimental.deoptimize.p0(...)
 ;      ...
@@ -1,11 +1,14 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0,main:1
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
 ;      ...
 ;      define {{type}} @__yk_compiled_trace_0(...
 ;      entry:
+;        br label %loop
+;
+;      loop:
 ;        %{{4}} = add i32 0, 999
 ;        switch i32 %{{4}}, label %{{5}} [
 ;          i32 0, label %guardfail
@@ -18,7 +21,8 @@
 ;
 ;      {{5}}:...
 ;        %{{6}} = add i32 %{{4}}, 2
-;        ret {{type}}...
+;        ...
+;        br label %loop
 ;        ...
 ;      --- End jit-pre-opt ---
 
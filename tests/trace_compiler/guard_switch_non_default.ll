@@ -1,11 +1,14 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0,main:4
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
 ;      ...
 ;      define {{type}} @__yk_compiled_trace_0(...
 ;      entry:
+;        br label %loop
+;
+;      loop:
 ;        %{{4}} = add i32 0, 2
 ;        %{{5}} = icmp eq i32 %{{4}}, 2
 ;        br i1 %{{5}}, label %{{6}}, label %guardfail
@@ -15,8 +18,8 @@
 ;
 ;      {{6}}:...
 ;        %{{7}} = add i32 %{{4}}, 1
-;        ret {{type}}...
-;      }
+;        ...
+;        br label %loop
 ;      ...
 ;      --- End jit-pre-opt ---
 
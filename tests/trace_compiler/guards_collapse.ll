@@ -1,5 +1,5 @@
 ; Run-time-filtered:
-;   env-var: YKD_PRINT_IR=jit-pre-opt,jit-post-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt,jit-post-opt
 ;   env-var: YKT_TRACE_BBS=main:0,main:1,main:2
 ;   stdout:
 ;     --- Begin icmps ---
@@ -1,15 +1,19 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0,f:0,main:0
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
 ;      ...
 ;      define {{type}} @__yk_compiled_trace_0(...
 ;      entry:
+;        br label %loop
+;
+;      loop:
 ;        %{{0}} = add i32 1, 1
 ;        %{{1}} = sub i32 %{{0}}, 2
 ;        %{{2}} = add i32 %{{1}}, 3
-;        ret {{type}}...
+;        ...
+;        br label %loop
 ;      }
 ;      ...
 ;      --- End jit-pre-opt ---
@@ -1,12 +1,15 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0,f:0,f:1,g:0,f:0,f:2,g:0,f:1,f:2,main:0
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
 ;
 ;      ...
-;      define {{type}} @__yk_compiled_trace_0(ptr %0, ptr %1, i64 %2, ptr %3, ptr %4) {
+;      define {{type}} @__yk_compiled_trace_0(ptr %0, ptr %1, ptr %2, ptr %3) {
 ;      entry:
+;        br label %loop
+;
+;      loop:
 ;        %{{4}} = icmp eq i32 1, 0
 ;        br i1 %{{4}}, label %guardfail, label %{{rtnbb}}
 ;
@@ -17,8 +20,9 @@
 ;
 ;      {{rtnbb}}:...
 ;        call void @f(i32 0)
-;        ret {{type}}...
-;      }
+;        ...
+;        br label %loop
+;      ...
 ;
 ;      declare {{type}} @llvm.experimental.deoptimize.p0(...)
 ;
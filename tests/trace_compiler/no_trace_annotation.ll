@@ -1,5 +1,5 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt,jit-post-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt,jit-post-opt
 ;   env-var: YKT_TRACE_BBS=main:0,call_me:0,main:0
 ;   stderr:
 ;     ...
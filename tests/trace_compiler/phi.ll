@@ -1,5 +1,5 @@
 ; Run-time:
-;   env-var: YKD_PRINT_IR=jit-pre-opt
+;   env-var: YK_PRINT_IR=jit-pre-opt
 ;   env-var: YKT_TRACE_BBS=main:0,main:1,main:3
 ;   stderr:
 ;      --- Begin jit-pre-opt ---
@@ -12,8 +12,8 @@
 ;        %{{y}} = add i32 %{{x}}, 1
 ;        %{{select}} = select i1 false, i32 %{{y}}, i32 %{{y}}
 ;        %{{z}} = add i32 %{{select}}, 2
-;        ret {{type}}...
-;      }
+;        ...
+;        br label %loop
 ;      ...
 ;      --- End jit-pre-opt ---
 
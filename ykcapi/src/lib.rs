@@ -15,18 +15,42 @@ mod testing;
 
 use std::arch::asm;
 use std::convert::{TryFrom, TryInto};
-use std::ffi::{c_char, c_void, CString};
-use std::{ptr, slice};
+use std::error::Error;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::time::Instant;
+use std::{ptr, slice, sync::Arc};
 use ykfr::{self, FrameReconstructor};
-#[cfg(feature = "yk_jitstate_debug")]
-use ykrt::print_jit_state;
-use ykrt::{HotThreshold, Location, MT};
+use ykrt::{HotThreshold, JitEvent, JitObserver, Location, LocationKind, MTBuilder, MT};
 use yksmp::{Location as SMLocation, StackMapParser};
 
 #[no_mangle]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn yk_mt_new(err_msg: *mut *const c_char) -> *mut MT {
-    match MT::new() {
+    mt_or_err_msg(MT::new(), err_msg)
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn yk_mt_new_with_config(
+    config: *const c_char,
+    err_msg: *mut *const c_char,
+) -> *mut MT {
+    let mt = MTBuilder::from_env().and_then(|b| {
+        if config.is_null() {
+            return b.build();
+        }
+        let config = unsafe { CStr::from_ptr(config) }
+            .to_str()
+            .map_err(|e| format!("Configuration is not valid UTF-8: {}", e))?;
+        b.config(config)?.build()
+    });
+    mt_or_err_msg(mt, err_msg)
+}
+
+/// Return a pointer to the boxed meta-tracer `mt` or, if `mt` is an error, `NULL`. In the latter
+/// case, if `err_msg` is `NULL`, panic; otherwise put a `malloc`d error message in `*err_msg`.
+fn mt_or_err_msg(mt: Result<MT, Box<dyn Error>>, err_msg: *mut *const c_char) -> *mut MT {
+    match mt {
         Ok(mt) => Box::into_raw(Box::new(mt)),
         Err(e) => {
             if err_msg.is_null() {
@@ -76,11 +100,147 @@ pub extern "C" fn __ykrt_control_point(
     std::ptr::null()
 }
 
+// Called by a side trace which has reached the control point for `loc`. Returns the trace to
+// stitch to, or null if execution must continue in the interpreter.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn __ykrt_stitch_location(loc: *const Location) -> *const c_void {
+    if loc.is_null() {
+        return std::ptr::null();
+    }
+    ykrt::stitch_location(unsafe { &*loc })
+}
+
 #[no_mangle]
 pub extern "C" fn yk_mt_hot_threshold_set(mt: &MT, hot_threshold: HotThreshold) {
     mt.set_hot_threshold(hot_threshold);
 }
 
+#[no_mangle]
+pub extern "C" fn yk_mt_side_trace_threshold_set(mt: &MT, side_trace_threshold: HotThreshold) {
+    mt.set_side_trace_threshold(side_trace_threshold);
+}
+
+#[no_mangle]
+pub extern "C" fn yk_mt_max_worker_threads_set(mt: &MT, max_worker_threads: usize) {
+    mt.set_max_worker_threads(max_worker_threads);
+}
+
+#[no_mangle]
+pub extern "C" fn yk_mt_serialise_compilation_set(mt: &MT, serialise_compilation: bool) {
+    mt.set_serialise_compilation(serialise_compilation);
+}
+
+#[no_mangle]
+pub extern "C" fn yk_mt_abort_tracing(mt: &MT) {
+    mt.abort_tracing();
+}
+
+#[no_mangle]
+pub extern "C" fn yk_mt_max_trace_length_set(mt: &MT, max_trace_length: usize) {
+    mt.set_max_trace_length(max_trace_length);
+}
+
+/// A C mirror of [ykrt::Stats]. Times are in nanoseconds.
+#[repr(C)]
+pub struct YkMTStats {
+    traces_started: u64,
+    traces_completed: u64,
+    traces_aborted: u64,
+    traces_compiled: u64,
+    traces_compile_failed: u64,
+    deopts: u64,
+    tracing_time_ns: u64,
+    mapping_time_ns: u64,
+    compiling_time_ns: u64,
+    stopgap_time_ns: u64,
+    compile_queue_len: usize,
+    active_worker_threads: usize,
+}
+
+#[no_mangle]
+pub extern "C" fn yk_mt_stats(mt: &MT) -> YkMTStats {
+    let stats = mt.stats();
+    let ns = |d: std::time::Duration| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+    YkMTStats {
+        traces_started: stats.traces_started,
+        traces_completed: stats.traces_completed,
+        traces_aborted: stats.traces_aborted,
+        traces_compiled: stats.traces_compiled,
+        traces_compile_failed: stats.traces_compile_failed,
+        deopts: stats.deopts,
+        tracing_time_ns: ns(stats.tracing_time),
+        mapping_time_ns: ns(stats.mapping_time),
+        compiling_time_ns: ns(stats.compiling_time),
+        stopgap_time_ns: ns(stats.stopgap_time),
+        compile_queue_len: stats.compile_queue_len,
+        active_worker_threads: stats.active_worker_threads,
+    }
+}
+
+/// A C mirror of [ykrt::JitEvent].
+#[repr(C)]
+pub enum YkJitEvent {
+    StartTracing,
+    StopTracing,
+    StartSideTracing,
+    StopSideTracing,
+    EnterJitCode,
+    ExitJitCode,
+    Deoptimise,
+    TraceCompilationAborted,
+}
+
+/// A C function to be notified of JIT events. `reason` is null, except for
+/// [YkJitEvent::TraceCompilationAborted] events, where it is a string which is only valid for the
+/// duration of the call.
+type YkJitObserverFn = extern "C" fn(YkJitEvent, *const c_char, *mut c_void);
+
+/// Forwards [JitEvent]s to a C function, along with the opaque pointer it was registered with.
+struct CJitObserver {
+    func: YkJitObserverFn,
+    data: *mut c_void,
+}
+
+// It is the C caller's responsibility to ensure that `data` can be used from any thread.
+unsafe impl Send for CJitObserver {}
+unsafe impl Sync for CJitObserver {}
+
+impl JitObserver for CJitObserver {
+    fn notify(&self, event: &JitEvent) {
+        let (cevent, reason) = match event {
+            JitEvent::StartTracing => (YkJitEvent::StartTracing, None),
+            JitEvent::StopTracing => (YkJitEvent::StopTracing, None),
+            JitEvent::StartSideTracing => (YkJitEvent::StartSideTracing, None),
+            JitEvent::StopSideTracing => (YkJitEvent::StopSideTracing, None),
+            JitEvent::EnterJitCode => (YkJitEvent::EnterJitCode, None),
+            JitEvent::ExitJitCode => (YkJitEvent::ExitJitCode, None),
+            JitEvent::Deoptimise => (YkJitEvent::Deoptimise, None),
+            JitEvent::TraceCompilationAborted(x) => (
+                YkJitEvent::TraceCompilationAborted,
+                // Interior nul bytes are unlikely, but we'd rather lose the reason than panic.
+                Some(CString::new(x.as_str()).unwrap_or_default()),
+            ),
+        };
+        (self.func)(
+            cevent,
+            reason.as_ref().map_or(ptr::null(), |x| x.as_ptr()),
+            self.data,
+        );
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn yk_mt_jit_observer_set(
+    mt: &MT,
+    func: Option<YkJitObserverFn>,
+    data: *mut c_void,
+) {
+    mt.set_jit_observer(
+        func.map(|func| Arc::new(CJitObserver { func, data }) as Arc<dyn JitObserver>),
+    );
+}
+
 #[no_mangle]
 pub extern "C" fn yk_location_new() -> Location {
     Location::new()
@@ -91,6 +251,60 @@ pub extern "C" fn yk_location_drop(loc: Location) {
     drop(loc)
 }
 
+#[no_mangle]
+pub extern "C" fn yk_location_set_threshold(loc: &Location, hot_threshold: HotThreshold) {
+    loc.set_hot_threshold(hot_threshold);
+}
+
+#[no_mangle]
+pub extern "C" fn yk_location_set_dont_trace(loc: &Location) {
+    loc.set_dont_trace();
+}
+
+#[no_mangle]
+pub extern "C" fn yk_promote(val: usize) -> usize {
+    yktrace::record_promotion(val);
+    val
+}
+
+/// A C mirror of [ykrt::LocationKind], without the data.
+#[repr(C)]
+pub enum YkLocationKind {
+    Counting,
+    Tracing,
+    Compiling,
+    Compiled,
+    DontTrace,
+}
+
+/// A C mirror of [ykrt::LocationState]. `count` is only meaningful for
+/// [YkLocationKind::Counting], and `trace_id` for [YkLocationKind::Compiled]: otherwise they are 0.
+#[repr(C)]
+pub struct YkLocationState {
+    kind: YkLocationKind,
+    count: HotThreshold,
+    trace_failures: u16,
+    trace_id: u64,
+}
+
+#[no_mangle]
+pub extern "C" fn yk_location_state(loc: &Location) -> YkLocationState {
+    let state = loc.state();
+    let (kind, count, trace_id) = match state.kind {
+        LocationKind::Counting(count) => (YkLocationKind::Counting, count, 0),
+        LocationKind::Tracing => (YkLocationKind::Tracing, 0, 0),
+        LocationKind::Compiling => (YkLocationKind::Compiling, 0, 0),
+        LocationKind::Compiled(trace_id) => (YkLocationKind::Compiled, 0, trace_id),
+        LocationKind::DontTrace => (YkLocationKind::DontTrace, 0, 0),
+    };
+    YkLocationState {
+        kind,
+        count,
+        trace_failures: state.trace_failures,
+        trace_id,
+    }
+}
+
 /// Reads out registers spilled to the stack of the previous frame during the deoptimisation
 /// routine. The order of the registers are in accordance to the DWARF register number mapping
 /// referenced in the SystemV ABI manual (https://uclibc.org/docs/psABI-x86_64.pdf).
@@ -154,14 +368,17 @@ pub struct CVec {
     length: usize,
 }
 
-/// Address, offset, and length of the live AOT values for this guard failure. Mirrors the struct
-/// defined in ykllvmwrap/jitmodbuilder.cc.
+/// Address, offset, and length of the live AOT values for this guard failure, the ID of the guard
+/// that failed, and the trace containing it. Mirrors the struct defined in
+/// ykllvmwrap/jitmodbuilder.cc.
 #[derive(Debug)]
 #[repr(C)]
 pub struct LiveAOTVals {
     addr: *const c_void,
     offset: usize,
     length: usize,
+    guard: usize,
+    trace: *const c_void,
 }
 
 /// After a guard failure, reconstructs the stack frames and registers and then jumps back to the
@@ -234,8 +451,8 @@ pub extern "C" fn yk_stopgap(
     retaddr: usize,
     rsp: *const c_void,
 ) -> *const c_void {
-    #[cfg(feature = "yk_jitstate_debug")]
-    print_jit_state("deoptimise");
+    let start = Instant::now();
+    let (trace, guard) = (aotvals.trace, aotvals.guard);
 
     // Parse the live AOT values.
     let aotvalsptr =
@@ -330,7 +547,11 @@ pub extern "C" fn yk_stopgap(
         }
     }
 
-    framerec.reconstruct_frames(frameaddr)
+    let newframes = framerec.reconstruct_frames(frameaddr);
+
+    // Remember which guard failed, so that the meta-tracer can decide whether to side trace it.
+    yktrace::record_guard_failure(trace, guard, start.elapsed());
+    newframes
 }
 
 /// The `__llvm__deoptimize()` function required by `llvm.experimental.deoptimize` intrinsic, that
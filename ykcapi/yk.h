@@ -1,6 +1,8 @@
 #ifndef YK_H
 #define YK_H
 
+#include <stdbool.h>
+#include <stddef.h>
 #include <stdint.h>
 
 // A `Location` stores state that the meta-tracer needs to identify hot loops
@@ -37,9 +39,19 @@ typedef struct YkMT YkMT;
 //       2. `yk_mt_new` will return `NULL`.
 YkMT *yk_mt_new(char **err_msg);
 
+// Create a new `YkMT` instance configured by `config`, which consists of lines
+// of the form `<option> = <value>`, in the same format as the file named by
+// the `YK_CONFIG` environment variable. Options in `config` take precedence
+// over those in the environment. If `config` is `NULL`, this is equivalent to
+// `yk_mt_new`. Errors, including invalid options, are reported as for
+// `yk_mt_new`.
+YkMT *yk_mt_new_with_config(const char *config, char **err_msg);
+
 // Drop a `YkMT` instance. This must be called at most once per `YkMT`
 // instance: calling this function more than once on a `YkMT` instance leads to
-// undefined behaviour.
+// undefined behaviour. Traces waiting to be compiled are discarded, and this
+// function waits for traces which are being compiled to finish compiling and
+// for the instance's compilation worker threads to exit.
 void yk_mt_drop(YkMT *);
 
 // Notify yk that an iteration of an interpreter loop is about to start. The
@@ -51,6 +63,98 @@ void yk_mt_control_point(YkMT *, YkLocation *);
 // Set the threshold at which `YkLocation`'s are considered hot.
 void yk_mt_hot_threshold_set(YkMT *, YkHotThreshold);
 
+// Set the number of times a guard in a compiled trace must fail before a side
+// trace is compiled for it.
+void yk_mt_side_trace_threshold_set(YkMT *, YkHotThreshold);
+
+// If the current thread is tracing, stop tracing and discard the trace. Call
+// this when the trace is known to be of no use (e.g. because an exception is
+// unwinding past the loop being traced). The `YkLocation` being traced can be
+// traced again once it is hot again: an aborted trace does not count as a
+// failed attempt to trace it.
+void yk_mt_abort_tracing(YkMT *);
+
+// Set the maximum number of worker threads compiling traces, which must be at
+// least 1 (0 is ignored). Worker threads which are already running are not
+// stopped if this is lower than the number of running worker threads.
+void yk_mt_max_worker_threads_set(YkMT *, size_t);
+
+// If the second argument is true, compile traces synchronously on the thread
+// which recorded them, rather than on a worker thread. The next time that
+// thread reaches the trace's `YkLocation`, the compiled trace is executed.
+// This makes the JIT's behaviour deterministic, at the cost of pausing the
+// interpreter while traces are compiled.
+void yk_mt_serialise_compilation_set(YkMT *, bool);
+
+// Set the maximum number of control points (including those of `YkLocation`s
+// other than the one being traced) that a thread may pass through while
+// tracing. If a trace grows longer than this, tracing is abandoned, and counts
+// as a failed attempt to trace its `YkLocation`.
+void yk_mt_max_trace_length_set(YkMT *, size_t);
+
+// Statistics about what a `YkMT` instance has been doing. This is a C mirror
+// of `ykrt::Stats`. Counts and times are cumulative over the lifetime of the
+// `YkMT` instance and include both root and side traces. Times are in
+// nanoseconds.
+typedef struct {
+  // How many traces have been started.
+  uint64_t traces_started;
+  // How many traces were completed, and passed on to be compiled.
+  uint64_t traces_completed;
+  // How many traces were aborted before they completed.
+  uint64_t traces_aborted;
+  // How many traces have been compiled.
+  uint64_t traces_compiled;
+  // How many completed traces could not be mapped or compiled.
+  uint64_t traces_compile_failed;
+  // How many times execution has deoptimised from a compiled trace.
+  uint64_t deopts;
+  uint64_t tracing_time_ns;
+  uint64_t mapping_time_ns;
+  uint64_t compiling_time_ns;
+  uint64_t stopgap_time_ns;
+  // How many compilation jobs are waiting to be run.
+  size_t compile_queue_len;
+  // How many worker threads are running.
+  size_t active_worker_threads;
+} YkMTStats;
+
+// Return a snapshot of a `YkMT` instance's statistics. Other threads may
+// concurrently update the statistics, so the snapshot may be immediately
+// stale.
+YkMTStats yk_mt_stats(YkMT *);
+
+// A JIT event. This is a C mirror of `ykrt::JitEvent`. There are no "start
+// interpreting" and "stop interpreting" events: if the system is not currently
+// tracing or executing JITted code, then it is implicitly interpreting.
+typedef enum {
+  YK_JIT_EVENT_START_TRACING,
+  YK_JIT_EVENT_STOP_TRACING,
+  YK_JIT_EVENT_START_SIDE_TRACING,
+  YK_JIT_EVENT_STOP_SIDE_TRACING,
+  YK_JIT_EVENT_ENTER_JIT_CODE,
+  YK_JIT_EVENT_EXIT_JIT_CODE,
+  YK_JIT_EVENT_DEOPTIMISE,
+  YK_JIT_EVENT_TRACE_COMPILATION_ABORTED,
+} YkJitEvent;
+
+// A function to be notified of JIT events. `reason` is `NULL` except for
+// `YK_JIT_EVENT_TRACE_COMPILATION_ABORTED` events, where it is a string
+// explaining why compilation was aborted: the string is only valid until the
+// function returns. `data` is the pointer passed to `yk_mt_jit_observer_set`.
+typedef void (*YkJitObserver)(YkJitEvent event, const char *reason,
+                              void *data);
+
+// Register a function to be notified of a `YkMT` instance's JIT events,
+// replacing any previously registered function. If the function is `NULL`, no
+// function is notified.
+//
+// The function is called synchronously on the thread where the event happened,
+// which for `YK_JIT_EVENT_TRACE_COMPILATION_ABORTED` may be a compilation
+// worker thread. It should return quickly, and must not call any `yk_*`
+// functions.
+void yk_mt_jit_observer_set(YkMT *, YkJitObserver, void *data);
+
 // Create a new `Location`.
 //
 // Note that a `Location` created by this call must not simply be discarded:
@@ -61,8 +165,85 @@ YkLocation yk_location_new(void);
 // Clean-up a `Location` previously created by `yk_new_location`. The
 // `Location` must not be further used after this call or undefined behaviour
 // will occur.
+//
+// A `Location` can be dropped at any point, including while a thread is
+// tracing it or its trace is being compiled (e.g. when an interpreter frees
+// the bytecode of a closure created at run-time): the trace is then
+// discarded. If a thread is executing the `Location`'s compiled trace, the
+// compiled trace is freed when the thread stops executing it.
 void yk_location_drop(YkLocation);
 
+// Set the threshold at which this `Location` is considered hot, overriding the
+// `YkMT`'s hot threshold. If the `Location` is later reprofiled, its threshold
+// is increased from this threshold. Thresholds of 2^27 or more are treated as
+// 2^27-1. Setting a threshold does not allocate memory.
+void yk_location_set_threshold(YkLocation *, YkHotThreshold);
+
+// Never trace this `Location`. If the `Location` already has a compiled trace,
+// the trace is still executed, but the `Location` is not traced again if the
+// trace is discarded. This does not allocate memory.
+void yk_location_set_dont_trace(YkLocation *);
+
+// Return `val`. If the current thread is tracing, the trace is specialised on
+// the value: a guard checks that later executions of the trace pass the same
+// value, so the compiled trace can treat it as a constant. This is useful for
+// values which rarely change at a given point in a program, e.g. the type of
+// an object or the target of a dynamically dispatched call.
+//
+// `yk_promote` must be called directly (not through a function pointer) from
+// code compiled by ykllvm. Otherwise the call isn't specialised on, and the
+// values of later calls in the same trace may be mixed up, causing their
+// guards to fail.
+uintptr_t yk_promote(uintptr_t val);
+
+// Marks a function as idempotent: given the same arguments, it always returns
+// the same result, and it has no side effects that the program relies upon
+// (e.g. a function looking up an entry in a table of constants). If a trace
+// calls an idempotent function whose arguments are all constant in the trace
+// (e.g. because they were passed to `yk_promote`), the function is called
+// once when the trace is compiled and the compiled trace uses its result.
+//
+// Such calls are only executed at compile-time if the function takes at most
+// 6 arguments, its arguments and result are integers or pointers, and it is
+// visible to `dlsym`. The function may be called on any thread.
+#define YK_IDEMPOTENT __attribute__((annotate("yk_idempotent"), noinline))
+
+// The states a `Location` can be in. There is no state for a dropped
+// `Location`: `yk_location_drop` frees the `Location`'s state but can't change
+// the caller's copy of the `YkLocation`, so a dropped `Location` can't be
+// distinguished from a live one and must not be passed to `yk_location_state`.
+typedef enum {
+  // Counting how often the `Location` has been executed until it is hot.
+  YK_LOCATION_COUNTING,
+  // A thread is tracing the `Location`.
+  YK_LOCATION_TRACING,
+  // The `Location`'s trace is waiting to be, or being, compiled.
+  YK_LOCATION_COMPILING,
+  // The `Location` has a compiled trace.
+  YK_LOCATION_COMPILED,
+  // The `Location` will not be traced (again).
+  YK_LOCATION_DONT_TRACE,
+} YkLocationKind;
+
+// A snapshot of a `Location`'s state, as returned by `yk_location_state`.
+typedef struct {
+  YkLocationKind kind;
+  // For `YK_LOCATION_COUNTING`, how often the `Location` has been executed
+  // (since it was last reprofiled, if it has been). Otherwise 0.
+  YkHotThreshold count;
+  // How many attempts to trace, or compile a trace for, the `Location` have
+  // failed.
+  uint16_t trace_failures;
+  // For `YK_LOCATION_COMPILED`, the ID of the compiled trace (as used in the
+  // `YKD_LOG` log). Otherwise 0.
+  uint64_t trace_id;
+} YkLocationState;
+
+// Return a snapshot of a `Location`'s state, e.g. so that a debugger can show
+// which loops have been compiled. Other threads may change the `Location`'s
+// state, so the snapshot is potentially stale as soon as it is returned.
+YkLocationState yk_location_state(YkLocation *);
+
 void yk_stopgap(void *addr, uintptr_t size, uintptr_t retaddr, void *rsp);
 
 #endif
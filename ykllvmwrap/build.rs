@@ -27,6 +27,7 @@ fn main() {
     comp.file("src/ykllvmwrap.cc")
         .file("src/jitmodbuilder.cc")
         .file("src/memman.cc")
+        .file("src/objcache.cc")
         // Lots of unused parameters in the LLVM headers.
         .flag("-Wno-unused-parameter")
         .cpp(true);
@@ -13,6 +13,7 @@
 #include <atomic>
 #include <bit>
 #include <err.h>
+#include <set>
 #include <sys/types.h>
 #include <variant>
 
@@ -29,26 +30,66 @@ uint64_t getNewTraceIdx() {
 
 #define TRACE_FUNC_PREFIX "__yk_compiled_trace_"
 #define YK_NEW_CONTROL_POINT "__ykrt_control_point"
+#define YK_CONTROL_POINT_ARG_LOC_IDX 1
 #define YK_CONTROL_POINT_ARG_VARS_IDX 2
 #define YK_CONTROL_POINT_ARG_FRAMEADDR_IDX 3
 #define YK_CONTROL_POINT_NUM_ARGS 4
 
 #define JITFUNC_ARG_INPUTS_STRUCT_IDX 0
-#define JITFUNC_ARG_STACKMAP_ADDR_IDX 1
-#define JITFUNC_ARG_STACKMAP_LEN_IDX 2
-#define JITFUNC_ARG_FRAMEADDR_IDX 3
-#define JITFUNC_ARG_LIVEAOTVALS_PTR_IDX 4
+#define JITFUNC_ARG_EXECINFO_IDX 1
+#define JITFUNC_ARG_FRAMEADDR_IDX 2
+#define JITFUNC_ARG_GUARD_LIVEVARS_IDX 3
+
+// Fields of the struct describing a compiled trace at run-time, which is
+// passed to the trace. Mirrors `ExecInfo` in yktrace.
+#define EXECINFO_FUNC_IDX 0
+#define EXECINFO_STACKMAP_ADDR_IDX 1
+#define EXECINFO_STACKMAP_LEN_IDX 2
+#define EXECINFO_LIVEAOTVALS_PTR_IDX 3
+#define EXECINFO_SIDETRACES_IDX 4
+#define EXECINFO_EXECUTIONS_IDX 5
+
+// The function which guard failure blocks call to execute a side trace.
+#define YK_EXEC_SIDE_TRACE "__yktrace_exec_side_trace"
+
+// The function which side traces call to find the compiled trace of a
+// `Location` other than the one their root trace was compiled for.
+#define YK_STITCH_LOCATION "__ykrt_stitch_location"
 
 #define YK_OUTLINE_FNATTR "yk_outline"
 
+// The function which interpreters call to specialise a trace on a run-time
+// value.
+#define YK_PROMOTE "yk_promote"
+
+// The annotation (i.e. `__attribute__((annotate(...)))`) marking functions
+// which always return the same result for the same arguments.
+#define YK_IDEMPOTENT_ANNOT "yk_idempotent"
+
+// The maximum number of arguments of an idempotent function call that we
+// execute at compile-time. All of them are passed in registers.
+#define YK_IDEMPOTENT_MAX_ARGS 6
+
 // The first two arguments of a stackmap call are it's id and shadow bytes and
 // need to be skipped when scanning the operands for live values.
 #define YK_STACKMAP_SKIP_ARGS 2
 
-// Return value telling the caller of the compiled trace that no guard failure
-// occurred and it's safe to continue running the fake trace stitching loop.
+// Return value telling the caller of a side trace that execution reached the
+// control point of the `Location` that the root trace was compiled for, so the
+// root trace can start its next iteration.
 #define TRACE_RETURN_SUCCESS 0
 
+// Return value telling the caller of a side trace that execution reached a
+// control point other than the one the root trace was compiled for, and so
+// must continue in the interpreter.
+#define TRACE_RETURN_EXIT 1
+
+// Tag added to the address of the `ExecInfo` struct of another `Location`'s
+// compiled trace, which a side trace returns to tell its root trace to jump to
+// that trace. The reconstructed stacks returned when deoptimising are page
+// aligned, so can't be confused with tagged addresses.
+#define TRACE_RETURN_STITCH_TAG 2
+
 // The name prefix used for blocks that are branched to when a guard succeeds.
 #define GUARD_SUCCESS_BLOCK_NAME "guardsuccess"
 
@@ -294,6 +335,37 @@ void dumpValueAndExit(const char *Msg, Value *V) {
   exit(EXIT_FAILURE);
 }
 
+// Return the instruction at index `InstrIdx` in the block at index `BBIdx` of
+// the function `F`.
+Instruction *getInstrAt(Function *F, size_t BBIdx, size_t InstrIdx) {
+  auto BBIt = F->begin();
+  std::advance(BBIt, BBIdx);
+  assert(BBIt != F->end());
+  auto It = BBIt->begin();
+  std::advance(It, InstrIdx);
+  assert(It != BBIt->end());
+  return &*It;
+}
+
+// Return the index of the block containing `I` within its function, and the
+// index of `I` within that block.
+std::pair<size_t, size_t> getInstrPosition(Instruction *I) {
+  BasicBlock *BB = I->getParent();
+  size_t BBIdx = 0;
+  for (BasicBlock &TBB : *BB->getParent()) {
+    if (&TBB == BB)
+      break;
+    BBIdx++;
+  }
+  size_t InstrIdx = 0;
+  for (Instruction &TI : *BB) {
+    if (&TI == I)
+      break;
+    InstrIdx++;
+  }
+  return {BBIdx, InstrIdx};
+}
+
 // A function name and basic block index pair that identifies a mappable block
 // in the AOT LLVM IR.
 struct IRBlock {
@@ -402,12 +474,13 @@ public:
   }
 };
 
-// Struct to store a live AOT value.
-struct AOTInfo {
-  size_t BBIdx;
-  size_t InstrIdx;
-  const char *FName;
-  size_t FrameIdx;
+// Where a side trace starts: the guard in the parent trace whose failures the
+// side trace handles.
+struct SideTraceStart {
+  // The guard in the parent trace.
+  GuardInfo *Guard;
+  // The parent trace's live AOT values.
+  AOTInfo *ParentLiveAOT;
 };
 
 class JITModBuilder {
@@ -433,6 +506,20 @@ class JITModBuilder {
   // Function virtual addresses discovered from the input trace.
   FuncAddrs FAddrs;
 
+  // The values passed to `yk_promote` while the trace was collected, and the
+  // index of the value for the next call to `yk_promote` we encounter.
+  uintptr_t *Promotions;
+  size_t PromotionsLen;
+  size_t PromotionsIdx = 0;
+
+  // JIT values which, at the point in the trace we are compiling, are known
+  // to be equal to a constant (e.g. because a guard checked them), but which
+  // aren't constants themselves, mapped to that constant.
+  std::map<Value *, Constant *> KnownConsts;
+
+  // The functions in AOTMod annotated with `YK_IDEMPOTENT_ANNOT`.
+  std::set<Function *> IdempotentFuncs;
+
   // Maps field indices in the live variables struct to the value stored prior
   // to calling the control point.
   std::map<uint64_t, Value *> LiveIndexMap;
@@ -460,6 +547,31 @@ class JITModBuilder {
   // compilation.
   CallStack CallStack;
 
+  // The address of the `Location` that the root trace (i.e. this trace or, if
+  // we are compiling a side trace, the trace at the start of its chain of
+  // parent traces) was compiled for. Null in the trace compiler tests.
+  void *RootLoc;
+
+  // The JIT value of the `Location` passed to the control point at which the
+  // trace ends (or null if that isn't known).
+  Value *EndLoc = nullptr;
+
+  // The index of the first element of `InpTrace` to compile.
+  size_t TraceStartIdx = 0;
+
+  // If we are compiling a root trace, the block at the start of each of the
+  // trace's iterations.
+  BasicBlock *LoopBB = nullptr;
+
+  // If we are compiling a root trace, the blocks which handle a side trace
+  // returning something other than `TRACE_RETURN_SUCCESS` (or the trace
+  // ending at another `Location`), and the PHI node in the first of these
+  // blocks which receives the value to act upon. These are created on demand
+  // and only inserted into `JITFunc` once the trace is complete, so that they
+  // follow the blocks of the trace.
+  std::vector<BasicBlock *> SideTraceExitBBs;
+  PHINode *SideTraceRets = nullptr;
+
   Value *getMappedValue(Value *V) {
     if (VMap.find(V) != VMap.end()) {
       return VMap[V];
@@ -537,6 +649,103 @@ class JITModBuilder {
     return Struct;
   }
 
+  // Return the type of the struct describing a compiled trace at run-time.
+  StructType *getExecInfoTy() {
+    LLVMContext &Context = JITMod->getContext();
+    PointerType *PtrTy = PointerType::get(Context, 0);
+    return StructType::get(Context, {PtrTy, PtrTy, PointerSizedIntTy, PtrTy,
+                                     PtrTy, Type::getInt64Ty(Context)});
+  }
+
+  // Load field `Idx` of the struct `ExecInfo`, which describes a compiled
+  // trace at run-time.
+  Value *loadExecInfoField(IRBuilder<> &Builder, Value *ExecInfo,
+                           unsigned Idx) {
+    StructType *ExecInfoTy = getExecInfoTy();
+    Value *GEP = Builder.CreateStructGEP(ExecInfoTy, ExecInfo, Idx);
+    return Builder.CreateLoad(ExecInfoTy->getElementType(Idx), GEP);
+  }
+
+  // Count an execution of the compiled trace described by `ExecInfo`.
+  //
+  // This is done on every iteration of a trace, so, as with a `Location`'s hot
+  // count, we don't pay for an atomic read-modify-write: if several threads
+  // execute the trace at once, some of their increments may be lost, which is
+  // fine since the count only needs to be roughly right.
+  void countExecution(IRBuilder<> &Builder, Value *ExecInfo) {
+    Type *Int64Ty = Type::getInt64Ty(JITMod->getContext());
+    Value *GEP = Builder.CreateStructGEP(getExecInfoTy(), ExecInfo,
+                                         EXECINFO_EXECUTIONS_IDX);
+    LoadInst *Executions = Builder.CreateAlignedLoad(Int64Ty, GEP, Align(8));
+    Executions->setAtomic(AtomicOrdering::Monotonic);
+    StoreInst *Store = Builder.CreateAlignedStore(
+        Builder.CreateAdd(Executions, ConstantInt::get(Int64Ty, 1)), GEP,
+        Align(8));
+    Store->setAtomic(AtomicOrdering::Monotonic);
+  }
+
+  // Create a call which asks the runtime for the compiled trace of the
+  // `Location` `Loc`, returning either a tagged pointer to that trace's
+  // `ExecInfo` or, if it has no compiled trace, `TRACE_RETURN_EXIT`.
+  Value *createStitchLocation(IRBuilder<> &Builder, Value *Loc) {
+    LLVMContext &Context = JITMod->getContext();
+    PointerType *PtrTy = PointerType::get(Context, 0);
+    FunctionCallee StitchLocation = JITMod->getOrInsertFunction(
+        YK_STITCH_LOCATION, FunctionType::get(PtrTy, {PtrTy}, false));
+    Value *Target = Builder.CreateCall(StitchLocation, {Loc});
+    Value *Tagged = Builder.CreateGEP(
+        Type::getInt8Ty(Context), Target,
+        ConstantInt::get(PointerSizedIntTy, TRACE_RETURN_STITCH_TAG));
+    Value *Exit = ConstantExpr::getIntToPtr(
+        ConstantInt::get(PointerSizedIntTy, TRACE_RETURN_EXIT), PtrTy);
+    return Builder.CreateSelect(Builder.CreateIsNull(Target), Exit, Tagged);
+  }
+
+  // Create the blocks which, in a root trace, handle a side trace returning
+  // something other than `TRACE_RETURN_SUCCESS`. If the side trace returned
+  // a tagged pointer to another `Location`'s compiled trace, we jump to that
+  // trace. Since every compiled trace has the same signature, we can do so
+  // with a tail call, which replaces this trace's frame: no matter how often
+  // execution moves between traces, the stack doesn't grow. Anything else the
+  // side trace returned is returned to our caller.
+  void createSideTraceExitBlocks() {
+    if (!SideTraceExitBBs.empty())
+      return;
+    LLVMContext &Context = JITMod->getContext();
+    PointerType *PtrTy = PointerType::get(Context, 0);
+    BasicBlock *SideTraceExitBB = BasicBlock::Create(Context, "sidetraceexit");
+    BasicBlock *StitchBB = BasicBlock::Create(Context, "stitch");
+    BasicBlock *ExitBB = BasicBlock::Create(Context, "exit");
+    SideTraceExitBBs = {SideTraceExitBB, StitchBB, ExitBB};
+
+    IRBuilder<> ExitBuilder(SideTraceExitBB);
+    SideTraceRets = ExitBuilder.CreatePHI(PtrTy, 0);
+    Value *Tag = ExitBuilder.CreateAnd(
+        ExitBuilder.CreatePtrToInt(SideTraceRets, PointerSizedIntTy),
+        ConstantInt::get(PointerSizedIntTy, sizeof(void *) - 1));
+    ExitBuilder.CreateCondBr(
+        ExitBuilder.CreateICmpEQ(
+            Tag, ConstantInt::get(PointerSizedIntTy, TRACE_RETURN_STITCH_TAG)),
+        StitchBB, ExitBB);
+
+    ExitBuilder.SetInsertPoint(StitchBB);
+    Value *Target = ExitBuilder.CreateGEP(
+        Type::getInt8Ty(Context), SideTraceRets,
+        ConstantInt::getSigned(PointerSizedIntTy, -TRACE_RETURN_STITCH_TAG));
+    countExecution(ExitBuilder, Target);
+    CallInst *Stitch = ExitBuilder.CreateCall(
+        JITFunc->getFunctionType(),
+        loadExecInfoField(ExitBuilder, Target, EXECINFO_FUNC_IDX),
+        {JITFunc->getArg(JITFUNC_ARG_INPUTS_STRUCT_IDX), Target,
+         JITFunc->getArg(JITFUNC_ARG_FRAMEADDR_IDX),
+         ConstantPointerNull::get(PtrTy)});
+    Stitch->setTailCallKind(CallInst::TCK_MustTail);
+    ExitBuilder.CreateRet(Stitch);
+
+    ExitBuilder.SetInsertPoint(ExitBB);
+    ExitBuilder.CreateRet(SideTraceRets);
+  }
+
   void handleCallInst(CallInst *CI, Function *CF, size_t &CurBBIdx,
                       size_t &CurInstrIdx) {
     // Update the most-recent frame's progress so that we return to the right
@@ -552,18 +761,36 @@ class JITModBuilder {
       if (CF != nullptr && VMap.find(CF) == VMap.end()) {
         declareFunction(CF);
       }
+      bool IsPromote = CF != nullptr && CF->getName() == YK_PROMOTE;
       if (!Outlining) {
         copyInstruction(&Builder, (Instruction *)&*CI, CurBBIdx, CurInstrIdx);
+        if (IsPromote)
+          handlePromote(CI, CurBBIdx, CurInstrIdx);
         startOutlining();
+      } else if (IsPromote) {
+        // Outlined code isn't specialised on the value it promotes, but it
+        // was recorded all the same.
+        PromotionsIdx++;
       }
       CallStack.pushFrame(StackFrame::CreateForeignFrame());
     } else {
       // Calling to a non-foreign function.
       if (!Outlining) {
-        // We are not outlining, but should this call start us outlining?
-        if (CF->hasFnAttribute(YK_OUTLINE_FNATTR) || CF->isVarArg() ||
-            CallStack.hasFrameForFunction(CF)) {
-          // We will outline this call.
+        // If the callee is idempotent and its arguments are constant, we can
+        // execute it now, so that the trace need neither call it nor contain
+        // its code.
+        Constant *Result = nullptr;
+        if (IdempotentFuncs.count(CF) != 0)
+          Result = executeIdempotentCall(CI, CF);
+
+        if (Result != nullptr) {
+          // We skip the callee's blocks in the trace as if we were outlining
+          // the call, but, rather than copying the call, we assign its result.
+          assignCallResult(CI, Result, CurBBIdx, CurInstrIdx);
+          startOutlining();
+        } else if (CF->hasFnAttribute(YK_OUTLINE_FNATTR) || CF->isVarArg() ||
+                   CallStack.hasFrameForFunction(CF)) {
+          // We are not outlining, but this call starts us outlining.
           //
           // If this is a recursive call that has been inlined, or if the callee
           // has the "yk_outline" annotation, remove the inlined code and turn
@@ -594,6 +821,162 @@ class JITModBuilder {
     }
   }
 
+  // Emits a guard checking that the call to `yk_promote` `CI`, which has just
+  // been copied into JITMod, returns the value it returned while tracing.
+  //
+  // The call's JIT value stays in `VMap`, so that if this guard, or a later
+  // one, fails, the interpreter sees the value that was actually returned.
+  // LLVM propagates the equality that the guard establishes, replacing uses of
+  // the call after the guard with the constant, and folding them. We also
+  // remember it in `KnownConsts`, so that later calls to idempotent functions
+  // can be executed at compile-time.
+  void handlePromote(CallInst *CI, size_t CurBBIdx, size_t CurInstrIdx) {
+    LLVMContext &Context = JITMod->getContext();
+    assert(PromotionsIdx < PromotionsLen);
+    ConstantInt *Promoted = ConstantInt::get(cast<IntegerType>(CI->getType()),
+                                             Promotions[PromotionsIdx++]);
+
+    // If the guard fails, execution resumes after the call, so the live
+    // variables of the most-recent frame are those of the stackmap call
+    // following it. `getGuardFailureBlock` sets a temporary resume point for
+    // that frame, so we must remove the one `handleCallInst` has set in the
+    // meantime (it's the same one).
+    MappableFrame *CurFrame = CallStack.curMappableFrame();
+    CurFrame->LastSMCall = cast<CallInst>(CI->getNextNonDebugInstruction());
+    CurFrame->clearResume();
+    BasicBlock *FailBB =
+        getGuardFailureBlock(CI->getParent(), CurBBIdx, CI, CurInstrIdx);
+    CurFrame->setResume(CurBBIdx, CI, CurInstrIdx);
+
+    BasicBlock *SuccBB =
+        BasicBlock::Create(Context, GUARD_SUCCESS_BLOCK_NAME, JITFunc);
+    Builder.CreateCondBr(Builder.CreateICmpEQ(getMappedValue(CI), Promoted),
+                         SuccBB, FailBB);
+    Builder.SetInsertPoint(SuccBB);
+    KnownConsts[getMappedValue(CI)] = Promoted;
+  }
+
+  // If the AOT value `V` is known to be constant in the trace at this point,
+  // return the constant, otherwise return null.
+  Constant *getKnownConstant(Value *V) {
+    Value *JV = getMappedValue(V);
+    if (Constant *C = dyn_cast<Constant>(JV))
+      return C;
+    auto It = KnownConsts.find(JV);
+    if (It != KnownConsts.end())
+      return It->second;
+    return nullptr;
+  }
+
+  // If `C` is an integer or a pointer to a known address, return its value as
+  // an argument to pass to an idempotent function whose parameter `ParamIdx`
+  // is `C`'s type. Otherwise return an empty optional.
+  std::optional<uintptr_t> getIdempotentArg(Function *CF, unsigned ParamIdx,
+                                            Constant *C) {
+    if (isa<ConstantPointerNull>(C))
+      return 0;
+    ConstantExpr *CE = dyn_cast<ConstantExpr>(C);
+    if (CE != nullptr && CE->getOpcode() == Instruction::IntToPtr)
+      C = CE->getOperand(0);
+    ConstantInt *CI = dyn_cast<ConstantInt>(C);
+    if (CI == nullptr || CI->getBitWidth() > sizeof(uintptr_t) * CHAR_BIT)
+      return std::nullopt;
+    // The caller must extend arguments narrower than a register if (and only
+    // if) the callee's parameter says so.
+    if (CF->hasParamAttribute(ParamIdx, Attribute::SExt))
+      return static_cast<uintptr_t>(CI->getSExtValue());
+    return CI->getZExtValue();
+  }
+
+  // Try to execute the call `CI` to the idempotent function `CF` now, at
+  // compile-time, returning its result as a constant. Returns null if we
+  // can't, e.g. because not all of its arguments are known to be constant.
+  //
+  // We only deal with integer and pointer arguments and results, which the
+  // x86-64 System V ABI passes in registers, so that we can call any such
+  // function through a single function pointer type.
+  Constant *executeIdempotentCall(CallInst *CI, Function *CF) {
+    Type *RetTy = CF->getReturnType();
+    if (CF->isVarArg() || CI->arg_size() > YK_IDEMPOTENT_MAX_ARGS ||
+        !(RetTy->isPointerTy() ||
+          (RetTy->isIntegerTy() &&
+           RetTy->getIntegerBitWidth() <= sizeof(uintptr_t) * CHAR_BIT)))
+      return nullptr;
+    // Traces which are compiled but never executed (in the trace compiler tests
+    // and when replaying a saved trace) are given dummy function addresses,
+    // which can't be called.
+    void *FAddr = FAddrs[CF->getName().data()];
+    if (FAddr == nullptr || FAddr == (void *)YK_INVALID_ALIGNED_VADDR)
+      return nullptr;
+
+    uintptr_t Args[YK_IDEMPOTENT_MAX_ARGS] = {0};
+    for (unsigned I = 0; I < CI->arg_size(); I++) {
+      Value *Arg = CI->getArgOperand(I);
+      handleOperand(Arg);
+      Constant *C = getKnownConstant(Arg);
+      if (C == nullptr)
+        return nullptr;
+      std::optional<uintptr_t> A = getIdempotentArg(CF, I, C);
+      if (!A.has_value())
+        return nullptr;
+      Args[I] = *A;
+    }
+
+    typedef uintptr_t (*IdempotentFunc)(uintptr_t, uintptr_t, uintptr_t,
+                                        uintptr_t, uintptr_t, uintptr_t);
+    uintptr_t Ret = reinterpret_cast<IdempotentFunc>(FAddr)(
+        Args[0], Args[1], Args[2], Args[3], Args[4], Args[5]);
+    if (RetTy->isPointerTy())
+      return ConstantExpr::getIntToPtr(
+          ConstantInt::get(PointerSizedIntTy, Ret), RetTy);
+    // Bits of the return register beyond the result's width are undefined, so
+    // `ConstantInt::get` truncating `Ret` is what we want.
+    return ConstantInt::get(cast<IntegerType>(RetTy), Ret);
+  }
+
+  // Make the result of the call `CI` the constant `C`.
+  //
+  // As for PHI nodes (see `handlePHINode`), we emulate an assignment with a
+  // select instruction, so that there is a JIT value mapping back to `CI` for
+  // guard failures to initialise.
+  void assignCallResult(CallInst *CI, Constant *C, size_t CurBBIdx,
+                        size_t CurInstrIdx) {
+    Instruction *NewInst = SelectInst::Create(
+        ConstantInt::get(Type::getInt1Ty(JITMod->getContext()), 0), C, C);
+    Builder.Insert(NewInst);
+    VMap[CI] = NewInst;
+    insertAOTMap(CI, NewInst, CurBBIdx, CurInstrIdx);
+    KnownConsts[NewInst] = C;
+  }
+
+  // Find the functions in AOTMod annotated as being idempotent.
+  void findIdempotentFuncs() {
+    GlobalVariable *Annots =
+        AOTMod->getGlobalVariable("llvm.global.annotations");
+    if (Annots == nullptr || !Annots->hasInitializer())
+      return;
+    ConstantArray *CA = dyn_cast<ConstantArray>(Annots->getInitializer());
+    if (CA == nullptr)
+      return;
+    for (Value *Op : CA->operands()) {
+      // Each element is a struct whose first two fields are the annotated
+      // value and a global string containing the annotation.
+      ConstantStruct *CS = dyn_cast<ConstantStruct>(Op);
+      if (CS == nullptr || CS->getNumOperands() < 2)
+        continue;
+      Function *F = dyn_cast<Function>(CS->getOperand(0)->stripPointerCasts());
+      GlobalVariable *AnnotGV =
+          dyn_cast<GlobalVariable>(CS->getOperand(1)->stripPointerCasts());
+      if (F == nullptr || AnnotGV == nullptr || !AnnotGV->hasInitializer())
+        continue;
+      ConstantDataArray *Annot =
+          dyn_cast<ConstantDataArray>(AnnotGV->getInitializer());
+      if (Annot != nullptr && Annot->isCString() &&
+          Annot->getAsCString() == YK_IDEMPOTENT_ANNOT)
+        IdempotentFuncs.insert(F);
+    }
+  }
+
   // Emits a guard for a LLVM `br` instruction, returning a pointer to the
   // guard success block, or null if no guard was required.
   BasicBlock *handleBranchInst(Function *JITFunc, BasicBlock *NextBlock,
@@ -738,15 +1121,16 @@ class JITModBuilder {
     std::vector<Type *> InputTypes;
     InputTypes.push_back(TraceInputs->getType());
 
-    // Add arguments for stackmap pointer and size.
-    InputTypes.push_back(PointerSizedIntTy->getPointerTo());
-    InputTypes.push_back(PointerSizedIntTy);
+    // Add argument for the struct describing this trace at run-time (its
+    // stackmap, live AOT values, side traces etc.).
+    InputTypes.push_back(PointerType::get(JITMod->getContext(), 0));
 
     // Add argument in which to store the value of an interpreted return.
     InputTypes.push_back(FrameAddr);
 
-    // Add argument for memory block holding live AOT values.
-    InputTypes.push_back(PointerSizedIntTy->getPointerTo());
+    // Add argument for the struct holding the variables live at the start of
+    // a side trace (null if this isn't a side trace).
+    InputTypes.push_back(PointerType::get(JITMod->getContext(), 0));
 
     llvm::FunctionType *FType = llvm::FunctionType::get(
         PointerType::get(JITMod->getContext(), 0), InputTypes, false);
@@ -821,12 +1205,16 @@ class JITModBuilder {
 
     LLVMContext &Context = JITFunc->getContext();
 
-    // Create the block.
+    // Create the blocks. The guard failure block executes the guard's side
+    // trace if it has one, and otherwise deoptimises.
     // FIXME: Cache guard blocks where live variables and frames are the same,
     // e.g. this can happen when a loop is unrolled and the same condition
     // produces a guard in each unrolled iteration.
     BasicBlock *GuardFailBB = BasicBlock::Create(Context, "guardfail", JITFunc);
-    IRBuilder<> FailBuilder(GuardFailBB);
+    BasicBlock *SideTraceBB = BasicBlock::Create(Context, "sidetrace", JITFunc);
+    BasicBlock *DeoptBB = BasicBlock::Create(Context, "deopt", JITFunc);
+    IRBuilder<> DeoptBuilder(DeoptBB);
+    Value *ExecInfo = JITFunc->getArg(JITFUNC_ARG_EXECINFO_IDX);
 
     // Add the control point struct to the live variables we pass into the
     // `deoptimize` call so the stopgap interpreter can access it.
@@ -844,7 +1232,7 @@ class JITModBuilder {
     // FIXME: Use function index instead of string name.
     StructType *ActiveFrameSTy = StructType::get(
         Context, {PointerSizedIntTy, PointerSizedIntTy, Int8PtrTy});
-    AllocaInst *ActiveFrameVec = FailBuilder.CreateAlloca(
+    AllocaInst *ActiveFrameVec = DeoptBuilder.CreateAlloca(
         ActiveFrameSTy, ConstantInt::get(PointerSizedIntTy, CallStack.size()));
 
     // To describe the callstack at a guard failure, the following code uses
@@ -855,6 +1243,7 @@ class JITModBuilder {
     assert(CurFrame);
     CurFrame->setResume(CurBBIdx, Instr, CurInstrIdx);
 
+    GuardInfo GI;
     std::vector<Value *> LiveValues;
     for (size_t I = 0; I < CallStack.size(); I++) {
       StackFrame &SF = CallStack.getFrame(I);
@@ -876,6 +1265,7 @@ class JITModBuilder {
         if (VMap.find(Arg) != VMap.end()) {
           Value *JITArg = VMap[Arg];
           LiveValues.push_back(JITArg);
+          GI.LiveVars.push_back({I, Idx});
         }
       }
 
@@ -886,30 +1276,34 @@ class JITModBuilder {
       // element (since each element has only 3 fields a Int8 would suffice,
       // but for convenience we just use the Int32Ty we already have defined
       // above).
-      auto GEP = FailBuilder.CreateGEP(ActiveFrameSTy, ActiveFrameVec,
+      auto GEP = DeoptBuilder.CreateGEP(ActiveFrameSTy, ActiveFrameVec,
                                        {ConstantInt::get(PointerSizedIntTy, I),
                                         ConstantInt::get(Int32Ty, 0)});
       assert(MF->Resume.has_value());
       BlockResumePoint &RP = MF->Resume.value();
-      FailBuilder.CreateStore(
+      DeoptBuilder.CreateStore(
           ConstantInt::get(PointerSizedIntTy, RP.ResumeBBIdx), GEP);
-      GEP = FailBuilder.CreateGEP(ActiveFrameSTy, ActiveFrameVec,
+      GEP = DeoptBuilder.CreateGEP(ActiveFrameSTy, ActiveFrameVec,
                                   {ConstantInt::get(PointerSizedIntTy, I),
                                    ConstantInt::get(Int32Ty, 1)});
-      FailBuilder.CreateStore(
+      DeoptBuilder.CreateStore(
           ConstantInt::get(PointerSizedIntTy, RP.ResumeAfterInstrIdx), GEP);
-      Value *CurFunc = FailBuilder.CreateGlobalStringPtr(MF->Func->getName());
-      GEP = FailBuilder.CreateGEP(ActiveFrameSTy, ActiveFrameVec,
+      Value *CurFunc = DeoptBuilder.CreateGlobalStringPtr(MF->Func->getName());
+      GEP = DeoptBuilder.CreateGEP(ActiveFrameSTy, ActiveFrameVec,
                                   {ConstantInt::get(PointerSizedIntTy, I),
                                    ConstantInt::get(Int32Ty, 2)});
-      FailBuilder.CreateStore(CurFunc, GEP);
+      DeoptBuilder.CreateStore(CurFunc, GEP);
+
+      auto [SMCBBIdx, SMCInstrIdx] = getInstrPosition(SMC);
+      GI.Frames.push_back({MF->Func->getName().str(), RP.ResumeBBIdx,
+                           RP.ResumeAfterInstrIdx, SMCBBIdx, SMCInstrIdx});
     }
 
     // Store the active frames vector and its length in a separate struct to
     // save arguments.
     AllocaInst *ActiveFramesStruct = createAndFillStruct(
-        FailBuilder, {ActiveFrameVec,
-                      ConstantInt::get(PointerSizedIntTy, CallStack.size())});
+        DeoptBuilder, {ActiveFrameVec,
+                       ConstantInt::get(PointerSizedIntTy, CallStack.size())});
 
     // Make more space to store the locations of the corresponding live AOT
     // values for this guard failure.
@@ -932,19 +1326,71 @@ class JITModBuilder {
       CurrentRegion[I] = {BBIdx, InstrIdx, FName, StackFrameIdx};
     }
 
-    // Store the live variable vector and its length in a separate struct to
-    // save arguments.
+    // Remember what's needed to compile a side trace for this guard.
+    GI.LiveAOTIdx = CurPos;
+    size_t GuardIdx = Guards.size();
+    Guards.push_back(std::move(GI));
+
+    // Store the live variable vector, its length, the ID of the guard, and
+    // the trace the guard belongs to in a separate struct to save arguments.
     AllocaInst *AOTLocs = createAndFillStruct(
-        FailBuilder,
-        {JITFunc->getArg(JITFUNC_ARG_LIVEAOTVALS_PTR_IDX),
+        DeoptBuilder,
+        {loadExecInfoField(DeoptBuilder, ExecInfo,
+                           EXECINFO_LIVEAOTVALS_PTR_IDX),
          ConstantInt::get(PointerSizedIntTy, CurPos * sizeof(AOTInfo)),
-         ConstantInt::get(PointerSizedIntTy, LiveValues.size())});
+         ConstantInt::get(PointerSizedIntTy, LiveValues.size()),
+         ConstantInt::get(PointerSizedIntTy, GuardIdx), ExecInfo});
 
     // Store the stackmap address and length in a separate struct to save
     // arguments.
     AllocaInst *StackMapStruct = createAndFillStruct(
-        FailBuilder, {JITFunc->getArg(JITFUNC_ARG_STACKMAP_ADDR_IDX),
-                      JITFunc->getArg(JITFUNC_ARG_STACKMAP_LEN_IDX)});
+        DeoptBuilder,
+        {loadExecInfoField(DeoptBuilder, ExecInfo, EXECINFO_STACKMAP_ADDR_IDX),
+         loadExecInfoField(DeoptBuilder, ExecInfo, EXECINFO_STACKMAP_LEN_IDX)});
+
+    // If a side trace has been compiled for this guard, we execute it instead
+    // of deoptimising. Side traces are installed by other threads, so the
+    // guard's entry in the side trace array must be loaded atomically.
+    IRBuilder<> FailBuilder(GuardFailBB);
+    PointerType *PtrTy = PointerType::get(Context, 0);
+    Value *SideTracePtr = FailBuilder.CreateGEP(
+        PtrTy,
+        loadExecInfoField(FailBuilder, ExecInfo, EXECINFO_SIDETRACES_IDX),
+        ConstantInt::get(PointerSizedIntTy, GuardIdx));
+    LoadInst *SideTrace = FailBuilder.CreateLoad(PtrTy, SideTracePtr);
+    SideTrace->setAtomic(AtomicOrdering::Acquire);
+    FailBuilder.CreateCondBr(FailBuilder.CreateIsNull(SideTrace), DeoptBB,
+                             SideTraceBB);
+
+    // The side trace takes over from the interpreter frames that the guard
+    // would otherwise have reconstructed, so we pass it the live variables
+    // which the `deoptimize` call would otherwise have been given. Root traces
+    // loop, so the stack space used to do so must be freed again afterwards.
+    IRBuilder<> SideTraceBuilder(SideTraceBB);
+    Value *StackPtr = SideTraceBuilder.CreateCall(
+        Intrinsic::getDeclaration(JITMod, Intrinsic::stacksave));
+    AllocaInst *GuardLiveVars =
+        createAndFillStruct(SideTraceBuilder, LiveValues);
+    FunctionCallee ExecSideTrace = JITMod->getOrInsertFunction(
+        YK_EXEC_SIDE_TRACE,
+        FunctionType::get(PtrTy, {PtrTy, PtrTy, PtrTy, PtrTy}, false));
+    CallInst *SideTraceRet = SideTraceBuilder.CreateCall(
+        ExecSideTrace,
+        {SideTrace, JITFunc->getArg(JITFUNC_ARG_INPUTS_STRUCT_IDX),
+         JITFunc->getArg(JITFUNC_ARG_FRAMEADDR_IDX), GuardLiveVars});
+    SideTraceBuilder.CreateCall(
+        Intrinsic::getDeclaration(JITMod, Intrinsic::stackrestore), StackPtr);
+    if (LoopBB != nullptr) {
+      // If the side trace got back to this trace's `Location`, we start the
+      // next iteration of this trace.
+      createSideTraceExitBlocks();
+      SideTraceRets->addIncoming(SideTraceRet, SideTraceBB);
+      SideTraceBuilder.CreateCondBr(SideTraceBuilder.CreateIsNull(SideTraceRet),
+                                    LoopBB, SideTraceExitBBs[0]);
+    } else {
+      // Side traces leave it to their root trace to decide what to do next.
+      SideTraceBuilder.CreateRet(SideTraceRet);
+    }
 
     // Create the deoptimization call.
     Type *retty = PointerType::get(Context, 0);
@@ -958,10 +1404,10 @@ class JITModBuilder {
         CallInst::Create(DeoptInt,
                          {StackMapStruct, AOTLocs, ActiveFramesStruct,
                           JITFunc->getArg(JITFUNC_ARG_FRAMEADDR_IDX)},
-                         {ob}, "", GuardFailBB);
+                         {ob}, "", DeoptBB);
 
     // We always need to return after the deoptimisation call.
-    ReturnInst::Create(Context, Ret, GuardFailBB);
+    ReturnInst::Create(Context, Ret, DeoptBB);
 
     // Delete the temporary resume point for the most-recent frame.
     CurFrame->clearResume();
@@ -1139,7 +1585,15 @@ class JITModBuilder {
           MapMetadata(MD.second, VMap, llvm::RF_ReuseAndMutateDistinctMDs));
     }
 
-    // And finally insert the new instruction into the JIT module.
+    // And finally insert the new instruction into the JIT module. A root
+    // trace loops, so fixed size stack allocations must be moved out of the
+    // loop, lest the stack grows on each iteration.
+    AllocaInst *AI = dyn_cast<AllocaInst>(NewInst);
+    if (LoopBB != nullptr && AI != nullptr &&
+        isa<Constant>(AI->getArraySize())) {
+      AI->insertBefore(JITFunc->getEntryBlock().getTerminator());
+      return;
+    }
     Builder->Insert(NewInst);
   }
 
@@ -1321,6 +1775,102 @@ class JITModBuilder {
     }
   }
 
+  // If `LI` is a `getelementpointer`/`load` pair that is loading from the
+  // `YkCtrlPointVars` pointer, then we have to update the `VMap` using the
+  // information we previously computed in `LiveIndexMap`. See comments above
+  // about `LiveIndexMap`.
+  void mapLiveIndex(LoadInst *LI) {
+    Value *LoadOper = LI->getPointerOperand();
+    if (isa<GetElementPtrInst>(LoadOper)) {
+      GetElementPtrInst *GI = cast<GetElementPtrInst>(LoadOper);
+      if (GI->getPointerOperand() == TraceInputs) {
+        Value *LoadedFromIdxVal = *(std::next(GI->idx_begin()));
+        assert(isa<ConstantInt>(LoadedFromIdxVal));
+        uint64_t LoadedFromIdx =
+            cast<ConstantInt>(LoadedFromIdxVal)->getZExtValue();
+        Value *NewMapVal = LiveIndexMap[LoadedFromIdx];
+        VMap[NewMapVal] = getMappedValue(LI);
+      }
+    }
+  }
+
+  // Prepare to compile a side trace starting at the guard described by
+  // `Side`: the call stack is rebuilt as it was when the guard failed, and the
+  // variables live at the guard are loaded from the struct passed in by the
+  // parent trace.
+  void initSideTrace(SideTraceStart &Side) {
+    LLVMContext &Context = AOTMod->getContext();
+    GuardInfo &G = *Side.Guard;
+
+    assert(!G.Frames.empty());
+    for (size_t I = 0; I < G.Frames.size(); I++) {
+      GuardFrame &GF = G.Frames[I];
+      Function *F = AOTMod->getFunction(GF.FuncName);
+      assert(F != nullptr);
+      Instruction *Instr = getInstrAt(F, GF.BBIdx, GF.InstrIdx);
+      CallInst *SMC =
+          cast<CallInst>(getInstrAt(F, GF.SMCallBBIdx, GF.SMCallInstrIdx));
+      if (I < G.Frames.size() - 1) {
+        // Older frames were part-way through a call, and resume after it when
+        // the callee returns.
+        assert(isa<CallInst>(Instr));
+        CallStack.pushFrame(StackFrame::CreateMappableFrame(
+            F, SMC, BlockResumePoint{GF.BBIdx, Instr, GF.InstrIdx}));
+      } else {
+        // The most-recent frame failed the guard at the end of `Instr`'s
+        // block, so the trace starts at one of the block's successors.
+        StackFrame SF = StackFrame::CreateMappableFrame(F, SMC);
+        SF.getMappableFrame()->PrevBB = Instr->getParent();
+        CallStack.pushFrame(SF);
+      }
+    }
+
+    // Map each variable live at the guard to a load from the struct the
+    // parent trace passes in. The struct's layout mirrors the order in which
+    // the parent trace filled it.
+    std::vector<Value *> AOTLiveVars;
+    std::vector<Type *> Types;
+    for (GuardLiveVar &LV : G.LiveVars) {
+      MappableFrame *MF = CallStack.getFrame(LV.FrameIdx).getMappableFrame();
+      assert(MF);
+      Value *V = MF->LastSMCall->getArgOperand(LV.SMCallArgIdx);
+      AOTLiveVars.push_back(V);
+      Types.push_back(V->getType());
+    }
+    StructType *GuardLiveVarsTy = StructType::get(Context, Types);
+    IntegerType *Int32Ty = Type::getInt32Ty(Context);
+    for (size_t I = 0; I < AOTLiveVars.size(); I++) {
+      Value *AOTVar = AOTLiveVars[I];
+      // The control point struct is already mapped to an argument.
+      if (AOTVar == TraceInputs)
+        continue;
+      Value *GEP = Builder.CreateGEP(
+          GuardLiveVarsTy, JITFunc->getArg(JITFUNC_ARG_GUARD_LIVEVARS_IDX),
+          {ConstantInt::get(PointerSizedIntTy, 0),
+           ConstantInt::get(Int32Ty, I)});
+      LoadInst *Load = Builder.CreateLoad(Types[I], GEP);
+      VMap[AOTVar] = Load;
+      if (isa<LoadInst>(AOTVar))
+        mapLiveIndex(cast<LoadInst>(AOTVar));
+
+      // If one of this side trace's guards fails, the loaded value must be
+      // deoptimised to the same AOT variable as in the parent trace.
+      AOTInfo &AI = Side.ParentLiveAOT[G.LiveAOTIdx + I];
+      Function *AIF = AOTMod->getFunction(AI.FName);
+      assert(AIF != nullptr);
+      AOTMap[Load] = {AI.BBIdx, AI.InstrIdx,
+                      getInstrAt(AIF, AI.BBIdx, AI.InstrIdx), AI.FrameIdx};
+    }
+
+    // The remainder of the block containing the guard was executed by the
+    // parent trace, but a hardware trace may still include it.
+    IRBlock *FirstIB = InpTrace[0].getMappedBlock();
+    GuardFrame &GF = G.Frames.back();
+    if (FirstIB && GF.FuncName == FirstIB->FuncName &&
+        GF.BBIdx == FirstIB->BBIdx)
+      TraceStartIdx = 1;
+  }
+
   // Find the call site to the (patched) control point, the index of that call
   // site in the parent block, and the type of the struct used to pass in the
   // live LLVM variables.
@@ -1357,13 +1907,16 @@ class JITModBuilder {
   // OPT: https://github.com/ykjit/yk/issues/419
   JITModBuilder(Module *AOTMod, char *FuncNames[], size_t BBs[],
                 size_t TraceLen, char *FAddrKeys[], void *FAddrVals[],
-                size_t FAddrLen, CallInst *CPCI,
+                size_t FAddrLen, uintptr_t Promotions[], size_t PromotionsLen,
+                CallInst *CPCI,
                 std::optional<std::tuple<size_t, CallInst *>> InitialResume,
-                Value *TraceInputs)
+                Value *TraceInputs, void *RootLoc,
+                SideTraceStart *Side = nullptr)
       : AOTMod(AOTMod), Builder(AOTMod->getContext()),
         InpTrace(FuncNames, BBs, TraceLen),
-        FAddrs(FAddrKeys, FAddrVals, FAddrLen), TraceInputs(TraceInputs),
-        ControlPointCallInst(CPCI) {
+        FAddrs(FAddrKeys, FAddrVals, FAddrLen), Promotions(Promotions),
+        PromotionsLen(PromotionsLen), TraceInputs(TraceInputs),
+        ControlPointCallInst(CPCI), RootLoc(RootLoc) {
     LLVMContext &Context = AOTMod->getContext();
     JITMod = new Module("", Context);
 
@@ -1378,24 +1931,35 @@ class JITModBuilder {
     Builder.SetInsertPoint(DstBB);
 
     createLiveIndexMap(ControlPointCallInst, TraceInputs->getType());
+    findIdempotentFuncs();
 
     // Map the live variables struct used inside the trace to the corresponding
     // argument of the compiled trace function.
     VMap[TraceInputs] = JITFunc->getArg(JITFUNC_ARG_INPUTS_STRUCT_IDX);
 
-    // Push the initial frame.
-    IRBlock *StartIRB = InpTrace[0].getMappedBlock();
-    assert(StartIRB);
-    Function *StartFunc = AOTMod->getFunction(StartIRB->FuncName);
-    std::optional<BlockResumePoint> RP;
-    if (InitialResume.has_value()) {
-      auto [StartInstrIdx, StartCPCall] = *InitialResume;
-      assert(StartCPCall->getFunction() == StartFunc);
-      RP = BlockResumePoint{StartIRB->BBIdx, StartCPCall, StartInstrIdx};
+    if (Side != nullptr) {
+      initSideTrace(*Side);
+    } else {
+      // Push the initial frame.
+      IRBlock *StartIRB = InpTrace[0].getMappedBlock();
+      assert(StartIRB);
+      Function *StartFunc = AOTMod->getFunction(StartIRB->FuncName);
+      std::optional<BlockResumePoint> RP;
+      if (InitialResume.has_value()) {
+        auto [StartInstrIdx, StartCPCall] = *InitialResume;
+        assert(StartCPCall->getFunction() == StartFunc);
+        RP = BlockResumePoint{StartIRB->BBIdx, StartCPCall, StartInstrIdx};
+      }
+      StackFrame InitFrame =
+          StackFrame::CreateMappableFrame(StartFunc, nullptr, RP);
+      CallStack.pushFrame(InitFrame);
+
+      // A root trace loops until a guard fails, rather than returning to its
+      // caller after each iteration.
+      LoopBB = BasicBlock::Create(Context, "loop", JITFunc);
+      Builder.CreateBr(LoopBB);
+      Builder.SetInsertPoint(LoopBB);
     }
-    StackFrame InitFrame =
-        StackFrame::CreateMappableFrame(StartFunc, nullptr, RP);
-    CallStack.pushFrame(InitFrame);
 
     // In debug builds, sanity check our assumptions about the input trace.
 #ifndef NDEBUG
@@ -1424,18 +1988,37 @@ public:
   // live JIT values.
   AOTInfo *LiveAOTArray = nullptr;
   size_t LiveAOTNum = 0;
+  // Information about the trace's guards, indexed by guard ID.
+  vector<GuardInfo> Guards;
 
   JITModBuilder(JITModBuilder &&);
 
   static JITModBuilder Create(Module *AOTMod, char *FuncNames[], size_t BBs[],
                               size_t TraceLen, char *FAddrKeys[],
-                              void *FAddrVals[], size_t FAddrLen) {
+                              void *FAddrVals[], size_t FAddrLen,
+                              uintptr_t Promotions[], size_t PromotionsLen,
+                              void *RootLoc) {
+    CallInst *CPCI;
+    Value *TI;
+    size_t CPCIIdx;
+    std::tie(CPCI, CPCIIdx, TI) = GetControlPointInfo(AOTMod);
+    return JITModBuilder(AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals,
+                         FAddrLen, Promotions, PromotionsLen, CPCI,
+                         make_tuple(CPCIIdx, CPCI), TI, RootLoc);
+  }
+
+  static JITModBuilder
+  CreateSide(Module *AOTMod, char *FuncNames[], size_t BBs[], size_t TraceLen,
+             char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen,
+             uintptr_t Promotions[], size_t PromotionsLen,
+             SideTraceStart &Side, void *RootLoc) {
     CallInst *CPCI;
     Value *TI;
     size_t CPCIIdx;
     std::tie(CPCI, CPCIIdx, TI) = GetControlPointInfo(AOTMod);
     return JITModBuilder(AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals,
-                         FAddrLen, CPCI, make_tuple(CPCIIdx, CPCI), TI);
+                         FAddrLen, Promotions, PromotionsLen, CPCI, {}, TI,
+                         RootLoc, &Side);
   }
 
 #ifdef YK_TESTING
@@ -1493,9 +2076,11 @@ public:
     // first frame's `BlockResumePoint`. This means that the compiler will
     // start copying instructions from the beginning of the first block in the
     // trace, instead of after the return from the control point.
+    //
+    // No values are passed to `yk_promote` in the trace compiler tests.
     JITModBuilder JB(AOTMod, FuncNames, BBs, TraceLen, &NewFAddrKeys[0],
-                     &NewFAddrVals[0], NewFAddrKeys.size(), CPCI, {},
-                     TraceInputs);
+                     &NewFAddrVals[0], NewFAddrKeys.size(), nullptr, 0, CPCI,
+                     {}, TraceInputs, nullptr);
 
     return JB;
   }
@@ -1505,7 +2090,7 @@ public:
   // executed in the AOT module.
   Module *createModule() {
     // Iterate over the blocks of the trace.
-    for (size_t Idx = 0; Idx < InpTrace.Length(); Idx++) {
+    for (size_t Idx = TraceStartIdx; Idx < InpTrace.Length(); Idx++) {
       // Update the previously executed BB in the most-recent frame (if it's
       // mappable).
       TraceLoc Loc = InpTrace[Idx];
@@ -1720,6 +2305,20 @@ public:
             VMap[CI] = ConstantPointerNull::get(
                 Type::getInt8PtrTy(JITMod->getContext()));
 
+            // A trace ends at a control point, which needn't be for the
+            // `Location` that the trace started at, so we need to know which
+            // `Location` that control point is for. In particular, a thread
+            // stops tracing when it reaches a `Location` with a compiled
+            // trace: rather than the call of the compiled trace being a step
+            // within this trace, this trace jumps to the compiled trace.
+            if (!Outlining) {
+              Value *LocArg = CI->getArgOperand(YK_CONTROL_POINT_ARG_LOC_IDX);
+              if (isa<Constant>(LocArg) || VMap.find(LocArg) != VMap.end()) {
+                handleOperand(LocArg);
+                EndLoc = getMappedValue(LocArg);
+              }
+            }
+
             MPF->setResume(CurBBIdx, &*I, CurInstrIdx);
             if (!Outlining) {
               startOutlining();
@@ -1734,6 +2333,11 @@ public:
               // See: https://github.com/ykjit/yk/issues/610
               return nullptr;
             }
+            if (S == YK_PROMOTE && PromotionsIdx == PromotionsLen) {
+              // No value was recorded for this call to `yk_promote` (as in the
+              // trace compiler tests), so we can't specialise the trace on it.
+              return nullptr;
+            }
             handleCallInst(CI, CF, CurBBIdx, CurInstrIdx);
             break;
           }
@@ -1765,25 +2369,8 @@ public:
         // into JITMod.
         copyInstruction(&Builder, (Instruction *)&*I, CurBBIdx, CurInstrIdx);
 
-        // If we see a `getelementpointer`/`load` pair that is loading from the
-        // `YkCtrlPointVars` pointer, then we have to update the `VMap` using
-        // the information we previously computed in `LiveIndexMap`. See
-        // comments above about `LiveIndexMap`.
-        if (isa<LoadInst>(I)) {
-          LoadInst *LI = cast<LoadInst>(I);
-          Value *LoadOper = LI->getPointerOperand();
-          if (isa<GetElementPtrInst>(LoadOper)) {
-            GetElementPtrInst *GI = cast<GetElementPtrInst>(LoadOper);
-            if (GI->getPointerOperand() == TraceInputs) {
-              Value *LoadedFromIdxVal = *(std::next(GI->idx_begin()));
-              assert(isa<ConstantInt>(LoadedFromIdxVal));
-              uint64_t LoadedFromIdx =
-                  cast<ConstantInt>(LoadedFromIdxVal)->getZExtValue();
-              Value *NewMapVal = LiveIndexMap[LoadedFromIdx];
-              VMap[NewMapVal] = getMappedValue(LI);
-            }
-          }
-        }
+        if (isa<LoadInst>(I))
+          mapLiveIndex(cast<LoadInst>(I));
       }
 
       // Block complete. If we are still in the same frame, then update the
@@ -1793,36 +2380,101 @@ public:
       }
     }
 
-    // If the trace succeeded return a null pointer instead of a reconstructed
-    // frame address.
-    Builder.CreateRet(
-        ConstantPointerNull::get(PointerType::get(JITMod->getContext(), 0)));
+    LLVMContext &Context = JITMod->getContext();
+    PointerType *PtrTy = PointerType::get(Context, 0);
+    Value *Root = nullptr;
+    if (EndLoc != nullptr && RootLoc != nullptr) {
+      // We refer to the root `Location` through a global mapping, rather than
+      // a constant, so that the trace's machine code doesn't depend on where
+      // the `Location` happens to be in memory (see `TraceObjCache`).
+      GlobalVariable *RootGV = new GlobalVariable(
+          *JITMod, Type::getInt8Ty(Context), true,
+          GlobalValue::ExternalLinkage, nullptr, YK_ROOT_LOC);
+      GlobalMappings.insert({RootGV, RootLoc});
+      Root = ConstantExpr::getPointerCast(RootGV, EndLoc->getType());
+    } else if (EndLoc != nullptr) {
+      Root = ConstantPointerNull::get(cast<PointerType>(EndLoc->getType()));
+    }
+    if (LoopBB == nullptr) {
+      // A side trace which gets back to its root trace's `Location` returns
+      // to the root trace, which starts its next iteration. Otherwise, if the
+      // `Location` it reaches has a compiled trace, the side trace asks the
+      // root trace to jump to it; failing that, execution continues in the
+      // interpreter.
+      if (EndLoc != nullptr) {
+        BasicBlock *AtRootBB = BasicBlock::Create(Context, "atroot", JITFunc);
+        BasicBlock *StitchBB = BasicBlock::Create(Context, "stitch", JITFunc);
+        Builder.CreateCondBr(Builder.CreateICmpEQ(EndLoc, Root), AtRootBB,
+                             StitchBB);
+        Builder.SetInsertPoint(AtRootBB);
+        Builder.CreateRet(ConstantPointerNull::get(PtrTy));
+        Builder.SetInsertPoint(StitchBB);
+        Builder.CreateRet(createStitchLocation(Builder, EndLoc));
+      } else {
+        Builder.CreateRet(ConstantExpr::getIntToPtr(
+            ConstantInt::get(PointerSizedIntTy, TRACE_RETURN_EXIT), PtrTy));
+      }
+    } else {
+      // The root trace completed an iteration. A trace which started tracing
+      // at one `Location` may have stopped at another which already had a
+      // compiled trace, in which case we jump to that trace.
+      countExecution(Builder, JITFunc->getArg(JITFUNC_ARG_EXECINFO_IDX));
+      if (EndLoc != nullptr) {
+        BasicBlock *StitchBB = BasicBlock::Create(Context, "stitch", JITFunc);
+        Builder.CreateCondBr(Builder.CreateICmpEQ(EndLoc, Root), LoopBB,
+                             StitchBB);
+        Builder.SetInsertPoint(StitchBB);
+        Value *Target = createStitchLocation(Builder, EndLoc);
+        createSideTraceExitBlocks();
+        SideTraceRets->addIncoming(Target, StitchBB);
+        Builder.CreateBr(SideTraceExitBBs[0]);
+      } else {
+        Builder.CreateBr(LoopBB);
+      }
+    }
+    for (BasicBlock *BB : SideTraceExitBBs)
+      BB->insertInto(JITFunc);
     finalise(AOTMod, &Builder);
     return JITMod;
   }
 };
 
-tuple<Module *, string, std::map<GlobalValue *, void *>, void *>
+tuple<Module *, string, std::map<GlobalValue *, void *>, void *,
+      vector<GuardInfo>>
 createModule(Module *AOTMod, char *FuncNames[], size_t BBs[], size_t TraceLen,
-             char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen) {
-  JITModBuilder JB = JITModBuilder::Create(AOTMod, FuncNames, BBs, TraceLen,
-                                           FAddrKeys, FAddrVals, FAddrLen);
+             char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen,
+             uintptr_t Promotions[], size_t PromotionsLen, void *RootLoc) {
+  JITModBuilder JB =
+      JITModBuilder::Create(AOTMod, FuncNames, BBs, TraceLen, FAddrKeys,
+                            FAddrVals, FAddrLen, Promotions, PromotionsLen,
+                            RootLoc);
   auto JITMod = JB.createModule();
   return make_tuple(JITMod, std::move(JB.TraceName),
-                    std::move(JB.GlobalMappings), JB.LiveAOTArray);
+                    std::move(JB.GlobalMappings), JB.LiveAOTArray,
+                    std::move(JB.Guards));
 }
 
-#ifdef YK_TESTING
-tuple<Module *, string, std::map<GlobalValue *, void *>, void *>
-createModuleForTraceCompilerTests(Module *AOTMod, char *FuncNames[],
-                                  size_t BBs[], size_t TraceLen,
-                                  char *FAddrKeys[], void *FAddrVals[],
-                                  size_t FAddrLen) {
-  JITModBuilder JB = JITModBuilder::CreateMocked(
-      AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals, FAddrLen);
-
+tuple<Module *, string, std::map<GlobalValue *, void *>, void *,
+      vector<GuardInfo>>
+createSideModule(Module *AOTMod, char *FuncNames[], size_t BBs[],
+                 size_t TraceLen, char *FAddrKeys[], void *FAddrVals[],
+                 size_t FAddrLen, uintptr_t Promotions[], size_t PromotionsLen,
+                 GuardInfo &Guard, void *ParentLiveAOTVals, void *RootLoc) {
+  SideTraceStart Side{&Guard, static_cast<AOTInfo *>(ParentLiveAOTVals)};
+  JITModBuilder JB = JITModBuilder::CreateSide(
+      AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals, FAddrLen,
+      Promotions, PromotionsLen, Side, RootLoc);
   auto JITMod = JB.createModule();
+  return make_tuple(JITMod, std::move(JB.TraceName),
+                    std::move(JB.GlobalMappings), JB.LiveAOTArray,
+                    std::move(JB.Guards));
+}
 
+#ifdef YK_TESTING
+// Prepare a JIT module for compilation in a process where the symbols it
+// refers to don't exist, and in which it will never be executed.
+static void mockExternals(Module *JITMod,
+                          std::map<GlobalValue *, void *> &GlobalMappings) {
   // When the trace compiler encounters a non-const global in a trace, it
   // inserts an LLVM `global external` variable referencing the variable in the
   // interpreter's address space. In the trace compiler tests, such global
@@ -1836,8 +2488,8 @@ createModuleForTraceCompilerTests(Module *AOTMod, char *FuncNames[],
   // inconsequential. We just need it to compile.
   for (GlobalVariable &G : JITMod->globals()) {
     if ((!G.hasInitializer()) &&
-        (JB.GlobalMappings.find(&G) == JB.GlobalMappings.end())) {
-      JB.GlobalMappings.insert({&G, (void *)YK_INVALID_ALIGNED_VADDR});
+        (GlobalMappings.find(&G) == GlobalMappings.end())) {
+      GlobalMappings.insert({&G, (void *)YK_INVALID_ALIGNED_VADDR});
     }
   }
 
@@ -1853,7 +2505,71 @@ createModuleForTraceCompilerTests(Module *AOTMod, char *FuncNames[],
   IRBuilder<> DOBuilder(DOBB);
   DOBuilder.CreateUnreachable();
 
+  // Likewise, provide a dummy implementation of the function which executes
+  // side traces.
+  if (Function *ESTFunc = JITMod->getFunction(YK_EXEC_SIDE_TRACE)) {
+    BasicBlock *ESTBB = BasicBlock::Create(Context, "", ESTFunc);
+    IRBuilder<> ESTBuilder(ESTBB);
+    ESTBuilder.CreateUnreachable();
+  }
+}
+
+tuple<Module *, string, std::map<GlobalValue *, void *>, void *,
+      vector<GuardInfo>>
+createModuleForTraceCompilerTests(Module *AOTMod, char *FuncNames[],
+                                  size_t BBs[], size_t TraceLen,
+                                  char *FAddrKeys[], void *FAddrVals[],
+                                  size_t FAddrLen) {
+  JITModBuilder JB = JITModBuilder::CreateMocked(
+      AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals, FAddrLen);
+
+  auto JITMod = JB.createModule();
+  mockExternals(JITMod, JB.GlobalMappings);
+
+  return make_tuple(JITMod, std::move(JB.TraceName),
+                    std::move(JB.GlobalMappings), nullptr,
+                    std::move(JB.Guards));
+}
+
+tuple<Module *, string, std::map<GlobalValue *, void *>, void *,
+      vector<GuardInfo>>
+createModuleForReplay(Module *AOTMod, char *FuncNames[], size_t BBs[],
+                      size_t TraceLen, uintptr_t Promotions[],
+                      size_t PromotionsLen) {
+  // The addresses of functions in the process where the trace was collected
+  // mean nothing in this one (not least because of ASLR), so, as in the trace
+  // compiler tests, every function in the AOT module gets a dummy address. As a
+  // result, calls to idempotent functions aren't executed at compile-time, so
+  // a replayed trace may keep calls which the original trace didn't.
+  std::vector<char *> FAddrKeys;
+  std::vector<void *> FAddrVals;
+  for (Function &F : AOTMod->functions()) {
+    FAddrKeys.push_back(const_cast<char *>(F.getName().data()));
+    FAddrVals.push_back((void *)YK_INVALID_ALIGNED_VADDR);
+  }
+
+  // A replayed trace is compiled as a root trace, but, as in the trace compiler
+  // tests, there's no `Location` it belongs to.
+  JITModBuilder JB = JITModBuilder::Create(
+      AOTMod, FuncNames, BBs, TraceLen, FAddrKeys.data(), FAddrVals.data(),
+      FAddrKeys.size(), Promotions, PromotionsLen, nullptr);
+  auto JITMod = JB.createModule();
+  if (JITMod == nullptr)
+    return make_tuple(nullptr, std::move(JB.TraceName),
+                      std::move(JB.GlobalMappings), nullptr,
+                      std::move(JB.Guards));
+  mockExternals(JITMod, JB.GlobalMappings);
+
+  // The functions the trace calls exist in the process where the trace was
+  // collected, but (mostly) not in this one, so they all get dummy addresses
+  // too.
+  for (Function &F : JITMod->functions()) {
+    if (F.isDeclaration() && !F.isIntrinsic())
+      JB.GlobalMappings.insert({&F, (void *)YK_INVALID_ALIGNED_VADDR});
+  }
+
   return make_tuple(JITMod, std::move(JB.TraceName),
-                    std::move(JB.GlobalMappings), nullptr);
+                    std::move(JB.GlobalMappings), JB.LiveAOTArray,
+                    std::move(JB.Guards));
 }
 #endif
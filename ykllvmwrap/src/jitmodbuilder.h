@@ -4,20 +4,77 @@
 #include "llvm/IR/GlobalValue.h"
 #include "llvm/IR/Module.h"
 #include <map>
+#include <string>
+#include <vector>
 
 // An unaligned virtual address.
 #define YK_INVALID_ALIGNED_VADDR 0x1
 
+// The global whose address is that of the `Location` for which a trace's root
+// trace was compiled.
+#define YK_ROOT_LOC "__yk_root_loc"
+
 using namespace llvm;
 
-std::tuple<Module *, std::string, std::map<GlobalValue *, void *>, void *>
+// The resume point of a frame that was active at a guard, and the position of
+// the stackmap call describing the frame's live variables.
+struct GuardFrame {
+  std::string FuncName;
+  size_t BBIdx;
+  size_t InstrIdx;
+  size_t SMCallBBIdx;
+  size_t SMCallInstrIdx;
+};
+
+// A variable that is live at a guard, identified by its position in the
+// arguments of the stackmap call of the frame it belongs to.
+struct GuardLiveVar {
+  size_t FrameIdx;
+  size_t SMCallArgIdx;
+};
+
+// Struct to store a live AOT value.
+struct AOTInfo {
+  size_t BBIdx;
+  size_t InstrIdx;
+  const char *FName;
+  size_t FrameIdx;
+};
+
+// What the trace compiler needs to know about a guard in order to compile a
+// side trace starting at it.
+struct GuardInfo {
+  // The frames active at the guard, oldest first.
+  std::vector<GuardFrame> Frames;
+  // The variables live at the guard, in the order they are passed to a side
+  // trace.
+  std::vector<GuardLiveVar> LiveVars;
+  // The index of the guard's first entry in the trace's live AOT values.
+  size_t LiveAOTIdx;
+};
+
+std::tuple<Module *, std::string, std::map<GlobalValue *, void *>, void *,
+           std::vector<GuardInfo>>
 createModule(Module *AOTMod, char *FuncNames[], size_t BBs[], size_t TraceLen,
-             char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen);
+             char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen,
+             uintptr_t Promotions[], size_t PromotionsLen, void *RootLoc);
+std::tuple<Module *, std::string, std::map<GlobalValue *, void *>, void *,
+           std::vector<GuardInfo>>
+createSideModule(Module *AOTMod, char *FuncNames[], size_t BBs[],
+                 size_t TraceLen, char *FAddrKeys[], void *FAddrVals[],
+                 size_t FAddrLen, uintptr_t Promotions[], size_t PromotionsLen,
+                 GuardInfo &Guard, void *ParentLiveAOTVals, void *RootLoc);
 #ifdef YK_TESTING
-std::tuple<Module *, std::string, std::map<GlobalValue *, void *>, void *>
+std::tuple<Module *, std::string, std::map<GlobalValue *, void *>, void *,
+           std::vector<GuardInfo>>
 createModuleForTraceCompilerTests(Module *AOTMod, char *FuncNames[],
                                   size_t BBs[], size_t TraceLen,
                                   char *FAddrKeys[], void *FAddrVals[],
                                   size_t FAddrLen);
+std::tuple<Module *, std::string, std::map<GlobalValue *, void *>, void *,
+           std::vector<GuardInfo>>
+createModuleForReplay(Module *AOTMod, char *FuncNames[], size_t BBs[],
+                      size_t TraceLen, uintptr_t Promotions[],
+                      size_t PromotionsLen);
 #endif // YK_TESTING
 #endif
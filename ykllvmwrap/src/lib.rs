@@ -6,22 +6,67 @@
 // https://github.com/ykjit/yk/issues/426
 
 use libc::{c_void, size_t};
-use std::ffi::{c_char, c_int};
+use std::ffi::{c_char, c_int, c_uint};
 
 extern "C" {
     pub fn __ykllvmwrap_irtrace_compile(
+        loc: *const c_void,
         func_names: *const *const c_char,
         bbs: *const size_t,
         trace_len: size_t,
         faddr_keys: *const *const c_char,
         faddr_vals: *const *const c_void,
         faddr_len: size_t,
+        promotions: *const usize,
+        promotions_len: size_t,
         llvmbc_data: *const u8,
         llvmbc_len: u64,
         debuginfo_fd: c_int,
         debuginfo_path: *const c_char,
+        print_ir: c_uint,
+        perf_map: bool,
+        trace_cache_dir: *const c_char,
     ) -> *const c_void;
 
+    pub fn __ykllvmwrap_irtrace_compile_side(
+        parent_info: *const c_void,
+        guard_idx: size_t,
+        parent_aotvals: *const c_void,
+        root_loc: *const c_void,
+        func_names: *const *const c_char,
+        bbs: *const size_t,
+        trace_len: size_t,
+        faddr_keys: *const *const c_char,
+        faddr_vals: *const *const c_void,
+        faddr_len: size_t,
+        promotions: *const usize,
+        promotions_len: size_t,
+        llvmbc_data: *const u8,
+        llvmbc_len: u64,
+        debuginfo_fd: c_int,
+        debuginfo_path: *const c_char,
+        print_ir: c_uint,
+        perf_map: bool,
+        trace_cache_dir: *const c_char,
+    ) -> *const c_void;
+
+    pub fn __ykllvmwrap_free_compiled_trace(info: *const c_void);
+
+    pub fn __ykllvmwrap_block_may_call(
+        func_name: *const c_char,
+        bb: size_t,
+        callee_name: *const c_char,
+        llvmbc_data: *const u8,
+        llvmbc_len: u64,
+    ) -> bool;
+
+    pub fn __ykllvmwrap_block_returns(
+        func_name: *const c_char,
+        bb: size_t,
+        llvmbc_data: *const u8,
+        llvmbc_len: u64,
+    ) -> bool;
+
     #[cfg(feature = "yk_testing")]
     pub fn __ykllvmwrap_irtrace_compile_for_tc_tests(
         func_names: *const *const c_char,
@@ -34,5 +79,20 @@ extern "C" {
         llvmbc_len: u64,
         debuginfo_fd: c_int,
         debuginfo_path: *const c_char,
+        print_ir: c_uint,
+    ) -> *const c_void;
+
+    #[cfg(feature = "yk_testing")]
+    pub fn __ykllvmwrap_irtrace_compile_for_replay(
+        func_names: *const *const c_char,
+        bbs: *const size_t,
+        trace_len: size_t,
+        promotions: *const usize,
+        promotions_len: size_t,
+        llvmbc_data: *const u8,
+        llvmbc_len: u64,
+        debuginfo_fd: c_int,
+        debuginfo_path: *const c_char,
+        print_ir: c_uint,
     ) -> *const c_void;
 }
@@ -16,12 +16,14 @@ uint8_t *allocateSection(uintptr_t Size, unsigned Alignment,
 }
 
 MemMan::MemMan(){};
-MemMan::~MemMan(){};
+MemMan::~MemMan() { freeMemory(); };
 
 uint8_t *MemMan::allocateCodeSection(uintptr_t Size, unsigned Alignment,
                                      unsigned SectionID,
                                      StringRef SectionName) {
-  return allocateSection(Size, Alignment, &code);
+  uint8_t *Ptr = allocateSection(Size, Alignment, &code);
+  codeExtents.push_back({Ptr, Size});
+  return Ptr;
 }
 
 uint8_t *MemMan::allocateDataSection(uintptr_t Size, unsigned Alignment,
@@ -56,11 +58,13 @@ void MemMan::freeMemory() {
       errx(EXIT_FAILURE, "Failed to unmap memory.");
     }
   }
+  code.clear();
   for (const AllocMem &Value : data) {
     if (munmap(Value.Ptr, Value.Size) == -1) {
       errx(EXIT_FAILURE, "Failed to unmap memory.");
     }
   }
+  data.clear();
 }
 
 void MemMan::setStackMapStore(AllocMem *Ptr) { SMR = Ptr; }
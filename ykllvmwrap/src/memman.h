@@ -13,6 +13,8 @@ struct AllocMem {
 class MemMan : public RTDyldMemoryManager {
   std::vector<AllocMem> code;
   std::vector<AllocMem> data;
+  // The start, and the size requested by LLVM, of each code section.
+  std::vector<AllocMem> codeExtents;
   AllocMem *SMR;
 
 public:
@@ -28,6 +30,7 @@ public:
   bool finalizeMemory(std::string *ErrMsg) override;
   void freeMemory();
   void setStackMapStore(AllocMem *Ptr);
+  const std::vector<AllocMem> &getCodeExtents() { return codeExtents; }
 };
 
 #endif
@@ -0,0 +1,181 @@
+#include "llvm/ADT/StringExtras.h"
+#include "llvm/IR/Function.h"
+#include "llvm/Support/FileSystem.h"
+#include "llvm/Support/SHA1.h"
+#include "llvm/Support/raw_ostream.h"
+
+#include <algorithm>
+#include <sstream>
+#include <stdlib.h>
+
+#include "objcache.h"
+
+using namespace llvm;
+
+// The prefix of the name of a cached trace's function.
+#define CACHED_TRACE_FUNC_PREFIX "__yk_cached_trace_"
+
+// Atomically write `Data` to the file `Path`. Other threads and processes may
+// be caching the same trace, so we write to a temporary file which we then
+// rename. If the file can't be written, nothing is lost except time in a
+// later process.
+static void writeFileAtomically(const std::string &Path, StringRef Data) {
+  int FD;
+  SmallString<128> TmpPath;
+  if (sys::fs::createUniqueFile(Path + ".%%%%%%%%.tmp", FD, TmpPath))
+    return;
+  {
+    raw_fd_ostream OS(FD, true);
+    OS << Data;
+    OS.close();
+    if (OS.has_error()) {
+      OS.clear_error();
+      sys::fs::remove(TmpPath);
+      return;
+    }
+  }
+  if (sys::fs::rename(TmpPath, Path))
+    sys::fs::remove(TmpPath);
+}
+
+// Print a `frame` line for each of the frames of the guard `GI`, and a `live`
+// line for each of its live variables, which also describes the live AOT value
+// in `LiveAOT` the variable is deoptimised to.
+static void printGuard(raw_ostream &OS, const GuardInfo &GI,
+                       AOTInfo *LiveAOT) {
+  for (const GuardFrame &GF : GI.Frames)
+    OS << "frame " << GF.BBIdx << " " << GF.InstrIdx << " " << GF.SMCallBBIdx
+       << " " << GF.SMCallInstrIdx << " " << GF.FuncName << "\n";
+  for (size_t I = 0; I < GI.LiveVars.size(); I++) {
+    const GuardLiveVar &LV = GI.LiveVars[I];
+    AOTInfo &AI = LiveAOT[GI.LiveAOTIdx + I];
+    OS << "live " << LV.FrameIdx << " " << LV.SMCallArgIdx << " " << AI.BBIdx
+       << " " << AI.InstrIdx << " " << AI.FrameIdx << " " << AI.FName << "\n";
+  }
+}
+
+std::string traceCacheKey(char *FuncNames[], size_t BBs[], size_t TraceLen,
+                          uintptr_t Promotions[], size_t PromotionsLen,
+                          GuardInfo *Guard, void *ParentLiveAOTVals) {
+  std::string S;
+  raw_string_ostream OS(S);
+  for (size_t I = 0; I < TraceLen; I++) {
+    // Unmappable blocks have no function name.
+    if (FuncNames[I] == nullptr)
+      OS << "unmappable\n";
+    else
+      OS << "block " << BBs[I] << " " << FuncNames[I] << "\n";
+  }
+  for (size_t I = 0; I < PromotionsLen; I++)
+    OS << "promotion " << Promotions[I] << "\n";
+  if (Guard != nullptr)
+    printGuard(OS, *Guard, static_cast<AOTInfo *>(ParentLiveAOTVals));
+  return toHex(SHA1::hash(arrayRefFromStringRef(OS.str())), true);
+}
+
+TraceObjCache::TraceObjCache(const char *Dir, const std::string &Key) {
+  TraceName = std::string(CACHED_TRACE_FUNC_PREFIX) + Key.substr(0, 16);
+  Path = std::string(Dir) + "/" + Key;
+
+  // A missing, unreadable, or corrupt object just means that the trace is
+  // compiled as normal.
+  auto Buf = MemoryBuffer::getFile(Path + ".o");
+  if (!Buf)
+    return;
+  auto File = object::ObjectFile::createObjectFile((*Buf)->getMemBufferRef());
+  if (!File) {
+    consumeError(File.takeError());
+    return;
+  }
+  Obj = std::move(*Buf);
+  ObjFile = std::move(*File);
+}
+
+// The guards and live AOT values of a cached trace are stored as lines of
+// text: each guard is a `guard` line followed by the lines printed by
+// `printGuard`.
+bool TraceObjCache::readMeta(Module *AOTMod, std::vector<GuardInfo> &Guards,
+                             void *&LiveAOTVals) {
+  auto Buf = MemoryBuffer::getFile(Path + ".meta");
+  bool OK = static_cast<bool>(Buf);
+  std::vector<AOTInfo> AOTVals;
+  if (OK) {
+    std::istringstream In((*Buf)->getBuffer().str());
+    std::string Line;
+    while (OK && std::getline(In, Line)) {
+      std::istringstream LS(Line);
+      std::string Kind;
+      LS >> Kind;
+      if (Kind == "guard") {
+        GuardInfo GI;
+        GI.LiveAOTIdx = AOTVals.size();
+        Guards.push_back(std::move(GI));
+      } else if (Kind == "frame" && !Guards.empty()) {
+        GuardFrame GF;
+        LS >> GF.BBIdx >> GF.InstrIdx >> GF.SMCallBBIdx >> GF.SMCallInstrIdx;
+        std::getline(LS >> std::ws, GF.FuncName);
+        OK = !LS.fail();
+        Guards.back().Frames.push_back(std::move(GF));
+      } else if (Kind == "live" && !Guards.empty()) {
+        GuardLiveVar LV;
+        AOTInfo AI;
+        std::string FName;
+        LS >> LV.FrameIdx >> LV.SMCallArgIdx >> AI.BBIdx >> AI.InstrIdx >>
+            AI.FrameIdx;
+        std::getline(LS >> std::ws, FName);
+        // The function name must outlive the trace, so we use the AOT
+        // module's copy, as the JIT module builder does.
+        Function *F = LS.fail() ? nullptr : AOTMod->getFunction(FName);
+        OK = F != nullptr;
+        if (OK) {
+          AI.FName = F->getName().data();
+          Guards.back().LiveVars.push_back(LV);
+          AOTVals.push_back(AI);
+        }
+      } else {
+        OK = false;
+      }
+    }
+  }
+  if (!OK) {
+    Guards.clear();
+    ObjFile.reset();
+    Obj.reset();
+    return false;
+  }
+
+  LiveAOTVals = nullptr;
+  if (!AOTVals.empty()) {
+    AOTInfo *LiveAOTArray = static_cast<AOTInfo *>(
+        reallocarray(nullptr, AOTVals.size(), sizeof(AOTInfo)));
+    assert(LiveAOTArray != NULL);
+    std::copy(AOTVals.begin(), AOTVals.end(), LiveAOTArray);
+    LiveAOTVals = LiveAOTArray;
+  }
+  return true;
+}
+
+void TraceObjCache::setMeta(const std::vector<GuardInfo> &Guards,
+                            void *LiveAOTVals) {
+  Meta.clear();
+  raw_string_ostream OS(Meta);
+  for (const GuardInfo &GI : Guards) {
+    OS << "guard\n";
+    printGuard(OS, GI, static_cast<AOTInfo *>(LiveAOTVals));
+  }
+  OS.flush();
+}
+
+void TraceObjCache::notifyObjectCompiled(const Module *M,
+                                         MemoryBufferRef Obj) {
+  // A trace is only looked up if its object exists, so the object must be
+  // written after the guards and live AOT values it depends on.
+  writeFileAtomically(Path + ".meta", Meta);
+  writeFileAtomically(Path + ".o", Obj.getBuffer());
+}
+
+std::unique_ptr<MemoryBuffer> TraceObjCache::getObject(const Module *M) {
+  // A cached object is loaded directly (see `takeObject`), so the trace's
+  // module is only ever compiled when its object isn't cached.
+  return nullptr;
+}
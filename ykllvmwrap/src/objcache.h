@@ -0,0 +1,77 @@
+#ifndef __OBJCACHE_H
+#define __OBJCACHE_H
+
+#include "llvm/ExecutionEngine/ObjectCache.h"
+#include "llvm/IR/Module.h"
+#include "llvm/Object/ObjectFile.h"
+#include "llvm/Support/MemoryBuffer.h"
+#include <string>
+#include <vector>
+
+#include "jitmodbuilder.h"
+
+using namespace llvm;
+
+// An on-disk cache of compiled traces, so that a trace which was compiled by
+// an earlier process needn't be built into a JIT module, optimised, and
+// compiled again.
+//
+// A trace is cached under a key derived from what the trace compiler is given
+// (see `traceCacheKey`), so that a cached trace can be found before its JIT
+// module is built. Alongside its object, the cache stores the trace's guards
+// and live AOT values, which would otherwise come from building the module.
+// The object refers to functions, globals, and the root `Location` through
+// global mappings, which are resolved by name when the object is loaded, so
+// that it can be used by any process running the same binary. Objects from
+// other binaries or other versions of yk are kept apart by the caller, which
+// uses a different directory for each.
+class TraceObjCache : public ObjectCache {
+  // The path of the trace's cached files, less their extensions.
+  std::string Path;
+  // The name of the trace function in the cached object.
+  std::string TraceName;
+  // The cached object, if there is one, and its contents.
+  std::unique_ptr<MemoryBuffer> Obj;
+  std::unique_ptr<object::ObjectFile> ObjFile;
+  // The serialised guards and live AOT values of the trace being compiled.
+  std::string Meta;
+
+public:
+  // Look up the trace with the key `Key` in the cache directory `Dir`.
+  TraceObjCache(const char *Dir, const std::string &Key);
+
+  // Does the cache contain the trace's object?
+  bool hasObject() { return ObjFile != nullptr; }
+
+  // The name of the trace function, which is the same in every process.
+  const std::string &getTraceName() { return TraceName; }
+
+  // Read the guards and live AOT values of the cached trace into `Guards`
+  // and `LiveAOTVals`, resolving function names in `AOTMod`. Returns false,
+  // and discards the cached object, if they can't be read.
+  bool readMeta(Module *AOTMod, std::vector<GuardInfo> &Guards,
+                void *&LiveAOTVals);
+
+  // Remember the guards and live AOT values of the trace being compiled, so
+  // that they can be cached along with its object.
+  void setMeta(const std::vector<GuardInfo> &Guards, void *LiveAOTVals);
+
+  // Take the cached object.
+  object::OwningBinary<object::ObjectFile> takeObject() {
+    return object::OwningBinary<object::ObjectFile>(std::move(ObjFile),
+                                                    std::move(Obj));
+  }
+
+  void notifyObjectCompiled(const Module *M, MemoryBufferRef Obj) override;
+  std::unique_ptr<MemoryBuffer> getObject(const Module *M) override;
+};
+
+// Return the cache key of the trace made of the blocks `(FuncNames[I],
+// BBs[I])` with the values `Promotions` passed to `yk_promote`. A side trace
+// also depends on the guard `Guard` it starts at and the live AOT values
+// `ParentLiveAOTVals` of its parent trace; both are null for a root trace.
+std::string traceCacheKey(char *FuncNames[], size_t BBs[], size_t TraceLen,
+                          uintptr_t Promotions[], size_t PromotionsLen,
+                          GuardInfo *Guard, void *ParentLiveAOTVals);
+
+#endif
@@ -10,6 +10,7 @@
 #include "llvm/IR/AssemblyAnnotationWriter.h"
 #include "llvm/IR/DIBuilder.h"
 #include "llvm/IR/DebugInfo.h"
+#include "llvm/IR/Instructions.h"
 #include "llvm/IR/LLVMContext.h"
 #include "llvm/IR/LegacyPassManager.h"
 #include "llvm/IR/Module.h"
@@ -25,14 +26,18 @@
 #include <dlfcn.h>
 #include <err.h>
 #include <filesystem>
+#include <inttypes.h>
 #include <link.h>
+#include <mutex>
 #include <optional>
+#include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include <unistd.h>
 
 #include "jitmodbuilder.h"
 #include "memman.h"
+#include "objcache.h"
 
 // When we create a compilation unit for our JIT debug info, LLVM forces us to
 // choose a language from one of those "recognised" by the DWARF spec (see
@@ -140,6 +145,8 @@ void dumpValueToString(Value *V, string &S) {
 }
 #endif
 
+// The stages at which IR can be printed. Each stage's value is the index of its
+// bit in the `PrintIR` argument of `compileIRTrace`.
 enum DebugIR {
   AOT,
   JITPreOpt,
@@ -164,20 +171,7 @@ private:
   }
 
 public:
-  DebugIRPrinter() {
-    char *Env = std::getenv("YKD_PRINT_IR");
-    char *Val;
-    while ((Val = strsep(&Env, ",")) != nullptr) {
-      if (strcmp(Val, "aot") == 0)
-        toPrint.set(DebugIR::AOT);
-      else if (strcmp(Val, "jit-pre-opt") == 0)
-        toPrint.set(DebugIR::JITPreOpt);
-      else if (strcmp(Val, "jit-post-opt") == 0)
-        toPrint.set(DebugIR::JITPostOpt);
-      else
-        errx(EXIT_FAILURE, "invalid parameter for YKD_PRINT_IR: '%s'", Val);
-    }
-  }
+  DebugIRPrinter(unsigned PrintIR) : toPrint(PrintIR) {}
 
   void print(enum DebugIR IR, Module *M) {
     if (toPrint[IR]) {
@@ -233,53 +227,244 @@ LLVMGetThreadSafeModule(struct BitcodeSection &Bitcode) {
   return llvm::wrap(AOTMod);
 }
 
-// Compile a module in-memory and return a pointer to its function.
-extern "C" void *compileModule(string TraceName, Module *M,
-                               map<GlobalValue *, void *> GlobalMappings,
-                               void *LiveAOTVals) {
-  std::call_once(LLVMInitialised, initLLVM, nullptr);
+// Find the block with index `BBIdx` of the function `FuncName` in the AOT
+// module, returning null if there is no such block.
+BasicBlock *getAOTBlock(struct BitcodeSection &Bitcode, const char *FuncName,
+                        size_t BBIdx) {
+  Module *AOTMod = getThreadAOTMod(Bitcode)->getModuleUnlocked();
+  Function *F = AOTMod->getFunction(FuncName);
+  if (F == nullptr || BBIdx >= F->size())
+    return nullptr;
+  auto It = F->begin();
+  std::advance(It, BBIdx);
+  return &*It;
+}
 
-  // Use our own memory manager to keep track of stackmap address.
-  AllocMem SMR;
-  MemMan *memman = new MemMan();
-  memman->setStackMapStore(&SMR);
+// Might the AOT IR block with index `BBIdx` of the function `FuncName` call
+// the function `CalleeName` directly (i.e. other than via foreign code)? We
+// can't know where an indirect call goes, so a block containing one might
+// call any function.
+extern "C" bool __ykllvmwrap_block_may_call(char *FuncName, size_t BBIdx,
+                                            char *CalleeName,
+                                            void *BitcodeData,
+                                            uint64_t BitcodeLen) {
+  struct BitcodeSection Bitcode = {BitcodeData, BitcodeLen};
+  BasicBlock *BB = getAOTBlock(Bitcode, FuncName, BBIdx);
+  if (BB == nullptr)
+    return true;
+  for (Instruction &I : *BB) {
+    CallBase *CB = dyn_cast<CallBase>(&I);
+    if (CB == nullptr || CB->isInlineAsm())
+      continue;
+    Function *CF =
+        dyn_cast<Function>(CB->getCalledOperand()->stripPointerCasts());
+    if (CF == nullptr || CF->getName() == CalleeName)
+      return true;
+  }
+  return false;
+}
+
+// Does the AOT IR block with index `BBIdx` of the function `FuncName` return
+// from the function?
+extern "C" bool __ykllvmwrap_block_returns(char *FuncName, size_t BBIdx,
+                                           void *BitcodeData,
+                                           uint64_t BitcodeLen) {
+  struct BitcodeSection Bitcode = {BitcodeData, BitcodeLen};
+  BasicBlock *BB = getAOTBlock(Bitcode, FuncName, BBIdx);
+  return BB != nullptr && isa<ReturnInst>(BB->getTerminator());
+}
+
+// Serialises writes to the perf map (see `writePerfMap`).
+mutex PerfMapLock;
 
+// Append an entry for each code section of the compiled trace `TraceName` to
+// `/tmp/perf-<pid>.map`, so that `perf report` can attribute samples in the
+// trace's machine code to it. `Origin` describes where the trace came from, and
+// is shown after the trace's name.
+//
+// The perf map format can't express that code has been freed, so the entries
+// of a trace freed by `__ykllvmwrap_free_compiled_trace` stay in the map. If a
+// later trace's code is put at the same address, `perf report` may attribute
+// its samples to the freed trace.
+void writePerfMap(MemMan *MM, string &TraceName, string &Origin) {
+  lock_guard<mutex> Lock(PerfMapLock);
+  static FILE *PerfMap = nullptr;
+  if (PerfMap == nullptr) {
+    string Path = "/tmp/perf-" + to_string(getpid()) + ".map";
+    PerfMap = fopen(Path.c_str(), "a");
+    if (PerfMap == nullptr)
+      err(EXIT_FAILURE, "Can't open %s", Path.c_str());
+  }
+  for (const AllocMem &Code : MM->getCodeExtents())
+    fprintf(PerfMap, "%" PRIxPTR " %" PRIxPTR " %s (%s)\n",
+            reinterpret_cast<uintptr_t>(Code.Ptr), Code.Size,
+            TraceName.c_str(), Origin.c_str());
+  // `perf` reads the map after the process has exited, possibly abnormally,
+  // so we don't leave entries sitting in a buffer.
+  fflush(PerfMap);
+}
+
+// Describe a trace which starts in the function `FuncNames[0]` and was
+// compiled (as a trace of kind `Kind`) for the `Location` at address `Loc`,
+// for `writePerfMap`.
+string traceOrigin(const char *Kind, void *Loc, char *FuncNames[],
+                   size_t TraceLen) {
+  string Origin = string(Kind) + " trace";
+  if (Loc != nullptr) {
+    char Buf[32];
+    snprintf(Buf, sizeof(Buf), "%p", Loc);
+    Origin += string(" of Location ") + Buf;
+  }
+  if (TraceLen > 0 && FuncNames[0] != nullptr)
+    Origin += string(" in ") + FuncNames[0];
+  return Origin;
+}
+
+// What ykllvmwrap keeps hold of for each compiled trace, until the trace is
+// freed with `__ykllvmwrap_free_compiled_trace`.
+struct TraceInfo {
+  // The execution engine owning the trace's machine code, data, and stackmap.
+  ExecutionEngine *EE;
+  // Information about the trace's guards, indexed by guard ID.
+  vector<GuardInfo> Guards;
+
+  ~TraceInfo() {
+    // Deleting the execution engine deletes our `MemMan`, which unmaps the
+    // memory it allocated.
+    delete EE;
+  }
+};
+
+// Create an execution engine for the module `M`, using our own memory manager
+// `MM` to keep track of the stackmap address.
+static ExecutionEngine *createEngine(Module *M, MemMan *MM) {
   auto MPtr = std::unique_ptr<Module>(M);
   string ErrStr;
   ExecutionEngine *EE =
       EngineBuilder(std::move(MPtr))
           .setEngineKind(EngineKind::JIT)
-          .setMemoryManager(std::unique_ptr<MCJITMemoryManager>(memman))
+          .setMemoryManager(std::unique_ptr<MCJITMemoryManager>(MM))
           .setErrorStr(&ErrStr)
           .create();
 
   if (EE == nullptr)
     errx(EXIT_FAILURE, "Couldn't compile trace: %s", ErrStr.c_str());
+  return EE;
+}
 
-  for (auto GM : GlobalMappings) {
-    // If a value now has no parent, then it was optimised out and LLVM will be
-    // unhappy if we try to regster a global mapping for it.
-    if (GM.first->getParent() != nullptr)
-      EE->addGlobalMapping(GM.first, GM.second);
-  }
-
+// Finalise the trace function `TraceName` in `EE`, whose memory manager is
+// `MM` and whose stackmap is recorded in `SMR`, and return what yktrace needs
+// to know about the trace. `EE`'s module, `M`, is freed. If `PerfMap` is true,
+// the trace is added to the perf map, described by `Origin` (see
+// `writePerfMap`).
+static void *finaliseTrace(ExecutionEngine *EE, Module *M, MemMan *MM,
+                           AllocMem &SMR, string &TraceName,
+                           void *LiveAOTVals, vector<GuardInfo> Guards,
+                           bool PerfMap, string &Origin) {
   EE->finalizeObject();
   if (EE->hasError())
     errx(EXIT_FAILURE, "Couldn't compile trace: %s",
          EE->getErrorMessage().c_str());
-
-  // Allocate space for compiled trace address, stackmap address, and stackmap
-  // size.
+  // A cache, if there is one, doesn't outlive the trace's compilation.
+  EE->setObjectCache(nullptr);
+  if (PerfMap)
+    writePerfMap(MM, TraceName, Origin);
+
+  // Allocate space for compiled trace address, stackmap address, stackmap
+  // size, the live AOT values, the trace's `TraceInfo`, and the number of
+  // guards in the trace.
   // FIXME This is a temporary hack until the redesigned hot location is up.
-  uintptr_t *ptr = (uintptr_t *)malloc(sizeof(uintptr_t) * 4);
+  size_t NumGuards = Guards.size();
+  TraceInfo *TI = new TraceInfo{EE, std::move(Guards)};
+  uintptr_t *ptr = (uintptr_t *)malloc(sizeof(uintptr_t) * 6);
   ptr[0] = EE->getFunctionAddress(TraceName);
   ptr[1] = reinterpret_cast<uintptr_t>(SMR.Ptr);
   ptr[2] = SMR.Size;
   ptr[3] = reinterpret_cast<uintptr_t>(LiveAOTVals);
+  ptr[4] = reinterpret_cast<uintptr_t>(TI);
+  ptr[5] = NumGuards;
+
+  // The execution engine will be freed by whichever thread drops the last
+  // reference to the compiled trace, but the module lives in this thread's
+  // `LLVMContext`, which must not be touched by other threads. Now that code
+  // has been generated we no longer need the module, so we take it back from
+  // the execution engine and free it here.
+  EE->removeModule(M);
+  delete M;
 
   return ptr;
 }
 
+// Compile a module in-memory and return a pointer to its function. If `Cache`
+// is not null, the module's object is added to the cache. `PerfMap` and
+// `Origin` are as for `finaliseTrace`.
+extern "C" void *compileModule(string TraceName, Module *M,
+                               map<GlobalValue *, void *> GlobalMappings,
+                               void *LiveAOTVals, vector<GuardInfo> Guards,
+                               ObjectCache *Cache, bool PerfMap,
+                               string Origin) {
+  std::call_once(LLVMInitialised, initLLVM, nullptr);
+
+  AllocMem SMR;
+  MemMan *memman = new MemMan();
+  memman->setStackMapStore(&SMR);
+  ExecutionEngine *EE = createEngine(M, memman);
+
+  if (Cache != nullptr)
+    EE->setObjectCache(Cache);
+
+  for (auto GM : GlobalMappings) {
+    // If a value now has no parent, then it was optimised out and LLVM will be
+    // unhappy if we try to regster a global mapping for it.
+    if (GM.first->getParent() != nullptr)
+      EE->addGlobalMapping(GM.first, GM.second);
+  }
+
+  return finaliseTrace(EE, M, memman, SMR, TraceName, LiveAOTVals,
+                       std::move(Guards), PerfMap, Origin);
+}
+
+// Load the trace in `Cache`, whose guards and live AOT values are `Guards` and
+// `LiveAOTVals`, without building its JIT module. The trace's global mappings
+// are resolved by name: functions through the `FAddrKeys` and `FAddrVals`
+// arrays of length `FAddrLen`, and the root `Location` to `RootLoc`. Other
+// symbols are resolved by the memory manager, as for a compiled trace.
+// `PerfMap` and `Origin` are as for `finaliseTrace`.
+static void *loadCachedTrace(TraceObjCache &Cache, LLVMContext &Ctx,
+                             char *FAddrKeys[], void *FAddrVals[],
+                             size_t FAddrLen, void *RootLoc, void *LiveAOTVals,
+                             vector<GuardInfo> Guards, bool PerfMap,
+                             string Origin) {
+  std::call_once(LLVMInitialised, initLLVM, nullptr);
+
+  AllocMem SMR;
+  MemMan *memman = new MemMan();
+  memman->setStackMapStore(&SMR);
+  // An execution engine must be created for a module, but the cached trace
+  // needs nothing from it.
+  Module *M = new Module("cached_trace", Ctx);
+  ExecutionEngine *EE = createEngine(M, memman);
+
+  for (size_t I = 0; I < FAddrLen; I++)
+    EE->addGlobalMapping(FAddrKeys[I],
+                         reinterpret_cast<uintptr_t>(FAddrVals[I]));
+  if (RootLoc != nullptr)
+    EE->addGlobalMapping(YK_ROOT_LOC, reinterpret_cast<uintptr_t>(RootLoc));
+  EE->addObjectFile(Cache.takeObject());
+
+  string TraceName = Cache.getTraceName();
+  return finaliseTrace(EE, M, memman, SMR, TraceName, LiveAOTVals,
+                       std::move(Guards), PerfMap, Origin);
+}
+
+// Free the machine code, data, stackmaps, and guard information of a compiled
+// trace. The caller must guarantee that nothing is still executing, or will
+// execute, the trace. The trace's perf map entries, if any, are not removed
+// (see `writePerfMap`).
+extern "C" void __ykllvmwrap_free_compiled_trace(void *Info) {
+  delete static_cast<TraceInfo *>(Info);
+}
+
 /// Write the string `S` in its entirety to the file descriptor `FD`.
 void writeString(int FD, string S) {
   const char *Buf = S.c_str();
@@ -383,13 +568,25 @@ void rewriteDebugInfo(Module *M, string TraceName, int FD,
 // (FuncName[I], BBs[I]) pair identifies the LLVM block at position `I` in the
 // trace.
 //
+// `PrintIR` has a bit set for each stage (see `DebugIR`) at which to print the
+// IR. If `TraceCacheDir` is not null, it names the directory of the compiled
+// trace cache (see `TraceObjCache`), in which the trace is cached under
+// `CacheKey` (see `traceCacheKey`). If the trace is already cached, it is
+// loaded without building its JIT module, with `RootLoc` as the address of
+// its root trace's `Location`. A trace's debugging information refers to a
+// file which only exists for the life of the process, so a trace with
+// debugging information is never cached. `PerfMap` and `Origin` are as for
+// `compileModule`.
+//
 // Returns a pointer to the compiled function.
 template <typename FN>
 void *compileIRTrace(FN Func, char *FuncNames[], size_t BBs[], size_t TraceLen,
                      char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen,
                      void *BitcodeData, size_t BitcodeLen, int DebugInfoFD,
-                     char *DebugInfoPath) {
-  DebugIRPrinter DIP;
+                     char *DebugInfoPath, unsigned PrintIR, bool PerfMap,
+                     const char *TraceCacheDir, const string &CacheKey,
+                     void *RootLoc, string Origin) {
+  DebugIRPrinter DIP(PrintIR);
 
   struct BitcodeSection Bitcode = {BitcodeData, BitcodeLen};
   ThreadSafeModule *ThreadAOTMod = getThreadAOTMod(Bitcode);
@@ -399,11 +596,23 @@ void *compileIRTrace(FN Func, char *FuncNames[], size_t BBs[], size_t TraceLen,
 
   DIP.print(DebugIR::AOT, AOTMod);
 
+  std::unique_ptr<TraceObjCache> Cache;
+  if (TraceCacheDir != nullptr && DebugInfoFD == -1) {
+    Cache = std::make_unique<TraceObjCache>(TraceCacheDir, CacheKey);
+    std::vector<GuardInfo> Guards;
+    void *LiveAOTVals;
+    if (Cache->hasObject() && Cache->readMeta(AOTMod, Guards, LiveAOTVals))
+      return loadCachedTrace(*Cache, AOTMod->getContext(), FAddrKeys,
+                             FAddrVals, FAddrLen, RootLoc, LiveAOTVals,
+                             std::move(Guards), PerfMap, Origin);
+  }
+
   Module *JITMod;
   std::string TraceName;
   std::map<GlobalValue *, void *> GlobalMappings;
   void *AOTMappingVec;
-  std::tie(JITMod, TraceName, GlobalMappings, AOTMappingVec) =
+  std::vector<GuardInfo> Guards;
+  std::tie(JITMod, TraceName, GlobalMappings, AOTMappingVec, Guards) =
       Func(AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals, FAddrLen);
 
   // If we failed to build the trace, return null.
@@ -416,6 +625,14 @@ void *compileIRTrace(FN Func, char *FuncNames[], size_t BBs[], size_t TraceLen,
   llvm::verifyModule(*JITMod, &llvm::errs());
 #endif
 
+  // A cached trace's function has the same name in every process, so that
+  // the cached object can be loaded by any of them.
+  if (Cache != nullptr) {
+    JITMod->getFunction(TraceName)->setName(Cache->getTraceName());
+    TraceName = Cache->getTraceName();
+    Cache->setMeta(Guards, AOTMappingVec);
+  }
+
   // The MCJIT code-gen does no optimisations itself, so we must do it
   // ourselves.
   PassManagerBuilder Builder;
@@ -434,25 +651,100 @@ void *compileIRTrace(FN Func, char *FuncNames[], size_t BBs[], size_t TraceLen,
                      filesystem::path(DebugInfoPath));
 
   // Compile IR trace and return a pointer to its function.
-  return compileModule(TraceName, JITMod, GlobalMappings, AOTMappingVec);
+  return compileModule(TraceName, JITMod, GlobalMappings, AOTMappingVec,
+                       std::move(Guards), Cache.get(), PerfMap, Origin);
 }
 
+// Compile an IRTrace into a root trace for the `Location` at address `Loc`.
+//
+// `Promotions` is an array of length `PromotionsLen` containing the values
+// passed to `yk_promote` while the trace was collected. `PrintIR`, `PerfMap`,
+// and `TraceCacheDir` are as for `compileIRTrace`.
 extern "C" void *__ykllvmwrap_irtrace_compile(
+    void *Loc, char *FuncNames[], size_t BBs[], size_t TraceLen,
+    char *FAddrKeys[], void *FAddrVals[], size_t FAddrLen,
+    uintptr_t Promotions[], size_t PromotionsLen, void *BitcodeData,
+    uint64_t BitcodeLen, int DebugInfoFD, char *DebugInfoPath, unsigned PrintIR,
+    bool PerfMap, const char *TraceCacheDir) {
+  auto Create = [&](Module *AOTMod, char *FuncNames[], size_t BBs[],
+                    size_t TraceLen, char *FAddrKeys[], void *FAddrVals[],
+                    size_t FAddrLen) {
+    return createModule(AOTMod, FuncNames, BBs, TraceLen, FAddrKeys, FAddrVals,
+                        FAddrLen, Promotions, PromotionsLen, Loc);
+  };
+  string CacheKey;
+  if (TraceCacheDir != nullptr)
+    CacheKey = traceCacheKey(FuncNames, BBs, TraceLen, Promotions,
+                             PromotionsLen, nullptr, nullptr);
+  return compileIRTrace(Create, FuncNames, BBs, TraceLen, FAddrKeys,
+                        FAddrVals, FAddrLen, BitcodeData, BitcodeLen,
+                        DebugInfoFD, DebugInfoPath, PrintIR, PerfMap,
+                        TraceCacheDir, CacheKey, Loc,
+                        traceOrigin("root", Loc, FuncNames, TraceLen));
+}
+
+// Compile an IRTrace into a side trace for the guard with ID `GuardIdx` in the
+// compiled trace described by `ParentInfo`, whose live AOT values are
+// `ParentLiveAOTVals`. `RootLoc` is the address of the `Location` for which
+// the root trace of the side trace was compiled. `Promotions`, `PrintIR`,
+// `PerfMap`, and `TraceCacheDir` are as for `__ykllvmwrap_irtrace_compile`.
+extern "C" void *__ykllvmwrap_irtrace_compile_side(
+    void *ParentInfo, size_t GuardIdx, void *ParentLiveAOTVals, void *RootLoc,
     char *FuncNames[], size_t BBs[], size_t TraceLen, char *FAddrKeys[],
-    void *FAddrVals[], size_t FAddrLen, void *BitcodeData, uint64_t BitcodeLen,
-    int DebugInfoFD, char *DebugInfoPath) {
-  return compileIRTrace(createModule, FuncNames, BBs, TraceLen, FAddrKeys,
+    void *FAddrVals[], size_t FAddrLen, uintptr_t Promotions[],
+    size_t PromotionsLen, void *BitcodeData, uint64_t BitcodeLen,
+    int DebugInfoFD, char *DebugInfoPath, unsigned PrintIR, bool PerfMap,
+    const char *TraceCacheDir) {
+  GuardInfo &Guard = static_cast<TraceInfo *>(ParentInfo)->Guards.at(GuardIdx);
+  auto CreateSide = [&](Module *AOTMod, char *FuncNames[], size_t BBs[],
+                        size_t TraceLen, char *FAddrKeys[], void *FAddrVals[],
+                        size_t FAddrLen) {
+    return createSideModule(AOTMod, FuncNames, BBs, TraceLen, FAddrKeys,
+                            FAddrVals, FAddrLen, Promotions, PromotionsLen,
+                            Guard, ParentLiveAOTVals, RootLoc);
+  };
+  string CacheKey;
+  if (TraceCacheDir != nullptr)
+    CacheKey = traceCacheKey(FuncNames, BBs, TraceLen, Promotions,
+                             PromotionsLen, &Guard, ParentLiveAOTVals);
+  return compileIRTrace(CreateSide, FuncNames, BBs, TraceLen, FAddrKeys,
                         FAddrVals, FAddrLen, BitcodeData, BitcodeLen,
-                        DebugInfoFD, DebugInfoPath);
+                        DebugInfoFD, DebugInfoPath, PrintIR, PerfMap,
+                        TraceCacheDir, CacheKey, RootLoc,
+                        traceOrigin("side", RootLoc, FuncNames, TraceLen));
 }
 
 #ifdef YK_TESTING
+// The traces compiled by the following functions are never executed, so they
+// aren't added to the perf map.
+
 extern "C" void *__ykllvmwrap_irtrace_compile_for_tc_tests(
     char *FuncNames[], size_t BBs[], size_t TraceLen, char *FAddrKeys[],
     void *FAddrVals[], size_t FAddrLen, void *BitcodeData, uint64_t BitcodeLen,
-    int DebugInfoFD, char *DebugInfoPath) {
+    int DebugInfoFD, char *DebugInfoPath, unsigned PrintIR) {
   return compileIRTrace(createModuleForTraceCompilerTests, FuncNames, BBs,
                         TraceLen, FAddrKeys, FAddrVals, FAddrLen, BitcodeData,
-                        BitcodeLen, DebugInfoFD, DebugInfoPath);
+                        BitcodeLen, DebugInfoFD, DebugInfoPath, PrintIR,
+                        false, nullptr, "", nullptr,
+                        traceOrigin("test", nullptr, FuncNames, TraceLen));
+}
+
+// Compile an IRTrace which was collected by another process (from the same
+// binary) into a root trace. The compiled trace can't be executed. The other
+// process's function addresses aren't valid in this one, so none are passed.
+extern "C" void *__ykllvmwrap_irtrace_compile_for_replay(
+    char *FuncNames[], size_t BBs[], size_t TraceLen, uintptr_t Promotions[],
+    size_t PromotionsLen, void *BitcodeData, uint64_t BitcodeLen,
+    int DebugInfoFD, char *DebugInfoPath, unsigned PrintIR) {
+  auto Create = [&](Module *AOTMod, char *FuncNames[], size_t BBs[],
+                    size_t TraceLen, char *FAddrKeys[], void *FAddrVals[],
+                    size_t FAddrLen) {
+    return createModuleForReplay(AOTMod, FuncNames, BBs, TraceLen, Promotions,
+                                 PromotionsLen);
+  };
+  return compileIRTrace(Create, FuncNames, BBs, TraceLen, nullptr, nullptr, 0,
+                        BitcodeData, BitcodeLen, DebugInfoFD, DebugInfoPath,
+                        PrintIR, false, nullptr, "", nullptr,
+                        traceOrigin("replayed", nullptr, FuncNames, TraceLen));
 }
 #endif
@@ -0,0 +1,421 @@
+//! Configuring, and creating, a meta-tracer.
+//!
+//! Options can be set programmatically, from a configuration file, or from `YK_`-prefixed
+//! environment variables whose names are the upper-case option name (e.g. `YK_HOT_THRESHOLD`).
+//! Options given as strings are parsed in one place ([MTBuilder::set]), and all options are
+//! validated in one place ([MTBuilder::build]), no matter where they came from. Options which can
+//! also be changed once a meta-tracer has been created are checked by the same functions (e.g.
+//! [check_max_worker_threads]) when they are changed, so that they accept the same values.
+
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use hwtracer::decode::TraceDecoderKind;
+use yktrace::{CompileOptions, PrintIR, TracingBackend, TracingKind};
+
+use crate::mt::{GuardFailureThreshold, HotThreshold, TraceFailureThreshold, MT};
+
+const DEFAULT_HOT_THRESHOLD: HotThreshold = 50;
+const DEFAULT_TRACE_FAILURE_THRESHOLD: TraceFailureThreshold = 5;
+const DEFAULT_GUARD_FAILURE_THRESHOLD: GuardFailureThreshold = 100;
+const DEFAULT_SIDE_TRACE_THRESHOLD: HotThreshold = 10;
+const DEFAULT_MAX_TRACE_LENGTH: usize = 10000;
+
+/// The environment variable naming a configuration file to be read by [MTBuilder::from_env].
+const CONFIG_FILE_ENV_VAR: &str = "YK_CONFIG";
+
+/// The options understood by [MTBuilder::set].
+const OPTIONS: [&str; 13] = [
+    "hot_threshold",
+    "trace_failure_threshold",
+    "guard_failure_threshold",
+    "side_trace_threshold",
+    "max_trace_length",
+    "max_worker_threads",
+    "serialise_compilation",
+    "tracing_kind",
+    "trace_decoder",
+    "trace_cache",
+    "print_ir",
+    "trace_debuginfo",
+    "perf_map",
+];
+
+/// Configures, and then creates, a meta-tracer. Options which are not explicitly set take their
+/// default values.
+#[derive(Clone, Debug)]
+pub struct MTBuilder {
+    pub(crate) hot_threshold: HotThreshold,
+    pub(crate) trace_failure_threshold: TraceFailureThreshold,
+    pub(crate) guard_failure_threshold: GuardFailureThreshold,
+    pub(crate) side_trace_threshold: HotThreshold,
+    pub(crate) max_trace_length: usize,
+    pub(crate) max_worker_threads: usize,
+    pub(crate) serialise_compilation: bool,
+    /// The kind of tracing to use. If `None`, the most preferable kind for the platform is used.
+    tracing_kind: Option<TracingKind>,
+    /// The decoder to use for hardware traces. If `None`, the most preferable decoder for the
+    /// platform is used.
+    trace_decoder: Option<TraceDecoderKind>,
+    /// The directory in which to cache compiled traces. If `None`, compiled traces aren't cached.
+    pub(crate) trace_cache: Option<PathBuf>,
+    /// The stages of the JIT pipeline at which to print LLVM IR.
+    print_ir: PrintIR,
+    /// Whether to add debugging information to compiled traces.
+    trace_debuginfo: bool,
+    /// Whether to add compiled traces to the perf map.
+    perf_map: bool,
+}
+
+impl MTBuilder {
+    /// Create a builder with every option set to its default value.
+    pub fn new() -> Self {
+        Self {
+            hot_threshold: DEFAULT_HOT_THRESHOLD,
+            trace_failure_threshold: DEFAULT_TRACE_FAILURE_THRESHOLD,
+            guard_failure_threshold: DEFAULT_GUARD_FAILURE_THRESHOLD,
+            side_trace_threshold: DEFAULT_SIDE_TRACE_THRESHOLD,
+            max_trace_length: DEFAULT_MAX_TRACE_LENGTH,
+            max_worker_threads: num_cpus::get().saturating_sub(1).max(1),
+            serialise_compilation: false,
+            tracing_kind: None,
+            trace_decoder: None,
+            trace_cache: None,
+            print_ir: PrintIR::default(),
+            trace_debuginfo: false,
+            perf_map: false,
+        }
+    }
+
+    /// Create a builder configured from the environment: first from the configuration file named
+    /// by `YK_CONFIG` (if it is set), and then from the `YK_<OPTION>` environment variables (e.g.
+    /// `YK_HOT_THRESHOLD`), which take precedence over the configuration file.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let mut b = Self::new();
+        if let Some(p) = env::var_os(CONFIG_FILE_ENV_VAR) {
+            b = b.config_file(p)?;
+        }
+        for opt in OPTIONS {
+            let var = format!("YK_{}", opt.to_uppercase());
+            if let Ok(val) = env::var(&var) {
+                b = b
+                    .set(opt, &val)
+                    .map_err(|e| format!("Invalid value for {}: {}", var, e))?;
+            }
+        }
+        Ok(b)
+    }
+
+    /// Set the options in the configuration file `path`. See [MTBuilder::config] for the file's
+    /// format.
+    pub fn config_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path)
+            .map_err(|e| format!("Can't read configuration file {:?}: {}", path, e))?;
+        self.config(&config)
+            .map_err(|e| format!("In configuration file {:?}: {}", path, e).into())
+    }
+
+    /// Set the options in `config`, which consists of lines of the form `<option> = <value>`.
+    /// Blank lines, and lines starting with `#`, are ignored.
+    pub fn config(mut self, config: &str) -> Result<Self, Box<dyn Error>> {
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (opt, val) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected '<option> = <value>'", i + 1))?;
+            self = self
+                .set(opt.trim(), val.trim())
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(self)
+    }
+
+    /// Set the option `opt` to the value `val`, returning an error if `opt` is not a known option
+    /// or `val` can't be parsed as a value for it. Values which parse correctly but are out of
+    /// range are only rejected by [MTBuilder::build].
+    pub fn set(self, opt: &str, val: &str) -> Result<Self, Box<dyn Error>> {
+        match opt {
+            "hot_threshold" => Ok(self.hot_threshold(parse(opt, val)?)),
+            "trace_failure_threshold" => Ok(self.trace_failure_threshold(parse(opt, val)?)),
+            "guard_failure_threshold" => Ok(self.guard_failure_threshold(parse(opt, val)?)),
+            "side_trace_threshold" => Ok(self.side_trace_threshold(parse(opt, val)?)),
+            "max_trace_length" => Ok(self.max_trace_length(parse(opt, val)?)),
+            "max_worker_threads" => Ok(self.max_worker_threads(parse(opt, val)?)),
+            "serialise_compilation" => Ok(self.serialise_compilation(parse_bool(opt, val)?)),
+            "tracing_kind" => match val {
+                "software" => Ok(self.tracing_kind(TracingKind::SoftwareTracing)),
+                "hardware" => Ok(self.tracing_kind(TracingKind::HardwareTracing)),
+                _ => Err(format!(
+                    "tracing_kind must be 'software' or 'hardware', not '{}'",
+                    val
+                )
+                .into()),
+            },
+            "trace_decoder" => match val {
+                "libipt" => Ok(self.trace_decoder(TraceDecoderKind::LibIPT)),
+                "ykpt" => Ok(self.trace_decoder(TraceDecoderKind::YkPT)),
+                _ => Err(format!("trace_decoder must be 'libipt' or 'ykpt', not '{}'", val).into()),
+            },
+            "trace_cache" => Ok(self.trace_cache(val)),
+            "print_ir" => Ok(self.print_ir(
+                val.parse()
+                    .map_err(|e| format!("invalid print_ir stage: {}", e))?,
+            )),
+            "trace_debuginfo" => Ok(self.trace_debuginfo(parse_bool(opt, val)?)),
+            "perf_map" => Ok(self.perf_map(parse_bool(opt, val)?)),
+            _ => Err(format!("Unknown option '{}'", opt).into()),
+        }
+    }
+
+    /// Set the threshold at which `Location`'s are considered hot.
+    pub fn hot_threshold(mut self, hot_threshold: HotThreshold) -> Self {
+        self.hot_threshold = hot_threshold;
+        self
+    }
+
+    /// Set the threshold at which a `Location` from which tracing has failed multiple times is
+    /// marked as "do not try tracing again". This must be at least 1.
+    pub fn trace_failure_threshold(
+        mut self,
+        trace_failure_threshold: TraceFailureThreshold,
+    ) -> Self {
+        self.trace_failure_threshold = trace_failure_threshold;
+        self
+    }
+
+    /// Set the number of guard failures after which a compiled trace which fails guards in most
+    /// of its executions is discarded, and its `Location` reprofiled.
+    pub fn guard_failure_threshold(
+        mut self,
+        guard_failure_threshold: GuardFailureThreshold,
+    ) -> Self {
+        self.guard_failure_threshold = guard_failure_threshold;
+        self
+    }
+
+    /// Set the number of times a guard in a compiled trace must fail before a side trace is
+    /// compiled for it.
+    pub fn side_trace_threshold(mut self, side_trace_threshold: HotThreshold) -> Self {
+        self.side_trace_threshold = side_trace_threshold;
+        self
+    }
+
+    /// Set the maximum number of control points that a thread may pass through while tracing. If
+    /// this is 0, every trace is abandoned.
+    pub fn max_trace_length(mut self, max_trace_length: usize) -> Self {
+        self.max_trace_length = max_trace_length;
+        self
+    }
+
+    /// Set the maximum number of worker threads compiling traces. This must be at least 1.
+    pub fn max_worker_threads(mut self, max_worker_threads: usize) -> Self {
+        self.max_worker_threads = max_worker_threads;
+        self
+    }
+
+    /// If `serialise_compilation` is true, compile traces on the thread which recorded them,
+    /// rather than on a worker thread. See [MT::set_serialise_compilation].
+    pub fn serialise_compilation(mut self, serialise_compilation: bool) -> Self {
+        self.serialise_compilation = serialise_compilation;
+        self
+    }
+
+    /// Use tracing of kind `kind`, rather than the most preferable kind for the platform. If
+    /// `kind` is not available on the platform, [MTBuilder::build] returns an error.
+    pub fn tracing_kind(mut self, kind: TracingKind) -> Self {
+        self.tracing_kind = Some(kind);
+        self
+    }
+
+    /// Use the decoder `decoder` for hardware traces, rather than the most preferable decoder for
+    /// the platform. If `decoder` is not available on the platform, or software tracing is
+    /// requested, [MTBuilder::build] returns an error.
+    pub fn trace_decoder(mut self, decoder: TraceDecoderKind) -> Self {
+        self.trace_decoder = Some(decoder);
+        self
+    }
+
+    /// Cache compiled traces in the directory `dir`, which is created if it doesn't exist, so that
+    /// a trace compiled by one run of the interpreter is loaded, rather than compiled again, by
+    /// later runs. Traces are cached separately for each binary and version of yk. A trace is
+    /// only looked up in the cache once it has been recorded and mapped, so later runs still trace
+    /// every hot Location: only compilation is avoided.
+    pub fn trace_cache<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.trace_cache = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Print the LLVM IR of each trace (and of the interpreter) to stderr at the JIT pipeline
+    /// stages selected by `print_ir`.
+    pub fn print_ir(mut self, print_ir: PrintIR) -> Self {
+        self.print_ir = print_ir;
+        self
+    }
+
+    /// If `trace_debuginfo` is true, add debugging information to compiled traces, so that
+    /// debuggers which support gdb's JIT interface can show each trace's IR as its source code.
+    /// Traces with debugging information are never cached.
+    pub fn trace_debuginfo(mut self, trace_debuginfo: bool) -> Self {
+        self.trace_debuginfo = trace_debuginfo;
+        self
+    }
+
+    /// If `perf_map` is true, add an entry for each compiled trace to `/tmp/perf-<pid>.map`, so
+    /// that `perf report` attributes samples in compiled traces to them. Entries aren't removed
+    /// when a trace is freed, so samples in a later trace which reuses the freed trace's memory
+    /// may be attributed to the freed trace.
+    pub fn perf_map(mut self, perf_map: bool) -> Self {
+        self.perf_map = perf_map;
+        self
+    }
+
+    /// Return the options with which this configuration compiles traces, excluding the trace
+    /// cache (which is only created by [MTBuilder::build]). This allows tools which compile traces
+    /// without a meta-tracer to be configured in the same way as a meta-tracer.
+    pub fn compile_options(&self) -> CompileOptions {
+        CompileOptions {
+            print_ir: self.print_ir,
+            trace_debuginfo: self.trace_debuginfo,
+            perf_map: self.perf_map,
+            trace_cache: None,
+        }
+    }
+
+    /// Check that every option has a valid value, and select the tracing backend that this
+    /// configuration requests.
+    pub(crate) fn validate(&self) -> Result<TracingBackend, Box<dyn Error>> {
+        check_trace_failure_threshold(self.trace_failure_threshold)?;
+        check_max_worker_threads(self.max_worker_threads)?;
+        if matches!(&self.trace_cache, Some(d) if d.as_os_str().is_empty()) {
+            return Err("trace_cache must not be empty".into());
+        }
+        match (self.tracing_kind, self.trace_decoder) {
+            (None, None) => Ok(TracingBackend::probe()),
+            (kind, decoder) => Ok(TracingBackend::new(
+                kind.unwrap_or(TracingKind::HardwareTracing),
+                decoder,
+            )?),
+        }
+    }
+
+    /// Create a meta-tracer with this configuration, returning an error if any option has an
+    /// invalid value or the requested tracing backend is not available.
+    pub fn build(self) -> Result<MT, Box<dyn Error>> {
+        MT::from_builder(self)
+    }
+}
+
+/// Check that `trace_failure_threshold` is a valid value for the trace failure threshold.
+pub(crate) fn check_trace_failure_threshold(
+    trace_failure_threshold: TraceFailureThreshold,
+) -> Result<(), Box<dyn Error>> {
+    if trace_failure_threshold < 1 {
+        return Err("trace_failure_threshold must be >= 1".into());
+    }
+    Ok(())
+}
+
+/// Check that `max_worker_threads` is a valid value for the maximum number of worker threads.
+pub(crate) fn check_max_worker_threads(max_worker_threads: usize) -> Result<(), Box<dyn Error>> {
+    if max_worker_threads < 1 {
+        return Err("max_worker_threads must be >= 1".into());
+    }
+    Ok(())
+}
+
+/// Parse `val` as the value of option `opt`.
+fn parse<T: std::str::FromStr>(opt: &str, val: &str) -> Result<T, Box<dyn Error>> {
+    val.parse()
+        .map_err(|_| format!("'{}' is not a valid value for {}", val, opt).into())
+}
+
+/// Parse `val` as the value of the boolean option `opt`.
+fn parse_bool(opt: &str, val: &str) -> Result<bool, Box<dyn Error>> {
+    match val {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(format!(
+            "{} must be '1', '0', 'true', or 'false', not '{}'",
+            opt, val
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::Location;
+
+    #[test]
+    fn config() {
+        let b = MTBuilder::new()
+            .config("# Comment\n\nhot_threshold = 7\n  max_trace_length=3  \n")
+            .unwrap();
+        assert_eq!(b.hot_threshold, 7);
+        assert_eq!(b.max_trace_length, 3);
+        assert_eq!(b.trace_failure_threshold, DEFAULT_TRACE_FAILURE_THRESHOLD);
+        // Later options override earlier ones.
+        let b = b.config("hot_threshold = 8").unwrap();
+        assert_eq!(b.hot_threshold, 8);
+    }
+
+    #[test]
+    fn invalid_config() {
+        for (config, err) in [
+            ("hot_threshold", "line 1: expected '<option> = <value>'"),
+            (
+                "\nnot_an_option = 1",
+                "line 2: Unknown option 'not_an_option'",
+            ),
+            (
+                "hot_threshold = x",
+                "line 1: 'x' is not a valid value for hot_threshold",
+            ),
+            (
+                "tracing_kind = both",
+                "line 1: tracing_kind must be 'software' or 'hardware', not 'both'",
+            ),
+            (
+                "print_ir = aot,jit",
+                "line 1: invalid print_ir stage: 'jit' is not one of 'aot', 'jit-pre-opt', or \
+                 'jit-post-opt'",
+            ),
+        ] {
+            assert_eq!(
+                MTBuilder::new().config(config).unwrap_err().to_string(),
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(MTBuilder::new().validate().is_ok());
+        // The meta-tracer's hot threshold isn't limited to the range of a Location's own hot
+        // threshold, and a maximum trace length of 0 abandons every trace.
+        assert!(MTBuilder::new()
+            .hot_threshold(Location::MAX_HOT_THRESHOLD + 1)
+            .max_trace_length(0)
+            .validate()
+            .is_ok());
+        for b in [
+            MTBuilder::new().trace_failure_threshold(0),
+            MTBuilder::new().max_worker_threads(0),
+            MTBuilder::new().trace_cache(""),
+            MTBuilder::new()
+                .tracing_kind(TracingKind::SoftwareTracing)
+                .trace_decoder(TraceDecoderKind::YkPT),
+        ] {
+            assert!(b.validate().is_err());
+        }
+    }
+}
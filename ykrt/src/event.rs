@@ -0,0 +1,112 @@
+//! Notifying interested parties of what the JIT is doing.
+
+use std::{error::Error, fmt, sync::Arc};
+
+use parking_lot::RwLock;
+
+use crate::log::{JitLog, LogRecord};
+#[cfg(feature = "yk_jitstate_debug")]
+use std::{env, sync::LazyLock};
+
+#[cfg(feature = "yk_jitstate_debug")]
+static JITSTATE_DEBUG: LazyLock<bool> = LazyLock::new(|| env::var("YKD_PRINT_JITSTATE").is_ok());
+
+/// A JIT event. Note that there are no "start interpreting" and "stop interpreting" events: if the
+/// system is not currently tracing or executing JITted code, then it is implicitly interpreting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JitEvent {
+    /// This thread has started tracing.
+    StartTracing,
+    /// This thread has stopped tracing, and the trace will be compiled.
+    StopTracing,
+    /// This thread has started tracing a side trace for a guard which has failed often.
+    StartSideTracing,
+    /// This thread has stopped tracing a side trace, and the side trace will be compiled.
+    StopSideTracing,
+    /// This thread has started executing JITted code.
+    EnterJitCode,
+    /// This thread has stopped executing JITted code, and execution continues in the interpreter.
+    ExitJitCode,
+    /// A guard in JITted code executing in this thread has failed, and the interpreter's frames
+    /// have been reconstructed from the JITted code's state.
+    Deoptimise,
+    /// A trace could not be compiled for the reason given. Compilation usually happens on a worker
+    /// thread, so unlike the other events, this event is not necessarily notified on the thread
+    /// that recorded the trace.
+    TraceCompilationAborted(String),
+}
+
+impl fmt::Display for JitEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JitEvent::StartTracing => write!(f, "start-tracing"),
+            JitEvent::StopTracing => write!(f, "stop-tracing"),
+            JitEvent::StartSideTracing => write!(f, "start-side-tracing"),
+            JitEvent::StopSideTracing => write!(f, "stop-side-tracing"),
+            JitEvent::EnterJitCode => write!(f, "enter-jit-code"),
+            JitEvent::ExitJitCode => write!(f, "exit-jit-code"),
+            JitEvent::Deoptimise => write!(f, "deoptimise"),
+            JitEvent::TraceCompilationAborted(_) => write!(f, "trace-compilation-aborted"),
+        }
+    }
+}
+
+/// Something which wants to be notified of [JitEvent]s. Observers are called synchronously on the
+/// thread where the event happened (which may be a compilation worker thread), so they should
+/// return quickly, and must not call back into the meta-tracer.
+pub trait JitObserver: Send + Sync {
+    fn notify(&self, event: &JitEvent);
+}
+
+/// The observer, if any, registered with a meta-tracer, and the `YKD_LOG` log, if it is enabled.
+pub(crate) struct JitEvents {
+    observer: RwLock<Option<Arc<dyn JitObserver>>>,
+    log: Option<JitLog>,
+}
+
+impl JitEvents {
+    pub(crate) fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            observer: RwLock::new(None),
+            log: JitLog::from_env()?,
+        })
+    }
+
+    pub(crate) fn set_observer(&self, observer: Option<Arc<dyn JitObserver>>) {
+        *self.observer.write() = observer;
+    }
+
+    /// Notify the registered observer, if there is one, of `event`. If `YKD_PRINT_JITSTATE` is
+    /// set, also print the event to stderr. If the `YKD_LOG` log is enabled, also log the event,
+    /// adding details to the log record with `details`.
+    pub(crate) fn emit<F>(&self, event: JitEvent, details: F)
+    where
+        F: FnOnce(LogRecord) -> LogRecord,
+    {
+        #[cfg(feature = "yk_jitstate_debug")]
+        if *JITSTATE_DEBUG {
+            eprintln!("jit-state: {}", event);
+        }
+        if let Some(log) = &self.log {
+            let mut r = LogRecord::new(&event.to_string());
+            if let JitEvent::TraceCompilationAborted(reason) = &event {
+                r = r.str("reason", reason);
+            }
+            log.write(details(r));
+        }
+        if let Some(observer) = &*self.observer.read() {
+            observer.notify(&event);
+        }
+    }
+
+    /// If the `YKD_LOG` log is enabled, log `event`, which is of interest to those reading the log,
+    /// but is not a [JitEvent].
+    pub(crate) fn log<F>(&self, event: &str, details: F)
+    where
+        F: FnOnce(LogRecord) -> LogRecord,
+    {
+        if let Some(log) = &self.log {
+            log.write(details(LogRecord::new(event)));
+        }
+    }
+}
@@ -2,25 +2,19 @@
 
 #![cfg_attr(test, feature(test))]
 #![feature(lazy_cell)]
+#![feature(thread_id_value)]
 #![allow(clippy::type_complexity)]
 #![allow(clippy::new_without_default)]
 
-#[cfg(feature = "yk_jitstate_debug")]
-use std::{env, sync::LazyLock};
-
+mod builder;
+mod event;
 mod location;
+mod log;
 pub(crate) mod mt;
+mod stats;
 
-pub use self::location::Location;
-pub use self::mt::{HotThreshold, MT};
-
-#[cfg(feature = "yk_jitstate_debug")]
-static JITSTATE_DEBUG: LazyLock<bool> = LazyLock::new(|| env::var("YKD_PRINT_JITSTATE").is_ok());
-
-/// Print select JIT events to stderr for testing/debugging purposes.
-#[cfg(feature = "yk_jitstate_debug")]
-pub fn print_jit_state(state: &str) {
-    if *JITSTATE_DEBUG {
-        eprintln!("jit-state: {}", state);
-    }
-}
+pub use self::builder::MTBuilder;
+pub use self::event::{JitEvent, JitObserver};
+pub use self::location::{Location, LocationKind, LocationState};
+pub use self::mt::{stitch_location, HotThreshold, MT};
+pub use self::stats::Stats;
@@ -1,14 +1,16 @@
 //! Trace location: track the state of a program location (counting, tracing, compiled, etc).
 
 use std::{
+    cmp,
     convert::TryFrom,
     sync::{
-        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    thread,
 };
 
-use crate::mt::{HotThreshold, TraceFailureThreshold};
+use crate::mt::{HotThreshold, TraceFailure, TraceFailureThreshold};
 use parking_lot::Mutex;
 use parking_lot_core::{
     park, unpark_one, ParkResult, SpinWait, UnparkResult, UnparkToken, DEFAULT_PARK_TOKEN,
@@ -50,18 +52,36 @@ pub struct Location {
     //  │             │ in thread
     //  │             ▼
     //  │           ┌──────────────┐             ┌───────────┐
-    //  │           │  Compiling   │────────────▶│  Dropped  │
-    //  │           └──────────────┘             └───────────┘
+    //  ├───────────│  Compiling   │────────────▶│  Dropped  │
+    //  │  failed   └──────────────┘             └───────────┘
     //  │             │
     //  │             │ trace compiled
     //  │             ▼
     //  │           ┌──────────────┐
     //  └───────────│   Compiled   │
-    //              └──────────────┘
+    //   guards     └──────────────┘
+    //   fail too
+    //   often
     //
     // We hope that a Location soon reaches the `Compiled` state (aka "the happy state") and stays
     // there.
     //
+    // If tracing, mapping, or compiling a trace fails, the Location is "reprofiled", going back to
+    // `Counting` (so that it can be traced again once it is hot again) until it has failed more
+    // often than the trace failure threshold allows, at which point it moves to `DontTrace`. A
+    // Location is also reprofiled if its compiled trace fails guards in most of its executions,
+    // since a trace recorded during a transient phase of the program may be of no use once that
+    // phase is over: the trace is marked as unsuitable when its guard fails, and the Location is
+    // reprofiled the next time it is reached (the Location may have been dropped while the trace
+    // was executing, so it can't be touched when the guard fails). Each reprofile doubles the hot
+    // threshold that the Location has to reach before it is traced again. Since a reprofiled
+    // Location is already hot, it counts in its `HotLocation` rather than in its payload.
+    //
+    // A Location can be dropped in any state. Dropping a Location frees its `HotLocation`
+    // immediately: anything which may outlive it (a compiled trace, or the result of a compilation
+    // job) is reference counted, and a thread tracing the Location is told to discard its trace
+    // (see `TRACING_DROPPED`).
+    //
     // The state machine is encoded in a `usize` in a not-entirely-simple way, as we don't want to
     // allocate any memory for Locations that do not become hot. The layout is as follows (on a 64
     // bit machine):
@@ -74,6 +94,16 @@ pub struct Location {
     // locklessly. All other states have `IS_COUNTING` set to 0 and the payload is the address of a
     // `Box<HotLocation>`, access to which is controlled by `IS_LOCKED`.
     //
+    // In the `Counting` state, the payload also records the policy the user has set for this
+    // Location (if any), so that setting a policy doesn't allocate memory either:
+    //
+    //   bit(s) | 63..37    | 36            | 35         | 34..3
+    //          | threshold | HAS_THRESHOLD | DONT_TRACE | count
+    //
+    // If `HAS_THRESHOLD` is set, `threshold` is the hot threshold for this Location, overriding
+    // the meta-tracer's hot threshold. If `DONT_TRACE` is set, this Location is never traced.
+    // When a Location becomes hot, its policy moves to its `HotLocation`.
+    //
     // The possible combinations of the counting and mutex bits are thus as follows:
     //
     //   payload          | IS_COUNTING | IS_PARKED | IS_LOCKED | Notes
@@ -97,6 +127,9 @@ pub struct Location {
 }
 
 impl Location {
+    /// The largest hot threshold that can be set for an individual Location.
+    pub const MAX_HOT_THRESHOLD: HotThreshold = (1 << STATE_THRESHOLD_NUM_BITS) - 1;
+
     /// Create a new location.
     pub fn new() -> Self {
         // Locations start in the counting state with a count of 0.
@@ -105,6 +138,102 @@ impl Location {
         }
     }
 
+    /// Set the hot threshold for this Location, overriding the meta-tracer's hot threshold. If
+    /// this Location is later reprofiled, its threshold is increased from `hot_threshold` rather
+    /// than from the meta-tracer's threshold. Thresholds greater than
+    /// [Location::MAX_HOT_THRESHOLD] are treated as [Location::MAX_HOT_THRESHOLD].
+    pub fn set_hot_threshold(&self, hot_threshold: HotThreshold) {
+        let hot_threshold = cmp::min(hot_threshold, Self::MAX_HOT_THRESHOLD);
+        let mut ls = self.load(Ordering::Relaxed);
+        while ls.is_counting() {
+            match self.compare_exchange_weak(
+                ls,
+                ls.with_hot_threshold(hot_threshold),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(x) => ls = x,
+            }
+        }
+        if let Ok(ls) = self.lock() {
+            let hl = unsafe { ls.hot_location() };
+            hl.hot_threshold = Some(hot_threshold);
+            self.unlock();
+        } else {
+            // The Location moved back to the Counting state before we'd gained a lock.
+            self.set_hot_threshold(hot_threshold);
+        }
+    }
+
+    /// Never trace this Location. If this Location already has a compiled trace, the trace is
+    /// still executed, but if it is discarded, the Location is not traced again. A trace of this
+    /// Location which is still being recorded or compiled is discarded rather than installed.
+    pub fn set_dont_trace(&self) {
+        let mut ls = self.load(Ordering::Relaxed);
+        while ls.is_counting() {
+            match self.compare_exchange_weak(
+                ls,
+                ls.with_dont_trace(),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(x) => ls = x,
+            }
+        }
+        if let Ok(ls) = self.lock() {
+            let hl = unsafe { ls.hot_location() };
+            hl.dont_trace = true;
+            // If this Location's trace is waiting to be compiled, dropping the `Compiling` state
+            // cancels the compilation job.
+            if let HotLocationKind::Counting(_) | HotLocationKind::Compiling(_) = hl.kind {
+                hl.kind = HotLocationKind::DontTrace;
+            }
+            self.unlock();
+        } else {
+            // The Location moved back to the Counting state before we'd gained a lock.
+            self.set_dont_trace();
+        }
+    }
+
+    /// Return a snapshot of this Location's state, e.g. so that a debugger can show which of an
+    /// interpreter's loops have been compiled. Notice that other threads may change this
+    /// Location's state, so the snapshot is potentially stale as soon as it is returned.
+    pub fn state(&self) -> LocationState {
+        let ls = self.load(Ordering::Relaxed);
+        if ls.is_counting() {
+            let kind = if ls.is_dont_trace() {
+                LocationKind::DontTrace
+            } else {
+                LocationKind::Counting(ls.count())
+            };
+            return LocationState {
+                kind,
+                trace_failures: 0,
+            };
+        }
+        if let Ok(ls) = self.lock() {
+            let hl = unsafe { ls.hot_location() };
+            let kind = match &hl.kind {
+                HotLocationKind::Compiled(ctr) => LocationKind::Compiled(ctr.id()),
+                HotLocationKind::Compiling(_) => LocationKind::Compiling,
+                HotLocationKind::Counting(count) => LocationKind::Counting(*count),
+                HotLocationKind::DontTrace => LocationKind::DontTrace,
+                HotLocationKind::Tracing(_) => LocationKind::Tracing,
+            };
+            let trace_failures = hl.trace_failure;
+            self.unlock();
+            LocationState {
+                kind,
+                trace_failures,
+            }
+        } else {
+            // The Location moved back to the Counting state before we'd gained a lock.
+            self.state()
+        }
+    }
+
     /// Return this Location's internal state.
     pub(super) fn load(&self, order: Ordering) -> LocationInner {
         LocationInner {
@@ -296,31 +425,82 @@ impl Location {
 
 impl Drop for Location {
     fn drop(&mut self) {
-        let ls = self.load(Ordering::Relaxed);
-        if !ls.is_counting() {
-            self.lock().unwrap();
-            let ls = self.load(Ordering::Relaxed);
+        loop {
+            let ls = match self.lock() {
+                Ok(ls) => ls,
+                // A counting Location owns no memory.
+                Err(()) => return,
+            };
             let hl = unsafe { ls.hot_location() };
-            if let HotLocationKind::Compiled(_) = hl.kind {
-                // FIXME: we can't drop this memory as another thread may still be executing the
-                // trace that's pointed to. There should be a ref count that we decrement and free
-                // memory if it reaches zero.
-                self.unlock();
-            } else if let HotLocationKind::Compiling(_)
-            | HotLocationKind::DontTrace
-            | HotLocationKind::Tracing(_) = hl.kind
-            {
-                self.unlock();
-                unsafe {
-                    let _ = Box::from_raw(hl);
+            let hl_ptr = hl as *mut HotLocation;
+            if let HotLocationKind::Tracing(tracing) = &hl.kind {
+                // If a thread is still tracing this Location, tell it that the Location has been
+                // dropped, so that it discards its trace rather than trying to update this
+                // Location when it stops tracing. If the thread is in the middle of updating this
+                // Location (i.e. it has claimed it), we have to wait for it to finish.
+                if tracing.compare_exchange(
+                    hl_ptr,
+                    TRACING_DROPPED,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) == Err(TRACING_CLAIMED)
+                {
+                    self.unlock();
+                    thread::yield_now();
+                    continue;
                 }
-            } else {
-                unreachable!();
             }
+            self.unlock();
+            // Compiled traces are reference counted, so dropping the `HotLocation` only frees a
+            // compiled trace if no other thread is executing it (if one is, the trace is freed
+            // when that thread finishes with it). Similarly, a compilation job for this location
+            // holds a reference to the `Compiling` state's [CompilingTrace]: the job is cancelled
+            // if it hasn't started, and otherwise its result is freed when it finishes.
+            unsafe {
+                let _ = Box::from_raw(hl_ptr);
+            }
+            return;
         }
     }
 }
 
+/// Stored in a tracing thread's pointer to the [HotLocation] it is tracing when the [Location]
+/// being traced has been dropped: the thread must discard its trace, and must not access the
+/// [Location] again. Neither this nor [TRACING_CLAIMED] can be a valid pointer to a [HotLocation],
+/// since both are misaligned.
+pub(crate) const TRACING_DROPPED: *mut HotLocation = usize::MAX as *mut HotLocation;
+
+/// Stored in a tracing thread's pointer to the [HotLocation] it is tracing while the thread is
+/// updating the [Location] being traced: until the thread replaces this value, the [Location]
+/// can't be dropped.
+pub(crate) const TRACING_CLAIMED: *mut HotLocation = (usize::MAX - 1) as *mut HotLocation;
+
+/// A snapshot of a [Location]'s state, as returned by [Location::state].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocationState {
+    pub kind: LocationKind,
+    /// How many attempts to trace, or compile a trace for, the Location have failed.
+    pub trace_failures: TraceFailureThreshold,
+}
+
+/// The states that a [Location] can be in (see the state machine in [Location]). A dropped
+/// [Location] can't be queried, so there is no state for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocationKind {
+    /// The Location is counting how often it has been executed until it is hot. The count
+    /// restarts from 0 when the Location is reprofiled.
+    Counting(HotThreshold),
+    /// A thread is tracing the Location.
+    Tracing,
+    /// The Location's trace is waiting to be, or being, compiled.
+    Compiling,
+    /// The Location has a compiled trace with the given ID. Trace IDs are those used in the
+    /// `YKD_LOG` log.
+    Compiled(u64),
+    /// The Location will not be traced (again).
+    DontTrace,
+}
+
 #[cfg(target_pointer_width = "64")]
 const STATE_TAG: usize = 0b111; // All of the other tag data must fit in this.
 #[cfg(target_pointer_width = "64")]
@@ -330,6 +510,15 @@ const STATE_IS_LOCKED: usize = 0b001;
 const STATE_IS_PARKED: usize = 0b010;
 const STATE_IS_COUNTING: usize = 0b100;
 
+// The layout of the payload in the Counting state.
+#[cfg(target_pointer_width = "64")]
+const STATE_COUNT_NUM_BITS: usize = 32;
+const STATE_COUNT_MASK: usize = ((1 << STATE_COUNT_NUM_BITS) - 1) << STATE_NUM_BITS;
+const STATE_DONT_TRACE: usize = 1 << (STATE_NUM_BITS + STATE_COUNT_NUM_BITS);
+const STATE_HAS_THRESHOLD: usize = STATE_DONT_TRACE << 1;
+const STATE_THRESHOLD_SHIFT: usize = STATE_NUM_BITS + STATE_COUNT_NUM_BITS + 2;
+const STATE_THRESHOLD_NUM_BITS: usize = usize::BITS as usize - STATE_THRESHOLD_SHIFT;
+
 const TOKEN_NORMAL: UnparkToken = UnparkToken(0);
 const TOKEN_HANDOFF: UnparkToken = UnparkToken(1);
 
@@ -367,7 +556,25 @@ impl LocationInner {
     pub(super) fn count(&self) -> HotThreshold {
         debug_assert!(self.is_counting());
         debug_assert!(!self.is_locked());
-        u32::try_from(self.x >> STATE_NUM_BITS).unwrap()
+        u32::try_from((self.x & STATE_COUNT_MASK) >> STATE_NUM_BITS).unwrap()
+    }
+
+    /// If, and only if, the Location is in the counting state, return the hot threshold set for
+    /// this Location, if there is one.
+    pub(super) fn hot_threshold(&self) -> Option<HotThreshold> {
+        debug_assert!(self.is_counting());
+        if self.x & STATE_HAS_THRESHOLD != 0 {
+            Some(u32::try_from(self.x >> STATE_THRESHOLD_SHIFT).unwrap())
+        } else {
+            None
+        }
+    }
+
+    /// If, and only if, the Location is in the counting state, return true if this Location must
+    /// never be traced.
+    pub(super) fn is_dont_trace(&self) -> bool {
+        debug_assert!(self.is_counting());
+        self.x & STATE_DONT_TRACE != 0
     }
 
     /// If this `State` is not counting, return its `HotLocation`. It is undefined behaviour to
@@ -414,7 +621,29 @@ impl LocationInner {
         debug_assert!(self.is_counting());
         debug_assert_eq!(count << STATE_NUM_BITS >> STATE_NUM_BITS, count);
         LocationInner {
-            x: (self.x & STATE_TAG) | (usize::try_from(count).unwrap() << STATE_NUM_BITS),
+            x: (self.x & !STATE_COUNT_MASK) | (usize::try_from(count).unwrap() << STATE_NUM_BITS),
+        }
+    }
+
+    /// Return a version of this `State` with the hot threshold set to `hot_threshold`. It is
+    /// undefined behaviour to call this function if this `State` is not in the counting phase.
+    fn with_hot_threshold(&self, hot_threshold: HotThreshold) -> Self {
+        debug_assert!(self.is_counting());
+        debug_assert!(hot_threshold <= Location::MAX_HOT_THRESHOLD);
+        let mask = STATE_TAG | STATE_COUNT_MASK | STATE_DONT_TRACE;
+        LocationInner {
+            x: (self.x & mask)
+                | STATE_HAS_THRESHOLD
+                | (usize::try_from(hot_threshold).unwrap() << STATE_THRESHOLD_SHIFT),
+        }
+    }
+
+    /// Return a version of this `State` which must never be traced. It is undefined behaviour to
+    /// call this function if this `State` is not in the counting phase.
+    fn with_dont_trace(&self) -> Self {
+        debug_assert!(self.is_counting());
+        LocationInner {
+            x: self.x | STATE_DONT_TRACE,
         }
     }
 
@@ -432,7 +661,37 @@ impl LocationInner {
 
 pub(crate) struct HotLocation {
     pub(crate) kind: HotLocationKind,
+    /// The hot threshold set for this location, if there is one, overriding the meta-tracer's
+    /// hot threshold.
+    pub(crate) hot_threshold: Option<HotThreshold>,
+    /// Must this location never be traced (again)?
+    pub(crate) dont_trace: bool,
     pub(crate) trace_failure: TraceFailureThreshold,
+    /// How many times this location has been reprofiled.
+    pub(crate) reprofiles: u16,
+    /// Why the most recent attempt to trace or compile this location failed, if it has ever
+    /// failed.
+    pub(crate) last_failure: Option<TraceFailure>,
+}
+
+/// The state shared by a `Location` in the Compiling state and the job compiling its trace.
+#[derive(Debug)]
+pub(crate) struct CompilingTrace {
+    /// When compilation has completed, this changes from `None` to `Some`, containing either the
+    /// compiled trace or the reason that tracing or compilation failed.
+    pub(crate) result: Mutex<Option<Result<Arc<CompiledTrace>, TraceFailure>>>,
+    /// How many times the `Location`'s control point has been reached while its trace is waiting
+    /// to be compiled. The more often, the more urgently the trace is compiled.
+    pub(crate) hits: AtomicU64,
+}
+
+impl CompilingTrace {
+    pub(crate) fn new() -> Self {
+        Self {
+            result: Mutex::new(None),
+            hits: AtomicU64::new(0),
+        }
+    }
 }
 
 /// A `Location`'s non-counting states.
@@ -440,10 +699,12 @@ pub(crate) struct HotLocation {
 pub(crate) enum HotLocationKind {
     /// Points to executable machine code that can be executed instead of the interpreter for this
     /// HotLocation.
-    Compiled(*const CompiledTrace),
-    /// This HotLocation is being compiled in another thread: when compilation has completed the
-    /// `Option` will change from `None` to `Some`.
-    Compiling(Arc<Mutex<Option<Box<CompiledTrace>>>>),
+    Compiled(Arc<CompiledTrace>),
+    /// This HotLocation is being compiled in another thread.
+    Compiling(Arc<CompilingTrace>),
+    /// This HotLocation has been reprofiled, so we are counting up to its (increased) hot threshold
+    /// again before retracing it.
+    Counting(HotThreshold),
     /// This HotLocation has encountered problems (e.g. traces which are too long) and shouldn't be
     /// traced again.
     DontTrace,
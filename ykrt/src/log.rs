@@ -0,0 +1,123 @@
+//! A machine-readable log of JIT events, enabled with `YKD_LOG=<path>`. Each line of the log is a
+//! self-contained JSON object describing one event.
+
+use std::{
+    convert::TryFrom,
+    env,
+    error::Error,
+    ffi::c_void,
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+
+/// A JIT log file.
+pub(crate) struct JitLog {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl JitLog {
+    /// If `YKD_LOG` is set, create (or truncate) the file it names and return a log which writes
+    /// to it.
+    pub(crate) fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        match env::var_os("YKD_LOG") {
+            Some(p) => {
+                let file = File::create(&p)
+                    .map_err(|e| format!("Can't create YKD_LOG file {:?}: {}", p, e))?;
+                Ok(Some(Self {
+                    file: Mutex::new(BufWriter::new(file)),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Write `record` to the log. Errors are ignored: losing log records is preferable to
+    /// interrupting the interpreter.
+    pub(crate) fn write(&self, record: LogRecord) {
+        let mut buf = record.buf;
+        buf.push_str("}\n");
+        let mut file = self.file.lock();
+        let _ = file.write_all(buf.as_bytes());
+        // Flushing after every record means that the log is complete even if the process is
+        // killed, at the cost of being slow. That's acceptable for a debugging aid.
+        let _ = file.flush();
+    }
+}
+
+/// A single, under construction, JSON record. Every record includes the event name, the time at
+/// which it was created (in nanoseconds since the Unix epoch), and the ID of the thread which
+/// created it.
+pub(crate) struct LogRecord {
+    buf: String,
+}
+
+impl LogRecord {
+    pub(crate) fn new(event: &str) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut r = Self {
+            buf: String::from("{"),
+        };
+        r.push_key("event");
+        push_json_str(&mut r.buf, event);
+        r.duration("time_ns", time)
+            .u64("thread", thread::current().id().as_u64().get())
+    }
+
+    /// Add an integer field.
+    pub(crate) fn u64(mut self, key: &str, val: u64) -> Self {
+        self.push_key(key);
+        write!(self.buf, "{}", val).unwrap();
+        self
+    }
+
+    /// Add a duration field, in nanoseconds. By convention, `key` should end in `_ns`.
+    pub(crate) fn duration(self, key: &str, val: Duration) -> Self {
+        self.u64(key, u64::try_from(val.as_nanos()).unwrap_or(u64::MAX))
+    }
+
+    /// Add a pointer field, formatted as a hexadecimal string.
+    pub(crate) fn ptr<T>(mut self, key: &str, val: *const T) -> Self {
+        self.push_key(key);
+        write!(self.buf, "\"{:#x}\"", val as *const c_void as usize).unwrap();
+        self
+    }
+
+    /// Add a string field.
+    pub(crate) fn str(mut self, key: &str, val: &str) -> Self {
+        self.push_key(key);
+        push_json_str(&mut self.buf, val);
+        self
+    }
+
+    fn push_key(&mut self, key: &str) {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        push_json_str(&mut self.buf, key);
+        self.buf.push(':');
+    }
+}
+
+/// Append `s` to `buf` as a quoted and escaped JSON string.
+fn push_json_str(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(buf, "\\u{:04x}", c as u32).unwrap(),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
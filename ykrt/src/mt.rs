@@ -1,29 +1,36 @@
 //! The main end-user interface to the meta-tracing system.
 
-use hwtracer::decode::TraceDecoderKind;
-#[cfg(feature = "yk_testing")]
-use std::env;
 use std::{
-    cmp,
+    cell::{Cell, RefCell},
     collections::VecDeque,
+    convert::TryFrom,
+    env,
     error::Error,
     ffi::c_void,
+    fmt,
     marker::PhantomData,
+    mem,
     sync::{
-        atomic::{AtomicPtr, AtomicU16, AtomicU32, AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicPtr, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
     },
-    thread,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use parking_lot::{Condvar, Mutex, MutexGuard};
-#[cfg(feature = "yk_jitstate_debug")]
-use std::sync::LazyLock;
 
-use crate::location::{HotLocation, HotLocationKind, Location, LocationInner};
-#[cfg(feature = "yk_jitstate_debug")]
-use crate::print_jit_state;
-use yktrace::{start_tracing, stop_tracing, CompiledTrace, TracingKind, UnmappedTrace};
+use crate::builder::{check_max_worker_threads, check_trace_failure_threshold, MTBuilder};
+use crate::event::{JitEvent, JitEvents, JitObserver};
+use crate::location::{
+    CompilingTrace, HotLocation, HotLocationKind, Location, LocationInner, TRACING_CLAIMED,
+    TRACING_DROPPED,
+};
+use crate::log::LogRecord;
+use crate::stats::{AtomicStats, Stats};
+use yktrace::{
+    CompileOptions, CompiledTrace, InvalidTraceError, TracingBackend, TracingKind, UnmappedTrace,
+};
 
 // The HotThreshold must be less than a machine word wide for [`Location::Location`] to do its
 // pointer tagging thing. We therefore choose a type which makes this statically clear to
@@ -37,49 +44,86 @@ type AtomicHotThreshold = AtomicU32;
 pub type TraceFailureThreshold = u16;
 pub type AtomicTraceFailureThreshold = AtomicU16;
 
-const DEFAULT_HOT_THRESHOLD: HotThreshold = 50;
-const DEFAULT_TRACE_FAILURE_THRESHOLD: TraceFailureThreshold = 5;
+pub type GuardFailureThreshold = u64;
+type AtomicGuardFailureThreshold = AtomicU64;
 
-const TRACE_RETURN_SUCCESS: *const c_void = std::ptr::null();
+/// Returned by a compiled trace which reached the control point of a `Location` which has no
+/// compiled trace: execution must continue in the interpreter.
+const TRACE_RETURN_EXIT: usize = 1;
 
 thread_local! {static THREAD_MTTHREAD: MTThread = MTThread::new();}
 
-#[cfg(feature = "yk_testing")]
-static SERIALISE_COMPILATION: LazyLock<bool> = LazyLock::new(|| {
-    &env::var("YKD_SERIALISE_COMPILATION").unwrap_or_else(|_| "0".to_owned()) == "1"
-});
+/// The ID to be given to the next trace any thread starts recording.
+static NEXT_TRACE_ID: AtomicU64 = AtomicU64::new(0);
 
 /// A meta-tracer. Note that this is conceptually a "front-end" to the actual meta-tracer akin to
 /// an `Rc`: this struct can be freely `clone()`d without duplicating the underlying meta-tracer.
 pub struct MT {
     hot_threshold: AtomicHotThreshold,
     trace_failure_threshold: AtomicTraceFailureThreshold,
+    guard_failure_threshold: AtomicGuardFailureThreshold,
+    side_trace_threshold: AtomicHotThreshold,
+    /// The maximum number of control points a thread may pass through while tracing.
+    max_trace_length: AtomicUsize,
     /// The ordered queue of compilation worker functions.
-    job_queue: Arc<(Condvar, Mutex<VecDeque<Box<dyn FnOnce() + Send>>>)>,
+    job_queue: Arc<(Condvar, Mutex<JobQueue>)>,
+    /// The worker threads which have been started, so that they can be joined when this
+    /// meta-tracer is dropped.
+    worker_threads: Mutex<Vec<JoinHandle<()>>>,
     /// The hard cap on the number of worker threads.
     max_worker_threads: AtomicUsize,
+    /// If true, traces are compiled on the thread which recorded them, rather than on a worker
+    /// thread.
+    serialise_compilation: AtomicBool,
+    /// The options with which traces are compiled.
+    compile_opts: Arc<CompileOptions>,
     /// How many worker threads are currently running. Note that this may temporarily be `>`
     /// [`max_worker_threads`].
     active_worker_threads: AtomicUsize,
-    trace_decoder_kind: TraceDecoderKind,
-    tracing_kind: TracingKind,
+    /// The tracing backend selected for this platform.
+    tracing_backend: TracingBackend,
+    /// Statistics about what this meta-tracer has been doing.
+    stats: Arc<AtomicStats>,
+    /// Where this meta-tracer's [JitEvent]s are sent.
+    events: Arc<JitEvents>,
 }
 
 impl MT {
-    // Create a new meta-tracer instance. Arbitrarily many of these can be created, though there
-    // are no guarantees as to whether they will share resources effectively or fairly.
+    // Create a new meta-tracer instance, configured from the environment (see
+    // [MTBuilder::from_env]). Arbitrarily many of these can be created, though there are no
+    // guarantees as to whether they will share resources effectively or fairly.
     pub fn new() -> Result<Self, Box<dyn Error>> {
+        MTBuilder::from_env()?.build()
+    }
+
+    /// Create a new meta-tracer instance configured by `b`.
+    pub(crate) fn from_builder(b: MTBuilder) -> Result<Self, Box<dyn Error>> {
+        let tracing_backend = b.validate()?;
+        let mut compile_opts = b.compile_options();
+        if let Some(dir) = &b.trace_cache {
+            compile_opts.trace_cache =
+                Some(Arc::from(yktrace::trace_cache_dir(dir).map_err(|e| {
+                    format!("Can't create trace cache in {:?}: {}", dir, e)
+                })?));
+        }
+        if env::var("YKD_PRINT_TRACING_BACKEND").map_or(false, |x| x == "1") {
+            eprintln!("tracing-backend: {tracing_backend}");
+        }
         Ok(Self {
-            hot_threshold: AtomicHotThreshold::new(DEFAULT_HOT_THRESHOLD),
-            trace_failure_threshold: AtomicTraceFailureThreshold::new(
-                DEFAULT_TRACE_FAILURE_THRESHOLD,
-            ),
-            job_queue: Arc::new((Condvar::new(), Mutex::new(VecDeque::new()))),
-            max_worker_threads: AtomicUsize::new(cmp::max(1, num_cpus::get() - 1)),
+            hot_threshold: AtomicHotThreshold::new(b.hot_threshold),
+            trace_failure_threshold: AtomicTraceFailureThreshold::new(b.trace_failure_threshold),
+            guard_failure_threshold: AtomicGuardFailureThreshold::new(b.guard_failure_threshold),
+            side_trace_threshold: AtomicHotThreshold::new(b.side_trace_threshold),
+            max_trace_length: AtomicUsize::new(b.max_trace_length),
+            job_queue: Arc::new((Condvar::new(), Mutex::new(JobQueue::default()))),
+            worker_threads: Mutex::new(Vec::new()),
+            max_worker_threads: AtomicUsize::new(b.max_worker_threads),
+            serialise_compilation: AtomicBool::new(b.serialise_compilation),
+            compile_opts: Arc::new(compile_opts),
             active_worker_threads: AtomicUsize::new(0),
-            trace_decoder_kind: TraceDecoderKind::default_for_platform()
-                .ok_or("Tracing is not supported on this platform")?,
-            tracing_kind: TracingKind::default(),
+            tracing_backend,
+            stats: Arc::new(AtomicStats::default()),
+            events: Arc::new(JitEvents::new()?),
         })
     }
 
@@ -101,35 +145,129 @@ impl MT {
     }
 
     /// Set the threshold at which a `Location` from which tracing has failed multiple times is
-    /// marked as "do not try tracing again".
+    /// marked as "do not try tracing again". An invalid threshold (i.e. 0) is ignored.
     pub fn set_trace_failure_threshold(&self, trace_failure_threshold: TraceFailureThreshold) {
-        if trace_failure_threshold < 1 {
-            panic!("Trace failure threshold must be >= 1.");
+        if check_trace_failure_threshold(trace_failure_threshold).is_err() {
+            return;
         }
         self.trace_failure_threshold
             .store(trace_failure_threshold, Ordering::Relaxed);
     }
 
+    /// Return this `MT` instance's current guard failure threshold. Notice that this value can be
+    /// changed by other threads and is thus potentially stale as soon as it is read.
+    pub fn guard_failure_threshold(&self) -> GuardFailureThreshold {
+        self.guard_failure_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Set the number of guard failures after which a compiled trace which fails guards in most
+    /// of its executions is discarded, and its `Location` reprofiled.
+    pub fn set_guard_failure_threshold(&self, guard_failure_threshold: GuardFailureThreshold) {
+        self.guard_failure_threshold
+            .store(guard_failure_threshold, Ordering::Relaxed);
+    }
+
+    /// Return this `MT` instance's current side trace threshold. Notice that this value can be
+    /// changed by other threads and is thus potentially stale as soon as it is read.
+    pub fn side_trace_threshold(&self) -> HotThreshold {
+        self.side_trace_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Set the number of times a guard in a compiled trace must fail before a side trace is
+    /// compiled for it.
+    pub fn set_side_trace_threshold(&self, side_trace_threshold: HotThreshold) {
+        self.side_trace_threshold
+            .store(side_trace_threshold, Ordering::Relaxed);
+    }
+
+    /// Return this `MT` instance's current maximum trace length. Notice that this value can be
+    /// changed by other threads and is thus potentially stale as soon as it is read.
+    pub fn max_trace_length(&self) -> usize {
+        self.max_trace_length.load(Ordering::Relaxed)
+    }
+
+    /// Set the maximum number of control points (including those of `Location`s other than the
+    /// one being traced) that a thread may pass through while tracing. If a trace grows longer
+    /// than this, tracing is abandoned, and counts as a failed attempt to trace its `Location`. If
+    /// this is 0, every trace is abandoned.
+    pub fn set_max_trace_length(&self, max_trace_length: usize) {
+        self.max_trace_length
+            .store(max_trace_length, Ordering::Relaxed);
+    }
+
     /// Return this meta-tracer's maximum number of worker threads. Notice that this value can be
     /// changed by other threads and is thus potentially stale as soon as it is read.
     pub fn max_worker_threads(&self) -> usize {
         self.max_worker_threads.load(Ordering::Relaxed)
     }
 
+    /// Set the maximum number of worker threads compiling traces. Worker threads which are
+    /// already running are not stopped if this is lower than the number of running worker
+    /// threads, but no more are started until the number of worker threads is below the maximum.
+    /// An invalid maximum (i.e. 0) is ignored.
+    pub fn set_max_worker_threads(&self, max_worker_threads: usize) {
+        if check_max_worker_threads(max_worker_threads).is_err() {
+            return;
+        }
+        self.max_worker_threads
+            .store(max_worker_threads, Ordering::Relaxed);
+    }
+
+    /// Return true if this meta-tracer compiles traces on the thread which recorded them. Notice
+    /// that this value can be changed by other threads and is thus potentially stale as soon as it
+    /// is read.
+    pub fn serialise_compilation(&self) -> bool {
+        self.serialise_compilation.load(Ordering::Relaxed)
+    }
+
+    /// If `serialise_compilation` is true, compile traces synchronously on the thread which
+    /// recorded them, rather than on a worker thread. A thread which finishes recording a trace
+    /// then blocks until the trace is compiled, and the next time the thread reaches the trace's
+    /// `Location`, the compiled trace is executed. This makes the JIT's behaviour deterministic
+    /// (e.g. for reproducible benchmarks, or for debugging), at the cost of pausing the
+    /// interpreter while traces are compiled. Jobs which are already queued are unaffected.
+    pub fn set_serialise_compilation(&self, serialise_compilation: bool) {
+        self.serialise_compilation
+            .store(serialise_compilation, Ordering::Relaxed);
+    }
+
     /// Return the kind of tracing that this meta-tracer is using. Notice that this value can be
     /// changed by other threads and is thus potentially stale as soon as it is read.
     pub fn tracing_kind(&self) -> TracingKind {
-        self.tracing_kind
+        self.tracing_backend.kind()
+    }
+
+    /// Return the tracing backend that this meta-tracer selected, including the reasons why any
+    /// more preferable backends were rejected.
+    pub fn tracing_backend(&self) -> &TracingBackend {
+        &self.tracing_backend
+    }
+
+    /// Return a snapshot of this meta-tracer's statistics. Notice that other threads may be
+    /// updating the statistics, so the snapshot is potentially stale as soon as it is returned.
+    pub fn stats(&self) -> Stats {
+        let compile_queue_len = self.job_queue.1.lock().jobs.len();
+        self.stats.snapshot(
+            compile_queue_len,
+            self.active_worker_threads.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Register `observer` to be notified of this meta-tracer's [JitEvent]s, replacing any
+    /// previously registered observer. If `observer` is `None`, no observer is notified.
+    pub fn set_jit_observer(&self, observer: Option<Arc<dyn JitObserver>>) {
+        self.events.set_observer(observer);
     }
 
     /// Queue `job` to be run on a worker thread.
-    fn queue_job(&self, job: Box<dyn FnOnce() + Send>) {
+    fn queue_job(&self, job: Job) {
         // We have a very simple model of worker threads. Each time a job is queued, we spin up a
         // new worker thread iff we aren't already running the maximum number of worker threads.
-        // Once started, a worker thread never dies, waiting endlessly for work.
+        // Once started, a worker thread waits endlessly for work until this meta-tracer is
+        // dropped.
 
         let (cv, mtx) = &*self.job_queue;
-        mtx.lock().push_back(job);
+        mtx.lock().push(job);
         cv.notify_one();
 
         let max_jobs = self.max_worker_threads.load(Ordering::Relaxed);
@@ -146,16 +284,19 @@ impl MT {
             }
 
             let jq = Arc::clone(&self.job_queue);
-            thread::spawn(move || {
+            let handle = thread::spawn(move || {
                 let (cv, mtx) = &*jq;
                 let mut lock = mtx.lock();
-                loop {
-                    match lock.pop_front() {
-                        Some(x) => MutexGuard::unlocked(&mut lock, x),
+                while !lock.shutdown {
+                    match lock.pop() {
+                        Some(x) => MutexGuard::unlocked(&mut lock, x.run),
                         None => cv.wait(&mut lock),
                     }
                 }
             });
+            let mut worker_threads = self.worker_threads.lock();
+            worker_threads.retain(|x| !x.is_finished());
+            worker_threads.push(handle);
         }
     }
 
@@ -165,43 +306,108 @@ impl MT {
         ctrlp_vars: *mut c_void,
         frameaddr: *mut c_void,
     ) -> *const c_void {
+        // A side trace ends at the first control point it reaches, whichever `Location` that is.
+        if let Some((parent, guard_idx, root_loc)) =
+            THREAD_MTTHREAD.with(|mtt| mtt.side_tracing.take())
+        {
+            match self.stop_tracing(root_loc, true) {
+                (trace_id, Ok(utrace)) => {
+                    self.queue_side_trace_compile_job(
+                        trace_id, utrace, parent, guard_idx, root_loc,
+                    );
+                }
+                (_, Err(_)) => parent.guard(guard_idx).side_trace_failed(),
+            }
+            return std::ptr::null();
+        }
+
+        if THREAD_MTTHREAD.with(|mtt| mtt.tracing.load(Ordering::Acquire) == TRACING_DROPPED) {
+            // The `Location` this thread was tracing has been dropped.
+            self.claim_tracing_location();
+        }
+
+        if THREAD_MTTHREAD.with(|mtt| {
+            if mtt.tracing.load(Ordering::Relaxed).is_null() {
+                return false;
+            }
+            let trace_len = mtt.trace_len.get() + 1;
+            mtt.trace_len.set(trace_len);
+            trace_len > self.max_trace_length()
+        }) {
+            self.abandon_tracing(Some(TraceFailure::TooLong));
+        }
+
         match self.transition_location(loc) {
             TransitionLocation::NoAction => (),
             TransitionLocation::Execute(ctr) => {
-                // FIXME: If we want to free compiled traces, we'll need to refcount (or use
-                // a GC) to know if anyone's executing that trace at the moment.
-                //
-                // FIXME: this loop shouldn't exist. Trace stitching should be implemented in
-                // the trace itself.
-                // https://github.com/ykjit/yk/issues/442
-                loop {
-                    #[cfg(feature = "yk_jitstate_debug")]
-                    print_jit_state("enter-jit-code");
-                    match unsafe { &*ctr }.exec(ctrlp_vars, frameaddr) {
-                        TRACE_RETURN_SUCCESS => {
-                            #[cfg(feature = "yk_jitstate_debug")]
-                            print_jit_state("exit-jit-code");
-                        }
-                        v => {
-                            #[cfg(feature = "yk_jitstate_debug")]
-                            print_jit_state("exit-jit-code");
-                            return v;
+                // If this thread is tracing, that trace ends here.
+                self.stop_tracing_at_compiled();
+                // Note that `ctr` is reference counted, so the trace can't be freed while we're
+                // executing it, even if its `Location` is dropped in the meantime. The trace loops
+                // by itself, and side traces may stitch it to the traces of other `Location`s, so
+                // we only return here when the interpreter has to take over.
+                let trace_id = ctr.id();
+                self.events.emit(JitEvent::EnterJitCode, |r| {
+                    r.u64("trace_id", trace_id).ptr("location", loc)
+                });
+                let stitched = THREAD_MTTHREAD.with(|mtt| mtt.stitched.borrow().len());
+                ctr.executing();
+                let v = ctr.exec(ctrlp_vars, frameaddr, std::ptr::null());
+                let stitched =
+                    THREAD_MTTHREAD.with(|mtt| mtt.stitched.borrow_mut().split_off(stitched));
+                let exit_jit_code = || {
+                    self.events.emit(JitEvent::ExitJitCode, |r| {
+                        r.u64("trace_id", trace_id).ptr("location", loc)
+                    })
+                };
+                if v as usize == TRACE_RETURN_EXIT {
+                    exit_jit_code();
+                    return std::ptr::null();
+                }
+                // A guard failed: find out which trace it belongs to, remembering that execution
+                // may have been stitched to the trace of another `Location`.
+                if let Some(fg) = yktrace::take_failed_guard() {
+                    self.stats.deoptimised(fg.stopgap_time());
+                    let found = std::iter::once((loc as *const Location, ctr))
+                        .chain(stitched)
+                        .find_map(|(rloc, rctr)| {
+                            rctr.find_failed_guard(&fg).map(|x| (rloc, rctr, x))
+                        });
+                    self.events.emit(JitEvent::Deoptimise, |r| {
+                        let r = r.duration("stopgap_ns", fg.stopgap_time());
+                        match &found {
+                            Some((rloc, _, (gctr, guard_idx))) => r
+                                .u64("trace_id", gctr.id())
+                                .u64("guard", *guard_idx as u64)
+                                .ptr("location", *rloc),
+                            None => r,
                         }
+                    });
+                    exit_jit_code();
+                    if let Some((rloc, rctr, (gctr, guard_idx))) = found {
+                        // `rloc` may have been dropped while the trace was executing, so it must
+                        // not be dereferenced.
+                        self.guard_failed(&rctr);
+                        self.maybe_start_side_tracing(rloc, gctr, guard_idx);
                     }
+                } else {
+                    exit_jit_code();
                 }
+                return v;
             }
             TransitionLocation::StartTracing(kind) => {
-                #[cfg(feature = "yk_jitstate_debug")]
-                print_jit_state("start-tracing");
-                start_tracing(kind);
-            }
-            TransitionLocation::StopTracing(x) => match stop_tracing() {
-                Ok(utrace) => {
-                    #[cfg(feature = "yk_jitstate_debug")]
-                    print_jit_state("stop-tracing");
-                    self.queue_compile_job(utrace, x);
+                THREAD_MTTHREAD.with(|mtt| mtt.tracing_loc.set(loc));
+                self.start_tracing(kind, loc, None);
+            }
+            TransitionLocation::StopTracing(x) => match self.stop_tracing(loc, false) {
+                (trace_id, Ok(utrace)) => {
+                    self.queue_compile_job(trace_id, utrace, x, loc);
+                }
+                (_, Err(e)) => {
+                    // The next thread to encounter the location will move it out of the
+                    // Compiling state.
+                    x.result.lock().replace(Err(TraceFailure::Tracing(e)));
                 }
-                Err(_) => todo!(),
             },
         }
         std::ptr::null()
@@ -216,8 +422,11 @@ impl MT {
             debug_assert!(!ls.is_locked());
             debug_assert!(!ls.is_parked());
 
+            if ls.is_dont_trace() {
+                return TransitionLocation::NoAction;
+            }
             let count = ls.count();
-            if count < self.hot_threshold() {
+            if count < ls.hot_threshold().unwrap_or_else(|| self.hot_threshold()) {
                 // Try incrementing this location's hot count. We make no guarantees that this will
                 // succeed because under contention we can end up racing with many other threads
                 // and it's not worth our time to halt execution merely to have an accurate hot
@@ -244,7 +453,11 @@ impl MT {
                         // perform in a single step.
                         let hl_ptr = Box::into_raw(Box::new(HotLocation {
                             kind: HotLocationKind::Tracing(Arc::clone(&mtt.tracing)),
+                            hot_threshold: ls.hot_threshold(),
+                            dont_trace: false,
                             trace_failure: 0,
+                            reprofiles: 0,
+                            last_failure: None,
                         }));
                         let new_ls = LocationInner::new().with_hotlocation(hl_ptr).with_lock();
                         debug_assert!(!ls.is_locked());
@@ -301,39 +514,60 @@ impl MT {
                 }
             }
             let hl = unsafe { ls.hot_location() };
+            if reprofile_if_unsuitable(hl) {
+                loc.unlock();
+                return TransitionLocation::NoAction;
+            }
             match &hl.kind {
                 HotLocationKind::Compiled(ctr) => {
+                    // Note that we execute compiled traces even if this thread is tracing another
+                    // `Location`: see `stop_tracing_at_compiled`.
+                    let ctr = Arc::clone(ctr);
                     loc.unlock();
-                    let thread_arc = THREAD_MTTHREAD.with(|mtt| Arc::clone(&mtt.tracing));
-                    if !thread_arc.load(Ordering::Relaxed).is_null() {
-                        // FIXME: https://github.com/ykjit/yk/issues/519
-                        TransitionLocation::NoAction
-                    } else {
-                        TransitionLocation::Execute(*ctr)
-                    }
+                    TransitionLocation::Execute(ctr)
                 }
                 HotLocationKind::Compiling(arcmtx) => {
-                    let thread_arc = THREAD_MTTHREAD.with(|mtt| Arc::clone(&mtt.tracing));
-                    if !thread_arc.load(Ordering::Relaxed).is_null() {
-                        loc.unlock();
-                        // FIXME: https://github.com/ykjit/yk/issues/519
-                        return TransitionLocation::NoAction;
-                    }
-                    let r = match arcmtx.try_lock().map(|mut x| x.take()) {
+                    arcmtx.hits.fetch_add(1, Ordering::Relaxed);
+                    let r = match arcmtx.result.try_lock().map(|mut x| x.take()) {
                         None | Some(None) => {
                             // `None` means we failed to grab the lock; `Some(None)` means we
                             // grabbed the lock but compilation has not yet completed.
                             TransitionLocation::NoAction
                         }
-                        Some(Some(ctr)) => {
-                            let ctr = Box::into_raw(ctr);
-                            hl.kind = HotLocationKind::Compiled(ctr);
+                        Some(Some(Ok(ctr))) => {
+                            hl.kind = HotLocationKind::Compiled(Arc::clone(&ctr));
                             TransitionLocation::Execute(ctr)
                         }
+                        Some(Some(Err(reason))) => {
+                            self.trace_failed(hl, reason);
+                            TransitionLocation::NoAction
+                        }
                     };
                     loc.unlock();
                     r
                 }
+                HotLocationKind::Counting(count) => {
+                    if hl.dont_trace {
+                        hl.kind = HotLocationKind::DontTrace;
+                        loc.unlock();
+                        return TransitionLocation::NoAction;
+                    }
+                    if *count < self.reprofile_threshold(hl) {
+                        hl.kind = HotLocationKind::Counting(*count + 1);
+                        loc.unlock();
+                        return TransitionLocation::NoAction;
+                    }
+                    let thread_arc = THREAD_MTTHREAD.with(|mtt| Arc::clone(&mtt.tracing));
+                    if !thread_arc.load(Ordering::Relaxed).is_null() {
+                        // This thread is already tracing another Location.
+                        loc.unlock();
+                        return TransitionLocation::NoAction;
+                    }
+                    hl.kind = HotLocationKind::Tracing(Arc::clone(&thread_arc));
+                    thread_arc.store(hl as _, Ordering::Relaxed);
+                    loc.unlock();
+                    TransitionLocation::StartTracing(self.tracing_kind())
+                }
                 HotLocationKind::Tracing(ref tracing_arc) => {
                     let thread_arc = THREAD_MTTHREAD.with(|mtt| Arc::clone(&mtt.tracing));
                     if !thread_arc.load(Ordering::Relaxed).is_null() {
@@ -345,15 +579,15 @@ impl MT {
                         }
                         // ...and it's this location: we have therefore finished tracing the loop.
                         thread_arc.store(std::ptr::null_mut(), Ordering::Relaxed);
-                        let mtx = Arc::new(Mutex::new(None));
-                        hl.kind = HotLocationKind::Compiling(Arc::clone(&mtx));
+                        let mtx = Arc::new(CompilingTrace::new());
+                        hl.kind = compiling_unless_dont_trace(hl, &mtx);
                         loc.unlock();
                         TransitionLocation::StopTracing(mtx)
                     } else {
                         // This thread isn't tracing anything
                         if Arc::strong_count(tracing_arc) == 1 {
                             // Another thread was tracing this location but it's terminated.
-                            if hl.trace_failure < self.trace_failure_threshold() {
+                            if hl.trace_failure < self.trace_failure_threshold() && !hl.dont_trace {
                                 // Let's try tracing the location again in this thread.
                                 hl.trace_failure += 1;
                                 hl.kind = HotLocationKind::Tracing(Arc::clone(&thread_arc));
@@ -380,49 +614,581 @@ impl MT {
         }
     }
 
-    /// Add a compilation job for `sir` to the global work queue.
+    /// Return the hot threshold for the reprofiled `hl`. Each reprofile doubles the threshold
+    /// (starting from `hl`'s own threshold, if it has one), so that a location whose traces keep
+    /// turning out to be unsuitable is retraced ever less often.
+    fn reprofile_threshold(&self, hl: &HotLocation) -> HotThreshold {
+        let hot_threshold = hl.hot_threshold.unwrap_or_else(|| self.hot_threshold());
+        let reprofiles = hl.reprofiles;
+        if hot_threshold == 0 {
+            0
+        } else if hot_threshold.leading_zeros() < u32::from(reprofiles) {
+            HotThreshold::MAX
+        } else {
+            hot_threshold << reprofiles
+        }
+    }
+
+    /// Record that tracing or compiling the trace for `hl` failed for `reason`. If `hl` hasn't
+    /// failed too often, it is reprofiled so that it can be traced again; otherwise it is marked
+    /// as never to be traced again.
+    fn trace_failed(&self, hl: &mut HotLocation, reason: TraceFailure) {
+        if hl.trace_failure < self.trace_failure_threshold() {
+            hl.trace_failure += 1;
+            hl.reprofiles = hl.reprofiles.saturating_add(1);
+            hl.kind = HotLocationKind::Counting(0);
+        } else {
+            hl.kind = HotLocationKind::DontTrace;
+        }
+        hl.last_failure = Some(reason);
+    }
+
+    /// Record that a guard in the root trace `ctr` failed. If `ctr` fails guards too often, it
+    /// isn't worth executing, so it is marked as unsuitable, and its `Location` is reprofiled (see
+    /// [reprofile_if_unsuitable]) the next time the `Location` is reached. The `Location` itself
+    /// isn't touched here, since it may be dropped while its trace is executing.
+    fn guard_failed(&self, ctr: &CompiledTrace) {
+        // Traces are normally exited through a guard failure when the interpreter leaves the
+        // loop, so we only consider a trace unsuitable if most of its executions fail a guard.
+        let guard_failures = ctr.guard_failed();
+        if guard_failures < self.guard_failure_threshold()
+            || guard_failures.saturating_mul(2) <= ctr.executions()
+        {
+            return;
+        }
+        ctr.mark_unsuitable();
+    }
+
+    /// Record that the guard with ID `guard_idx` in `ctr` failed while executing the trace compiled
+    /// for `loc`. If the guard has failed often enough, start tracing a side trace for it: the
+    /// caller must then immediately resume execution in the AOT code at the guard. `loc` is never
+    /// dereferenced, so it needn't still be alive.
+    fn maybe_start_side_tracing(
+        &self,
+        loc: *const Location,
+        ctr: Arc<CompiledTrace>,
+        guard_idx: usize,
+    ) {
+        let guard = ctr.guard(guard_idx);
+        if guard.failed() < self.side_trace_threshold()
+            || guard.side_trace_failures() >= self.trace_failure_threshold()
+        {
+            return;
+        }
+        if THREAD_MTTHREAD.with(|mtt| {
+            // We can't side trace if this thread is already tracing.
+            if !mtt.tracing.load(Ordering::Relaxed).is_null() || !guard.claim_side_trace() {
+                return false;
+            }
+            *mtt.side_tracing.borrow_mut() = Some((Arc::clone(&ctr), guard_idx, loc));
+            true
+        }) {
+            self.start_tracing(self.tracing_kind(), loc, Some((&ctr, guard_idx)));
+        }
+    }
+
+    /// If this thread is tracing, stop tracing, because the control point of a `Location` whose
+    /// compiled trace we are about to execute has been reached. Compiled traces are opaque to the
+    /// tracer, so the trace ends with this `Location`, and when it is compiled it jumps straight to
+    /// this `Location`'s compiled trace. This allows, for example, the trace of an outer loop to be
+    /// built on top of the already compiled trace of an inner loop.
+    ///
+    /// Note that the outer trace can't record the call of the inner compiled trace as a step and
+    /// then carry on: a compiled trace only returns to the interpreter when it leaves its loop
+    /// (e.g. when a guard fails), by which point the interpreter's state has been reconstructed
+    /// from the trace's deoptimisation information rather than from anything the outer trace
+    /// could refer to. Instead, the outer trace is stitched to the inner one.
+    fn stop_tracing_at_compiled(&self) {
+        let (thread_arc, tracing_loc) = match self.claim_tracing_location() {
+            Some(x) => x,
+            None => return,
+        };
+        let mtx = match unsafe { &*tracing_loc }.lock() {
+            Ok(ls) => {
+                let hl = unsafe { ls.hot_location() };
+                let mtx = match &hl.kind {
+                    HotLocationKind::Tracing(x) if Arc::ptr_eq(x, &thread_arc) => {
+                        let mtx = Arc::new(CompilingTrace::new());
+                        hl.kind = compiling_unless_dont_trace(hl, &mtx);
+                        Some(mtx)
+                    }
+                    _ => None,
+                };
+                unsafe { &*tracing_loc }.unlock();
+                mtx
+            }
+            Err(()) => None,
+        };
+        // From this point on, `tracing_loc` may be dropped at any time, so we must not dereference
+        // it.
+        thread_arc.store(std::ptr::null_mut(), Ordering::Release);
+        match (self.stop_tracing(tracing_loc, false), mtx) {
+            ((trace_id, Ok(utrace)), Some(mtx)) => {
+                self.queue_compile_job(trace_id, utrace, mtx, tracing_loc);
+            }
+            ((_, Err(e)), Some(mtx)) => {
+                // The next thread to encounter the location will move it out of the Compiling
+                // state.
+                mtx.result.lock().replace(Err(TraceFailure::Tracing(e)));
+            }
+            (_, None) => (),
+        }
+    }
+
+    /// If this thread is tracing, stop tracing and discard the trace. The interpreter should call
+    /// this when it knows that the trace will be of no use (e.g. because an exception is unwinding
+    /// past the loop being traced). The `Location` being traced can be traced again once it is hot
+    /// again: an aborted trace does not count towards the trace failure threshold.
+    pub fn abort_tracing(&self) {
+        if let Some((parent, guard_idx, root_loc)) =
+            THREAD_MTTHREAD.with(|mtt| mtt.side_tracing.take())
+        {
+            self.discard_trace(root_loc, "aborted by the interpreter");
+            parent.guard(guard_idx).release_side_trace();
+        } else {
+            self.abandon_tracing(None);
+        }
+    }
+
+    /// If this thread is tracing a root trace, stop tracing and discard the trace. If `reason` is
+    /// `Some`, record that tracing the `Location` being traced failed for that reason; otherwise
+    /// send the `Location` back to counting, so that it can be traced again.
+    fn abandon_tracing(&self, reason: Option<TraceFailure>) {
+        let (thread_arc, tracing_loc) = match self.claim_tracing_location() {
+            Some(x) => x,
+            None => return,
+        };
+        self.discard_trace(
+            tracing_loc,
+            &reason.as_ref().map_or_else(
+                || "aborted by the interpreter".to_owned(),
+                |x| x.to_string(),
+            ),
+        );
+        let tracing_loc = unsafe { &*tracing_loc };
+        if let Ok(ls) = tracing_loc.lock() {
+            let hl = unsafe { ls.hot_location() };
+            if let HotLocationKind::Tracing(x) = &hl.kind {
+                if Arc::ptr_eq(x, &thread_arc) {
+                    match reason {
+                        Some(reason) => self.trace_failed(hl, reason),
+                        None => hl.kind = HotLocationKind::Counting(0),
+                    }
+                }
+            }
+            tracing_loc.unlock();
+        }
+        thread_arc.store(std::ptr::null_mut(), Ordering::Release);
+    }
+
+    /// If this thread is tracing a root trace, claim the `Location` being traced, so that it can't
+    /// be dropped until this thread sets its `tracing` pointer to `null`, and return the `tracing`
+    /// pointer and the `Location`. If this thread is not tracing, or the `Location` being traced
+    /// has been dropped (in which case the trace is discarded), return `None`.
+    fn claim_tracing_location(&self) -> Option<(Arc<AtomicPtr<HotLocation>>, *const Location)> {
+        let (thread_arc, tracing_loc) =
+            THREAD_MTTHREAD.with(|mtt| (Arc::clone(&mtt.tracing), mtt.tracing_loc.get()));
+        let hl_ptr = thread_arc.load(Ordering::Acquire);
+        if hl_ptr.is_null() {
+            return None;
+        }
+        // If the `Location` is dropped before we can claim it, the compare-and-exchange fails.
+        if hl_ptr == TRACING_DROPPED
+            || thread_arc
+                .compare_exchange(hl_ptr, TRACING_CLAIMED, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+        {
+            self.discard_trace(tracing_loc, "location dropped");
+            thread_arc.store(std::ptr::null_mut(), Ordering::Relaxed);
+            return None;
+        }
+        Some((thread_arc, tracing_loc))
+    }
+
+    /// Stop tracing in this thread, discarding the trace, which was being traced for `loc`, for
+    /// `reason`.
+    fn discard_trace(&self, loc: *const Location, reason: &str) {
+        let _ = yktrace::stop_tracing();
+        let (trace_id, started) = THREAD_MTTHREAD
+            .with(|mtt| mtt.tracing_started.take())
+            .unwrap();
+        let time = started.elapsed();
+        self.stats.trace_stopped(false, time);
+        self.events.log("tracing-aborted", |r| {
+            r.u64("trace_id", trace_id)
+                .ptr("location", loc)
+                .duration("tracing_ns", time)
+                .str("reason", reason)
+        });
+    }
+
+    /// Start tracing in this thread, giving the trace a new ID. If `side` is `Some((parent,
+    /// guard_idx))`, this is a side trace for the guard with ID `guard_idx` in `parent`, and `loc`
+    /// is the [Location] of `parent`'s root trace.
+    fn start_tracing(
+        &self,
+        kind: TracingKind,
+        loc: *const Location,
+        side: Option<(&CompiledTrace, usize)>,
+    ) {
+        let trace_id = NEXT_TRACE_ID.fetch_add(1, Ordering::Relaxed);
+        self.stats.trace_started();
+        match side {
+            Some((parent, guard_idx)) => {
+                self.events.emit(JitEvent::StartSideTracing, |r| {
+                    r.u64("trace_id", trace_id)
+                        .ptr("location", loc)
+                        .u64("parent_trace_id", parent.id())
+                        .u64("guard", guard_idx as u64)
+                });
+            }
+            None => {
+                self.events.emit(JitEvent::StartTracing, |r| {
+                    r.u64("trace_id", trace_id).ptr("location", loc)
+                });
+            }
+        }
+        THREAD_MTTHREAD.with(|mtt| {
+            mtt.tracing_started.set(Some((trace_id, Instant::now())));
+            mtt.trace_len.set(0);
+        });
+        if side.is_some() {
+            yktrace::start_side_tracing(kind);
+        } else {
+            yktrace::start_tracing(kind);
+        }
+    }
+
+    /// Stop tracing in this thread, returning the ID of the trace as well as the trace itself.
+    /// `loc` and `side` must be as passed to [Self::start_tracing] (`side` being true for a side
+    /// trace).
+    fn stop_tracing(
+        &self,
+        loc: *const Location,
+        side: bool,
+    ) -> (u64, Result<Box<dyn UnmappedTrace>, InvalidTraceError>) {
+        let utrace = yktrace::stop_tracing();
+        let (trace_id, started) = THREAD_MTTHREAD
+            .with(|mtt| mtt.tracing_started.take())
+            .unwrap();
+        let time = started.elapsed();
+        self.stats.trace_stopped(utrace.is_ok(), time);
+        let details = |r: LogRecord| {
+            r.u64("trace_id", trace_id)
+                .ptr("location", loc)
+                .duration("tracing_ns", time)
+        };
+        match &utrace {
+            Ok(_) if side => self.events.emit(JitEvent::StopSideTracing, details),
+            Ok(_) => self.events.emit(JitEvent::StopTracing, details),
+            Err(e) => self.events.log("tracing-aborted", |r| {
+                details(r).str("reason", &e.to_string())
+            }),
+        }
+        (trace_id, utrace)
+    }
+
+    /// Add a job to the global work queue which compiles `utrace` as a root trace for `loc`.
     fn queue_compile_job(
         &self,
+        trace_id: u64,
         utrace: Box<dyn UnmappedTrace>,
-        mtx: Arc<Mutex<Option<Box<CompiledTrace>>>>,
+        mtx: Arc<CompilingTrace>,
+        loc: *const Location,
     ) {
-        let tdk = self.trace_decoder_kind;
+        let tdk = self.tracing_backend.decoder();
+        // Raw pointers aren't `Send`, but `loc` is only used as a constant in the compiled trace
+        // and is never dereferenced.
+        let loc = loc as usize;
+        let compile_opts = Arc::clone(&self.compile_opts);
+        let stats = Arc::clone(&self.stats);
+        let events = Arc::clone(&self.events);
+        // The job is no longer wanted if `loc` has been dropped, or has left the Compiling state
+        // (e.g. because it's been marked as not to be traced), since `mtx` is then only referenced
+        // by the job itself.
+        let weak = Arc::downgrade(&mtx);
+        let weak2 = Weak::clone(&weak);
         let do_compile = move || {
-            // FIXME: if mapping or tracing fails we don't want to abort, but in order to do that,
-            // we'll need to move the location into something other than the Compiling state.
-            let irtrace = match utrace.map(tdk) {
+            // If the `Location` for which we're compiling has been dropped in the meantime, we
+            // hold the only reference to `mtx`, so whatever we put in it is freed as soon as we
+            // return.
+            let details = |r: LogRecord| {
+                r.u64("trace_id", trace_id)
+                    .ptr("location", loc as *const c_void)
+            };
+            let start = Instant::now();
+            let irtrace = utrace.map(tdk);
+            let mapping_time = start.elapsed();
+            stats.trace_mapped(mapping_time);
+            let irtrace = match irtrace {
                 Ok(x) => x,
-                Err(e) => todo!("{e:?}"),
+                Err(e) => {
+                    events.emit(JitEvent::TraceCompilationAborted(e.to_string()), |r| {
+                        details(r).duration("mapping_ns", mapping_time)
+                    });
+                    stats.trace_compiled(false, Duration::ZERO);
+                    mtx.result.lock().replace(Err(TraceFailure::Mapping(e)));
+                    return;
+                }
             };
-            match irtrace.compile() {
+            if Arc::strong_count(&mtx) == 1 {
+                // The trace is no longer wanted (e.g. because `loc` has been dropped while we
+                // were mapping the trace), so there's no point compiling it.
+                return;
+            }
+            let start = Instant::now();
+            let compiled = irtrace.compile(loc as *const c_void, &compile_opts);
+            let compile_time = start.elapsed();
+            stats.trace_compiled(compiled.is_ok(), compile_time);
+            let details = |r: LogRecord| {
+                details(r)
+                    .u64("trace_len", irtrace.len() as u64)
+                    .duration("mapping_ns", mapping_time)
+                    .duration("compile_ns", compile_time)
+            };
+            match compiled {
                 Ok((codeptr, di_tmpfile)) => {
-                    let ct = Box::new(CompiledTrace::new(codeptr, di_tmpfile));
-                    // FIXME: although we've now put the compiled trace into the `HotLocation`,
-                    // there's no guarantee that the `Location` for which we're compiling will ever
-                    // be executed again. In such a case, the memory has, in essence, leaked.
-                    mtx.lock().replace(ct);
+                    events.log("trace-compiled", details);
+                    let ct = Arc::new(CompiledTrace::new(trace_id, codeptr, di_tmpfile));
+                    mtx.result.lock().replace(Ok(ct));
                 }
-                Err(_e) => {
-                    // FIXME: Properly handle failed trace compilation, e.g. depending on the
-                    // reason for the failure we might want to block this location from being
-                    // traced again or only temporarily put it on hold and try again later.
-                    // See: https://github.com/ykjit/yk/issues/612
-                    // FIXME: Improve jit-state message.
-                    // See: https://github.com/ykjit/yk/issues/611
-                    //
-                    #[cfg(feature = "yk_jitstate_debug")]
-                    print_jit_state("trace-compilation-aborted");
+                Err(e) => {
+                    events.emit(JitEvent::TraceCompilationAborted(e.to_string()), details);
+                    mtx.result
+                        .lock()
+                        .replace(Err(TraceFailure::Compiling(e.to_string())));
                 }
             };
         };
 
-        #[cfg(feature = "yk_testing")]
-        if *SERIALISE_COMPILATION {
+        if self.serialise_compilation() {
+            do_compile();
+            return;
+        }
+
+        // `mtx` was created just before the job was queued, so its hits are those since then.
+        self.queue_job(Job::new(
+            Box::new(do_compile),
+            Box::new(move || weak.upgrade().map_or(0, |x| x.hits.load(Ordering::Relaxed))),
+            Box::new(move || weak2.strong_count() <= 1),
+        ));
+    }
+
+    /// Add a job to the global work queue which compiles `utrace` as a side trace for the guard
+    /// with ID `guard_idx` in `parent`, installing it in `parent` if compilation succeeds.
+    fn queue_side_trace_compile_job(
+        &self,
+        trace_id: u64,
+        utrace: Box<dyn UnmappedTrace>,
+        parent: Arc<CompiledTrace>,
+        guard_idx: usize,
+        root_loc: *const Location,
+    ) {
+        let tdk = self.tracing_backend.decoder();
+        // Raw pointers aren't `Send`, but `root_loc` is only used as a constant in the compiled
+        // side trace and is never dereferenced.
+        let root_loc = root_loc as usize;
+        let compile_opts = Arc::clone(&self.compile_opts);
+        let stats = Arc::clone(&self.stats);
+        let events = Arc::clone(&self.events);
+        // The more often the guard fails, the more urgently it needs a side trace. The job is no
+        // longer wanted if `parent` has been discarded, since it is then only referenced by the
+        // job itself.
+        let queued_failures = parent.guard(guard_idx).failures();
+        let weak = Arc::downgrade(&parent);
+        let weak2 = Weak::clone(&weak);
+        let do_compile = move || {
+            let start = Instant::now();
+            let irtrace = utrace.map(tdk);
+            let mapping_time = start.elapsed();
+            stats.trace_mapped(mapping_time);
+            let trace_len = irtrace.as_ref().map_or(0, |x| x.len());
+            if Arc::strong_count(&parent) == 1 {
+                // `parent` has been discarded (e.g. because its `Location` has been dropped) while
+                // we were mapping the trace, so there's no point compiling it.
+                return;
+            }
+            let start = Instant::now();
+            let compiled = irtrace.map_err(|e| e.to_string()).and_then(|irtrace| {
+                irtrace
+                    .compile_side(&parent, guard_idx, root_loc as *const c_void, &compile_opts)
+                    .map_err(|e| e.to_string())
+            });
+            let compile_time = start.elapsed();
+            stats.trace_compiled(compiled.is_ok(), compile_time);
+            let details = |r: LogRecord| {
+                r.u64("trace_id", trace_id)
+                    .ptr("location", root_loc as *const c_void)
+                    .u64("parent_trace_id", parent.id())
+                    .u64("guard", guard_idx as u64)
+                    .u64("trace_len", trace_len as u64)
+                    .duration("mapping_ns", mapping_time)
+                    .duration("compile_ns", compile_time)
+            };
+            match compiled {
+                Ok((codeptr, di_tmpfile)) => {
+                    events.log("trace-compiled", details);
+                    let ct = Arc::new(CompiledTrace::new(trace_id, codeptr, di_tmpfile));
+                    parent.install_side_trace(guard_idx, ct);
+                }
+                Err(e) => {
+                    events.emit(JitEvent::TraceCompilationAborted(e), details);
+                    parent.guard(guard_idx).side_trace_failed();
+                }
+            }
+        };
+
+        if self.serialise_compilation() {
             do_compile();
             return;
         }
 
-        self.queue_job(Box::new(do_compile));
+        self.queue_job(Job::new(
+            Box::new(do_compile),
+            Box::new(move || {
+                weak.upgrade().map_or(0, |x| {
+                    u64::from(
+                        x.guard(guard_idx)
+                            .failures()
+                            .saturating_sub(queued_failures),
+                    )
+                })
+            }),
+            Box::new(move || weak2.strong_count() <= 1),
+        ));
+    }
+}
+
+impl Drop for MT {
+    fn drop(&mut self) {
+        // Cancel the compilation jobs that haven't started yet, wait for those that have to
+        // finish, and then wait for the worker threads to exit. Since worker threads hold no
+        // reference to this `MT`, they can only be waiting for, or running, jobs.
+        let (cv, mtx) = &*self.job_queue;
+        let cancelled = {
+            let mut lock = mtx.lock();
+            lock.shutdown = true;
+            mem::take(&mut lock.jobs)
+        };
+        cv.notify_all();
+        // Cancelled jobs are dropped outside the lock, in case dropping them is slow.
+        drop(cancelled);
+        for handle in self.worker_threads.get_mut().drain(..) {
+            // A worker thread only panics if a job panicked, in which case there's nothing useful
+            // we can do.
+            let _ = handle.join();
+        }
+    }
+}
+
+/// How long the priorities of queued jobs are used for before [JobQueue::pop] recomputes them.
+const JOB_PRIORITY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The compilation jobs waiting to be run by an [MT]'s worker threads.
+#[derive(Default)]
+struct JobQueue {
+    /// The jobs, in the order they were queued.
+    jobs: VecDeque<Job>,
+    /// When the priorities of `jobs` were last recomputed, if they have been.
+    refreshed: Option<Instant>,
+    /// Set when the [MT] is being dropped, at which point worker threads exit as soon as they have
+    /// finished their current job.
+    shutdown: bool,
+}
+
+impl JobQueue {
+    fn push(&mut self, mut job: Job) {
+        job.priority = job.rate();
+        self.jobs.push_back(job);
+    }
+
+    /// Remove, and return, the job with the highest priority, preferring the job queued first if
+    /// several jobs have the same priority. Cancelled jobs are discarded. Priorities change over
+    /// time, but recomputing them means calling into every job, so they are only recomputed (and
+    /// cancelled jobs discarded) if they are older than [JOB_PRIORITY_INTERVAL].
+    fn pop(&mut self) -> Option<Job> {
+        if !matches!(self.refreshed, Some(x) if x.elapsed() < JOB_PRIORITY_INTERVAL) {
+            self.jobs.retain(|x| !(x.cancelled)());
+            for job in self.jobs.iter_mut() {
+                job.priority = job.rate();
+            }
+            self.refreshed = Some(Instant::now());
+        }
+        loop {
+            let mut best: Option<(usize, u64)> = None;
+            for (i, job) in self.jobs.iter().enumerate() {
+                if !matches!(best, Some((_, x)) if job.priority <= x) {
+                    best = Some((i, job.priority));
+                }
+            }
+            let job = self.jobs.remove(best?.0).unwrap();
+            if !(job.cancelled)() {
+                return Some(job);
+            }
+        }
+    }
+}
+
+/// A job for a worker thread, typically compiling a trace.
+struct Job {
+    run: Box<dyn FnOnce() + Send>,
+    /// How many times the trace compiled by the job has been wanted since the job was queued
+    /// (e.g. how often the trace's `Location` has been reached, or the guard that the trace is a
+    /// side trace for has failed).
+    hits: Box<dyn Fn() -> u64 + Send>,
+    /// When the job was queued.
+    queued: Instant,
+    /// How urgently the job should be run, as last computed by [Job::rate]: the higher, the more
+    /// urgently.
+    priority: u64,
+    /// Is the job's result no longer wanted? If so, the job is discarded without being run.
+    cancelled: Box<dyn Fn() -> bool + Send>,
+}
+
+impl Job {
+    fn new(
+        run: Box<dyn FnOnce() + Send>,
+        hits: Box<dyn Fn() -> u64 + Send>,
+        cancelled: Box<dyn Fn() -> bool + Send>,
+    ) -> Self {
+        Self {
+            run,
+            hits,
+            queued: Instant::now(),
+            priority: 0,
+            cancelled,
+        }
+    }
+
+    /// How often, per second, the trace compiled by this job has been wanted since the job was
+    /// queued. Root traces and side traces are thus prioritised on the same scale.
+    fn rate(&self) -> u64 {
+        let age = u64::try_from(self.queued.elapsed().as_micros()).unwrap_or(u64::MAX);
+        (self.hits)().saturating_mul(1_000_000) / age.max(1)
+    }
+}
+
+/// Why tracing or compiling a trace for a [Location] failed.
+#[derive(Debug)]
+pub enum TraceFailure {
+    /// The tracer could not produce a trace.
+    Tracing(InvalidTraceError),
+    /// The trace could not be mapped to IR.
+    Mapping(InvalidTraceError),
+    /// The IR trace could not be compiled.
+    Compiling(String),
+    /// Tracing was abandoned because the trace grew longer than the maximum trace length.
+    TooLong,
+}
+
+impl fmt::Display for TraceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceFailure::Tracing(e) => write!(f, "tracing failed: {e}"),
+            TraceFailure::Mapping(e) => write!(f, "mapping failed: {e}"),
+            TraceFailure::Compiling(e) => write!(f, "compilation failed: {e}"),
+            TraceFailure::TooLong => write!(f, "trace too long"),
+        }
     }
 }
 
@@ -430,9 +1196,11 @@ impl MT {
 /// be accessed from within a single thread.
 pub struct MTThread {
     /// Is this thread currently tracing something? If so, the [AtomicPtr] points to the
-    /// [HotLocation] currently being traced. If not, the [AtomicPtr] is `null`. Note that no
-    /// reads/writes must depend on the [AtomicPtr], so all loads/stores can be
-    /// [Ordering::Relaxed].
+    /// [HotLocation] currently being traced. If not, the [AtomicPtr] is `null`. The [AtomicPtr] is
+    /// only used to identify the [HotLocation], and is never dereferenced. While this thread is
+    /// tracing, two other values are possible: [TRACING_CLAIMED], while this thread is updating
+    /// the [Location] being traced, which stops the [Location] being dropped in the meantime; and
+    /// [TRACING_DROPPED], if the [Location] being traced has been dropped.
     ///
     /// We wrap the [AtomicPtr] in an [Arc] to serve a second purpose: when we start tracing, we
     /// `clone` the [Arc] and store it in [HotLocation::Tracing]. This allows another thread to
@@ -445,6 +1213,23 @@ pub struct MTThread {
     /// mechanism can't be fully relied upon: however, we can't monitor thread death in any other
     /// reasonable way, so this will have to do.
     tracing: Arc<AtomicPtr<HotLocation>>,
+    /// Is this thread currently tracing a side trace? If so, this records the compiled trace and
+    /// the ID of the guard the side trace starts from, and the [Location] for which the root trace
+    /// was compiled. Side tracing is not recorded in `tracing`, since it does not involve a
+    /// [HotLocation].
+    side_tracing: RefCell<Option<(Arc<CompiledTrace>, usize, *const Location)>>,
+    /// If this thread is tracing (see `tracing`), the [Location] being traced.
+    tracing_loc: Cell<*const Location>,
+    /// If this thread is tracing (a root or a side trace), the ID given to the trace and when
+    /// tracing started.
+    tracing_started: Cell<Option<(u64, Instant)>>,
+    /// If this thread is tracing a root trace, how many control points it has passed through
+    /// while tracing.
+    trace_len: Cell<usize>,
+    /// The compiled traces (and the [Location]s they were compiled for) that compiled traces
+    /// executing in this thread have stitched to. Holding a reference here ensures that a trace
+    /// can't be freed while it's executing.
+    stitched: RefCell<Vec<(*const Location, Arc<CompiledTrace>)>>,
     // Raw pointers are neither send nor sync.
     _dont_send_or_sync_me: PhantomData<*mut ()>,
 }
@@ -453,18 +1238,89 @@ impl MTThread {
     fn new() -> Self {
         MTThread {
             tracing: Arc::new(AtomicPtr::new(std::ptr::null_mut())),
+            side_tracing: RefCell::new(None),
+            tracing_loc: Cell::new(std::ptr::null()),
+            tracing_started: Cell::new(None),
+            trace_len: Cell::new(0),
+            stitched: RefCell::new(Vec::new()),
             _dont_send_or_sync_me: PhantomData,
         }
     }
 }
 
+/// If `loc` has a compiled trace, return a pointer to its [yktrace::CompiledTrace::exec_info], so
+/// that a side trace which has reached `loc`'s control point can jump directly to it. Otherwise,
+/// return null, in which case execution must continue in the interpreter.
+pub fn stitch_location(loc: &Location) -> *const c_void {
+    if loc.load(Ordering::Relaxed).is_counting() {
+        return std::ptr::null();
+    }
+    // We're in the middle of executing JIT code, so we don't wait for other threads.
+    let ctr = match loc.try_lock() {
+        Some(ls) => {
+            let hl = unsafe { ls.hot_location() };
+            reprofile_if_unsuitable(hl);
+            let ctr = match &hl.kind {
+                HotLocationKind::Compiled(ctr) => Some(Arc::clone(ctr)),
+                _ => None,
+            };
+            loc.unlock();
+            ctr
+        }
+        None => None,
+    };
+    match ctr {
+        Some(ctr) => {
+            let exec_info = ctr.exec_info();
+            THREAD_MTTHREAD.with(|mtt| {
+                let mut stitched = mtt.stitched.borrow_mut();
+                if !stitched.iter().any(|(_, x)| Arc::ptr_eq(x, &ctr)) {
+                    stitched.push((loc as *const _, ctr));
+                }
+            });
+            exec_info
+        }
+        None => std::ptr::null(),
+    }
+}
+
+/// If `hl`'s compiled trace has been marked as unsuitable (see [MT::guard_failed]), reprofile `hl`
+/// so that it can be traced again (unless it has been marked as not to be traced), returning
+/// `true`. The caller must hold `hl`'s `Location`'s lock.
+fn reprofile_if_unsuitable(hl: &mut HotLocation) -> bool {
+    match &hl.kind {
+        HotLocationKind::Compiled(ctr) if ctr.is_unsuitable() => {
+            hl.reprofiles = hl.reprofiles.saturating_add(1);
+            hl.kind = if hl.dont_trace {
+                HotLocationKind::DontTrace
+            } else {
+                HotLocationKind::Counting(0)
+            };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Return the state that `hl`, whose trace has just been recorded, should move to: normally
+/// Compiling, with `mtx` receiving the result of compiling the trace. If `hl` was marked as not to
+/// be traced while it was being traced, it moves straight to DontTrace instead, and since nothing
+/// else then references `mtx`, the trace is never compiled.
+fn compiling_unless_dont_trace(hl: &HotLocation, mtx: &Arc<CompilingTrace>) -> HotLocationKind {
+    if hl.dont_trace {
+        HotLocationKind::DontTrace
+    } else {
+        HotLocationKind::Compiling(Arc::clone(mtx))
+    }
+}
+
 /// What action should a caller of `MT::transition_location` take?
 #[derive(Debug)]
 enum TransitionLocation {
     NoAction,
-    Execute(*const CompiledTrace),
+    Execute(Arc<CompiledTrace>),
     StartTracing(TracingKind),
-    StopTracing(Arc<Mutex<Option<Box<CompiledTrace>>>>),
+    StopTracing(Arc<CompilingTrace>),
 }
 
 #[cfg(test)]
@@ -474,7 +1330,7 @@ impl PartialEq for TransitionLocation {
         match (self, other) {
             (TransitionLocation::NoAction, TransitionLocation::NoAction) => true,
             (TransitionLocation::Execute(p1), TransitionLocation::Execute(p2)) => {
-                std::ptr::eq(p1, p2)
+                Arc::ptr_eq(p1, p2)
             }
             (TransitionLocation::StartTracing(x), TransitionLocation::StartTracing(y)) => x == y,
             (x, y) => todo!("{:?} {:?}", x, y),
@@ -486,9 +1342,10 @@ impl PartialEq for TransitionLocation {
 mod tests {
     extern crate test;
     use super::*;
-    use crate::location::HotLocationKindDiscriminants;
+    use crate::location::{HotLocationKindDiscriminants, LocationKind, LocationState};
     use std::{convert::TryFrom, hint::black_box, sync::atomic::AtomicU64, thread};
     use test::bench::Bencher;
+    use yktrace::Guard;
 
     fn hotlocation_discriminant(loc: &Location) -> Option<HotLocationKindDiscriminants> {
         match loc.lock() {
@@ -528,8 +1385,413 @@ mod tests {
                     hotlocation_discriminant(&loc),
                     Some(HotLocationKindDiscriminants::Compiling)
                 );
-                mtx.lock()
-                    .replace(Box::new(unsafe { CompiledTrace::new_null() }));
+                mtx.result
+                    .lock()
+                    .replace(Ok(Arc::new(unsafe { CompiledTrace::new_null() })));
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::Execute(_)
+        ));
+    }
+
+    #[test]
+    fn compiled_traces_outlive_their_location() {
+        // A thread may still be executing a compiled trace when its `Location` is dropped: the
+        // trace must only be freed when nothing references it any more.
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(0);
+        let loc = Location::new();
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        let mtx = match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => mtx,
+            _ => unreachable!(),
+        };
+        mtx.result
+            .lock()
+            .replace(Ok(Arc::new(unsafe { CompiledTrace::new_null() })));
+        drop(mtx);
+        let ctr = match mt.transition_location(&loc) {
+            TransitionLocation::Execute(ctr) => ctr,
+            _ => unreachable!(),
+        };
+        assert_eq!(Arc::strong_count(&ctr), 2);
+        drop(loc);
+        assert_eq!(Arc::strong_count(&ctr), 1);
+    }
+
+    #[test]
+    fn dropping_a_location_being_traced() {
+        // If a `Location` is dropped while a thread is tracing it, the thread must discard its
+        // trace without touching the `Location` again, after which it can trace other
+        // `Location`s.
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(0);
+        let loc1 = Location::new();
+        assert!(matches!(
+            mt.transition_location(&loc1),
+            TransitionLocation::StartTracing(_)
+        ));
+        THREAD_MTTHREAD.with(|mtt| {
+            mtt.tracing_loc.set(&loc1);
+            mtt.tracing_started.set(Some((0, Instant::now())));
+        });
+        drop(loc1);
+        assert_eq!(
+            THREAD_MTTHREAD.with(|mtt| mtt.tracing.load(Ordering::Relaxed)),
+            TRACING_DROPPED
+        );
+        assert!(mt.claim_tracing_location().is_none());
+        assert!(THREAD_MTTHREAD.with(|mtt| mtt.tracing.load(Ordering::Relaxed).is_null()));
+        assert_eq!(mt.stats().traces_aborted, 1);
+        let loc2 = Location::new();
+        assert!(matches!(
+            mt.transition_location(&loc2),
+            TransitionLocation::StartTracing(_)
+        ));
+    }
+
+    #[test]
+    fn failed_compilation_is_retried() {
+        // If compiling a trace fails, the location must go back to counting towards an ever
+        // increasing hot threshold, until it has failed too often, at which point it must be
+        // marked DontTrace.
+        let hot_thrsh = 2;
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(hot_thrsh);
+        mt.set_trace_failure_threshold(2);
+        let loc = Location::new();
+        for _ in 0..hot_thrsh {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        }
+        for i in 0..=mt.trace_failure_threshold() {
+            assert_eq!(
+                mt.transition_location(&loc),
+                TransitionLocation::StartTracing(mt.tracing_kind())
+            );
+            match mt.transition_location(&loc) {
+                TransitionLocation::StopTracing(mtx) => {
+                    mtx.result
+                        .lock()
+                        .replace(Err(TraceFailure::Compiling("failed".to_owned())));
+                }
+                _ => unreachable!(),
+            }
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+            if i < mt.trace_failure_threshold() {
+                assert_eq!(
+                    hotlocation_discriminant(&loc),
+                    Some(HotLocationKindDiscriminants::Counting)
+                );
+                for _ in 0..hot_thrsh << (i + 1) {
+                    assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+                }
+            }
+        }
+        assert_eq!(
+            hotlocation_discriminant(&loc),
+            Some(HotLocationKindDiscriminants::DontTrace)
+        );
+        assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+    }
+
+    #[test]
+    fn reprofile_on_guard_failures() {
+        // A trace which fails guards in most of its executions must send its location back to
+        // counting, with a higher threshold than before.
+        let hot_thrsh = 2;
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(hot_thrsh);
+        mt.set_guard_failure_threshold(2);
+        let loc = Location::new();
+        for _ in 0..hot_thrsh {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        }
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => {
+                mtx.result
+                    .lock()
+                    .replace(Ok(Arc::new(unsafe { CompiledTrace::new_null() })));
+            }
+            _ => unreachable!(),
+        }
+        let ctr = match mt.transition_location(&loc) {
+            TransitionLocation::Execute(ctr) => ctr,
+            _ => unreachable!(),
+        };
+
+        // Guard failures in a minority of executions are expected (e.g. when the interpreter leaves
+        // the loop), so they mustn't cause the location to be reprofiled.
+        for _ in 0..10 {
+            ctr.executing();
+        }
+        mt.guard_failed(&ctr);
+        mt.guard_failed(&ctr);
+        assert!(!ctr.is_unsuitable());
+        for _ in 0..4 {
+            mt.guard_failed(&ctr);
+        }
+        assert!(ctr.is_unsuitable());
+        // The location is only reprofiled when it is next reached.
+        assert_eq!(
+            hotlocation_discriminant(&loc),
+            Some(HotLocationKindDiscriminants::Compiled)
+        );
+        assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        assert_eq!(
+            hotlocation_discriminant(&loc),
+            Some(HotLocationKindDiscriminants::Counting)
+        );
+
+        // The location must now count up to twice the hot threshold before being retraced.
+        for _ in 0..hot_thrsh * 2 {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        }
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+    }
+
+    #[test]
+    fn drop_joins_worker_threads() {
+        // Dropping an `MT` must cancel the jobs that haven't started, and wait for those that
+        // have to finish, leaving no worker threads running.
+        let mt = MT::new().unwrap();
+        mt.set_max_worker_threads(2);
+        let started = Arc::new(AtomicUsize::new(0));
+        let finished = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let started = Arc::clone(&started);
+            let finished = Arc::clone(&finished);
+            mt.queue_job(Job::new(
+                Box::new(move || {
+                    started.fetch_add(1, Ordering::Relaxed);
+                    thread::sleep(Duration::from_millis(10));
+                    finished.fetch_add(1, Ordering::Relaxed);
+                }),
+                Box::new(|| 0),
+                Box::new(|| false),
+            ));
+        }
+        while started.load(Ordering::Relaxed) == 0 {
+            thread::yield_now();
+        }
+        drop(mt);
+        // Every job has either finished or been dropped without running.
+        assert_eq!(Arc::strong_count(&started), 1);
+        assert_eq!(
+            started.load(Ordering::Relaxed),
+            finished.load(Ordering::Relaxed)
+        );
+        assert!(finished.load(Ordering::Relaxed) < 100);
+    }
+
+    #[test]
+    fn job_queue_priorities() {
+        // Jobs are run highest priority first, then in the order they were queued, and cancelled
+        // jobs are never run.
+        let run = Arc::new(Mutex::new(Vec::new()));
+        let mut jq = JobQueue::default();
+        let queued = Instant::now();
+        for (i, hits, cancelled) in [(0, 1, false), (1, 5, false), (2, 5, true), (3, 5, false)] {
+            let run = Arc::clone(&run);
+            jq.push(Job {
+                run: Box::new(move || run.lock().push(i)),
+                hits: Box::new(move || hits),
+                queued,
+                priority: 0,
+                cancelled: Box::new(move || cancelled),
+            });
+        }
+        while let Some(job) = jq.pop() {
+            (job.run)();
+        }
+        assert_eq!(*run.lock(), vec![1, 3, 0]);
+
+        // A job which is wanted more often per unit of time since it was queued is run first,
+        // whether it compiles a root or a side trace.
+        let mut jq = JobQueue::default();
+        let now = Instant::now();
+        for (i, hits, age) in [(0, 1000, 1), (1, 10, 0)] {
+            let run = Arc::clone(&run);
+            jq.push(Job {
+                run: Box::new(move || run.lock().push(i)),
+                hits: Box::new(move || hits),
+                queued: now - Duration::from_secs(age),
+                priority: 0,
+                cancelled: Box::new(|| false),
+            });
+        }
+        run.lock().clear();
+        while let Some(job) = jq.pop() {
+            (job.run)();
+        }
+        assert_eq!(*run.lock(), vec![1, 0]);
+    }
+
+    #[test]
+    fn location_hot_threshold() {
+        // A Location's own hot threshold overrides the meta-tracer's, including when the Location
+        // is reprofiled, without disturbing the count.
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(100);
+        mt.set_trace_failure_threshold(5);
+        let loc = Location::new();
+        assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        loc.set_hot_threshold(3);
+        assert_eq!(loc.load(Ordering::Relaxed).count(), 1);
+        assert_eq!(loc.load(Ordering::Relaxed).hot_threshold(), Some(3));
+        for _ in 0..2 {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        }
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => {
+                mtx.result
+                    .lock()
+                    .replace(Err(TraceFailure::Compiling("failed".to_owned())));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        for _ in 0..6 {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        }
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+
+        // Thresholds too big to be encoded in a Location are clamped.
+        let loc = Location::new();
+        loc.set_hot_threshold(HotThreshold::MAX);
+        assert_eq!(
+            loc.load(Ordering::Relaxed).hot_threshold(),
+            Some(Location::MAX_HOT_THRESHOLD)
+        );
+    }
+
+    #[test]
+    fn location_dont_trace() {
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(0);
+        let loc = Location::new();
+        loc.set_dont_trace();
+        for _ in 0..10 {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        }
+        assert!(loc.load(Ordering::Relaxed).is_counting());
+
+        // A hot Location which is told not to be traced must not be traced again once its compiled
+        // trace has been discarded.
+        mt.set_guard_failure_threshold(1);
+        let loc = Location::new();
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => {
+                mtx.result
+                    .lock()
+                    .replace(Ok(Arc::new(unsafe { CompiledTrace::new_null() })));
+            }
+            _ => unreachable!(),
+        }
+        let ctr = match mt.transition_location(&loc) {
+            TransitionLocation::Execute(ctr) => ctr,
+            _ => unreachable!(),
+        };
+        loc.set_dont_trace();
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::Execute(_)
+        ));
+        mt.guard_failed(&ctr);
+        assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        assert_eq!(
+            hotlocation_discriminant(&loc),
+            Some(HotLocationKindDiscriminants::DontTrace)
+        );
+
+        // A trace which is being recorded when its Location is told not to be traced must not be
+        // compiled.
+        let loc = Location::new();
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        loc.set_dont_trace();
+        match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => assert_eq!(Arc::strong_count(&mtx), 1),
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            hotlocation_discriminant(&loc),
+            Some(HotLocationKindDiscriminants::DontTrace)
+        );
+    }
+
+    #[test]
+    fn location_state() {
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(2);
+        let loc = Location::new();
+        let state = |kind| LocationState {
+            kind,
+            trace_failures: 0,
+        };
+        assert_eq!(loc.state(), state(LocationKind::Counting(0)));
+        for i in 1..=2 {
+            assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+            assert_eq!(loc.state(), state(LocationKind::Counting(i)));
+        }
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        assert_eq!(loc.state(), state(LocationKind::Tracing));
+        let mtx = match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => mtx,
+            _ => unreachable!(),
+        };
+        assert_eq!(loc.state(), state(LocationKind::Compiling));
+        mtx.result
+            .lock()
+            .replace(Err(TraceFailure::Compiling("".to_owned())));
+        assert_eq!(mt.transition_location(&loc), TransitionLocation::NoAction);
+        assert_eq!(
+            loc.state(),
+            LocationState {
+                kind: LocationKind::Counting(0),
+                trace_failures: 1
+            }
+        );
+
+        mt.set_hot_threshold(0);
+        let loc = Location::new();
+        assert!(matches!(
+            mt.transition_location(&loc),
+            TransitionLocation::StartTracing(_)
+        ));
+        match mt.transition_location(&loc) {
+            TransitionLocation::StopTracing(mtx) => {
+                mtx.result
+                    .lock()
+                    .replace(Ok(Arc::new(unsafe { CompiledTrace::new_null() })));
             }
             _ => unreachable!(),
         }
@@ -537,6 +1799,67 @@ mod tests {
             mt.transition_location(&loc),
             TransitionLocation::Execute(_)
         ));
+        assert_eq!(loc.state(), state(LocationKind::Compiled(0)));
+
+        let loc = Location::new();
+        loc.set_dont_trace();
+        assert_eq!(loc.state(), state(LocationKind::DontTrace));
+    }
+
+    #[test]
+    fn side_trace_guards_are_claimed_once() {
+        // Only one thread at a time may side trace a guard, and a guard whose side trace failed
+        // must fail as often again before being side traced again.
+        let guard = Guard::default();
+        for _ in 0..9 {
+            guard.failed();
+        }
+        assert_eq!(guard.failures(), 9);
+        assert_eq!(guard.failed(), 10);
+        assert_eq!(guard.failures(), 10);
+        let claims = AtomicU64::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    if guard.claim_side_trace() {
+                        claims.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(claims.load(Ordering::Relaxed), 1);
+        assert!(!guard.claim_side_trace());
+
+        guard.side_trace_failed();
+        assert_eq!(guard.side_trace_failures(), 1);
+        assert_eq!(guard.failed(), 1);
+        assert!(guard.claim_side_trace());
+        assert!(!guard.has_side_trace());
+    }
+
+    #[test]
+    fn aborted_side_traces_are_not_failures() {
+        // A guard whose side traces are aborted by the interpreter must still be side traced,
+        // however often that happens.
+        let mt = MTBuilder::new()
+            .tracing_kind(TracingKind::SoftwareTracing)
+            .build()
+            .unwrap();
+        mt.set_side_trace_threshold(1);
+        mt.set_trace_failure_threshold(2);
+        let loc = Location::new();
+        let ctr = Arc::new(unsafe { CompiledTrace::new_null_with_guards(1) });
+        let side_tracing = || THREAD_MTTHREAD.with(|mtt| mtt.side_tracing.borrow().is_some());
+        for _ in 0..=mt.trace_failure_threshold() {
+            ctr.guard(0).failed();
+            mt.maybe_start_side_tracing(&loc, Arc::clone(&ctr), 0);
+            assert!(side_tracing());
+            mt.abort_tracing();
+            assert!(!side_tracing());
+        }
+        assert_eq!(ctr.guard(0).side_trace_failures(), 0);
+        assert_eq!(ctr.guard(0).failures(), 0);
+        assert_eq!(mt.stats().traces_aborted, 3);
     }
 
     #[test]
@@ -799,8 +2122,9 @@ mod tests {
                                         hotlocation_discriminant(&loc),
                                         Some(HotLocationKindDiscriminants::Compiling)
                                     );
-                                    mtx.lock()
-                                        .replace(Box::new(unsafe { CompiledTrace::new_null() }));
+                                    mtx.result.lock().replace(Ok(Arc::new(unsafe {
+                                        CompiledTrace::new_null()
+                                    })));
                                 }
                                 x => unreachable!("Reached incorrect state {:?}", x),
                             }
@@ -899,7 +2223,7 @@ mod tests {
     }
 
     #[test]
-    fn dont_trace_execution_of_a_trace() {
+    fn execute_trace_while_tracing() {
         let mt = Arc::new(MT::new().unwrap());
         mt.set_hot_threshold(0);
         let loc1 = Location::new();
@@ -911,28 +2235,25 @@ mod tests {
             TransitionLocation::StartTracing(_)
         ));
         if let TransitionLocation::StopTracing(mtx) = mt.transition_location(&loc1) {
-            mtx.lock()
-                .replace(Box::new(unsafe { CompiledTrace::new_null() }));
+            mtx.result
+                .lock()
+                .replace(Ok(Arc::new(unsafe { CompiledTrace::new_null() })));
         } else {
             panic!();
         }
 
-        // If we transition `loc2` into `StartTracing`, then (for now) we should not execute the
-        // trace for `loc1`, as another location is being traced and we don't want to trace the
-        // execution of the trace!
-        //
-        // FIXME: this behaviour will need to change in the future:
-        // https://github.com/ykjit/yk/issues/519
+        // Even though `loc2` is being traced, we can execute the trace for `loc1`: the control
+        // point then ends the trace for `loc2`, rather than tracing the execution of the trace.
         assert!(matches!(
             mt.transition_location(&loc2),
             TransitionLocation::StartTracing(_)
         ));
         assert!(matches!(
             mt.transition_location(&loc1),
-            TransitionLocation::NoAction
+            TransitionLocation::Execute(_)
         ));
 
-        // But once we stop tracing for `loc2`, we should be able to execute the trace for `loc1`.
+        // Executing a trace doesn't, by itself, affect the `Location` being traced.
         assert!(matches!(
             mt.transition_location(&loc2),
             TransitionLocation::StopTracing(_)
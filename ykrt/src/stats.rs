@@ -0,0 +1,121 @@
+//! Statistics about what a meta-tracer has been doing.
+
+use std::{
+    convert::TryFrom,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// A snapshot of a meta-tracer's statistics, as returned by [crate::MT::stats]. Counts and
+/// timings are cumulative over the lifetime of the meta-tracer, and include both root and side
+/// traces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// How many traces have been started.
+    pub traces_started: u64,
+    /// How many traces were completed, and passed on to be compiled.
+    pub traces_completed: u64,
+    /// How many traces were aborted before they completed.
+    pub traces_aborted: u64,
+    /// How many traces have been compiled.
+    pub traces_compiled: u64,
+    /// How many completed traces could not be mapped or compiled.
+    pub traces_compile_failed: u64,
+    /// How many times execution has deoptimised from a compiled trace back to the interpreter.
+    pub deopts: u64,
+    /// The total time spent tracing.
+    pub tracing_time: Duration,
+    /// The total time spent mapping traces to IR.
+    pub mapping_time: Duration,
+    /// The total time spent compiling traces.
+    pub compiling_time: Duration,
+    /// The total time spent deoptimising in `yk_stopgap`.
+    pub stopgap_time: Duration,
+    /// How many compilation jobs are waiting to be run.
+    pub compile_queue_len: usize,
+    /// How many worker threads are running.
+    pub active_worker_threads: usize,
+}
+
+/// The counters from which a [Stats] snapshot is created. These can be updated by any thread,
+/// so the counters are independent of each other: a snapshot taken while other threads are busy
+/// may, for example, count a trace as compiled without having counted it as completed.
+#[derive(Debug, Default)]
+pub(crate) struct AtomicStats {
+    traces_started: AtomicU64,
+    traces_completed: AtomicU64,
+    traces_aborted: AtomicU64,
+    traces_compiled: AtomicU64,
+    traces_compile_failed: AtomicU64,
+    deopts: AtomicU64,
+    /// Times are stored in nanoseconds.
+    tracing_time: AtomicU64,
+    mapping_time: AtomicU64,
+    compiling_time: AtomicU64,
+    stopgap_time: AtomicU64,
+}
+
+impl AtomicStats {
+    /// Record that a trace was started.
+    pub(crate) fn trace_started(&self) {
+        self.traces_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a trace, which took `time` to record, was completed (if `completed` is true) or
+    /// aborted.
+    pub(crate) fn trace_stopped(&self, completed: bool, time: Duration) {
+        if completed {
+            self.traces_completed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.traces_aborted.fetch_add(1, Ordering::Relaxed);
+        }
+        add_time(&self.tracing_time, time);
+    }
+
+    /// Record that mapping a trace took `time`.
+    pub(crate) fn trace_mapped(&self, time: Duration) {
+        add_time(&self.mapping_time, time);
+    }
+
+    /// Record that a trace, which took `time` to compile, was compiled (if `compiled` is true) or
+    /// failed to compile. A trace that could not be mapped counts as having failed to compile.
+    pub(crate) fn trace_compiled(&self, compiled: bool, time: Duration) {
+        if compiled {
+            self.traces_compiled.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.traces_compile_failed.fetch_add(1, Ordering::Relaxed);
+        }
+        add_time(&self.compiling_time, time);
+    }
+
+    /// Record that execution deoptimised, and that doing so took `time`.
+    pub(crate) fn deoptimised(&self, time: Duration) {
+        self.deopts.fetch_add(1, Ordering::Relaxed);
+        add_time(&self.stopgap_time, time);
+    }
+
+    /// Return a snapshot of these statistics.
+    pub(crate) fn snapshot(&self, compile_queue_len: usize, active_worker_threads: usize) -> Stats {
+        Stats {
+            traces_started: self.traces_started.load(Ordering::Relaxed),
+            traces_completed: self.traces_completed.load(Ordering::Relaxed),
+            traces_aborted: self.traces_aborted.load(Ordering::Relaxed),
+            traces_compiled: self.traces_compiled.load(Ordering::Relaxed),
+            traces_compile_failed: self.traces_compile_failed.load(Ordering::Relaxed),
+            deopts: self.deopts.load(Ordering::Relaxed),
+            tracing_time: Duration::from_nanos(self.tracing_time.load(Ordering::Relaxed)),
+            mapping_time: Duration::from_nanos(self.mapping_time.load(Ordering::Relaxed)),
+            compiling_time: Duration::from_nanos(self.compiling_time.load(Ordering::Relaxed)),
+            stopgap_time: Duration::from_nanos(self.stopgap_time.load(Ordering::Relaxed)),
+            compile_queue_len,
+            active_worker_threads,
+        }
+    }
+}
+
+fn add_time(counter: &AtomicU64, time: Duration) {
+    counter.fetch_add(
+        u64::try_from(time.as_nanos()).unwrap_or(u64::MAX),
+        Ordering::Relaxed,
+    );
+}
@@ -0,0 +1,31 @@
+//! The on-disk cache of compiled traces, enabled with the `trace_cache` option of a meta-tracer.
+//!
+//! The cache's directory holds one subdirectory for each combination of binary and version of
+//! yk, so that a trace compiled by one is never used by another. Within a subdirectory,
+//! ykllvmwrap stores each compiled trace's object code, guards, and live AOT values under a hash
+//! of the trace's blocks and promoted values, so that a cached trace is found without building its
+//! JIT module.
+
+use crate::saved::SELF_BITCODE_HASH;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The version of the cache's layout and contents. This must be incremented whenever a change to
+/// yk means that traces compiled before the change can't be used after it, since yk's version
+/// number isn't changed by every such change.
+const CACHE_VERSION: u32 = 2;
+
+/// Return the directory, within the trace cache `dir`, holding traces compiled for the current
+/// binary by this version of yk, creating it if it doesn't exist.
+pub fn trace_cache_dir(dir: &Path) -> io::Result<PathBuf> {
+    let subdir = dir.join(format!(
+        "{}-{}-{:x}",
+        env!("CARGO_PKG_VERSION"),
+        CACHE_VERSION,
+        *SELF_BITCODE_HASH
+    ));
+    fs::create_dir_all(&subdir)?;
+    Ok(subdir)
+}
@@ -1,5 +1,6 @@
 //! Errors that can occur during tracing.
 
+use hwtracer::HWTracerError;
 use std::fmt::{self, Display, Formatter};
 
 #[derive(Debug)]
@@ -9,6 +10,15 @@ pub enum InvalidTraceError {
     EmptyTrace,
     /// Something went wrong in the compiler's tracing code.
     InternalError,
+    /// The hardware tracer failed to collect or decode the trace. The hardware tracer's errors
+    /// can't be sent between threads, so only the error's description is kept.
+    HWTracerError(String),
+}
+
+impl From<HWTracerError> for InvalidTraceError {
+    fn from(e: HWTracerError) -> Self {
+        InvalidTraceError::HWTracerError(e.to_string())
+    }
 }
 
 impl Display for InvalidTraceError {
@@ -16,6 +26,7 @@ impl Display for InvalidTraceError {
         match self {
             InvalidTraceError::EmptyTrace => write!(f, "Empty trace"),
             InvalidTraceError::InternalError => write!(f, "Internal tracing error"),
+            InvalidTraceError::HWTracerError(e) => write!(f, "Hardware tracing error: {}", e),
         }
     }
 }
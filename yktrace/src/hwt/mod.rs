@@ -24,10 +24,8 @@ struct HWTThreadTracer {
 impl ThreadTracerImpl for HWTThreadTracer {
     fn stop_tracing(&mut self) -> Result<Box<dyn UnmappedTrace>, InvalidTraceError> {
         self.active = false;
-        match TRACE_COLLECTOR.stop_thread_collector() {
-            Ok(t) => Ok(Box::new(PTTrace(t))),
-            Err(e) => todo!("{e:?}"),
-        }
+        let t = TRACE_COLLECTOR.stop_thread_collector()?;
+        Ok(Box::new(PTTrace(t)))
     }
 }
 
@@ -45,20 +43,23 @@ pub(crate) fn start_tracing() -> ThreadTracer {
         .expect("Failed to start trace collector");
     ThreadTracer {
         t_impl: Box::new(HWTThreadTracer { active: true }),
+        promotions: Vec::new(),
     }
 }
 
 struct PTTrace(Box<dyn hwtracer::Trace>);
 
 impl UnmappedTrace for PTTrace {
-    fn map(self: Box<Self>, decoder: TraceDecoderKind) -> Result<IRTrace, InvalidTraceError> {
-        let tdec = TraceDecoderBuilder::new().kind(decoder).build().unwrap();
+    fn map(
+        self: Box<Self>,
+        decoder: Option<TraceDecoderKind>,
+    ) -> Result<IRTrace, InvalidTraceError> {
+        let decoder = decoder.ok_or(InvalidTraceError::InternalError)?;
+        let tdec = TraceDecoderBuilder::new().kind(decoder).build()?;
         let mut itr = tdec.iter_blocks(self.0.as_ref());
         let mut mt = HWTMapper::new();
 
-        let mapped = mt
-            .map_trace(&mut *itr)
-            .map_err(|_| InvalidTraceError::InternalError)?;
+        let mapped = mt.map_trace(&mut *itr)?;
         if mapped.is_empty() {
             return Err(InvalidTraceError::EmptyTrace);
         }
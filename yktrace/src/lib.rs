@@ -6,25 +6,36 @@
 #![allow(clippy::new_without_default)]
 #![allow(clippy::missing_safety_doc)]
 
+mod cache;
 mod errors;
-use hwtracer::decode::TraceDecoderKind;
+mod options;
+mod saved;
+use hwtracer::{collect::TraceCollectorKind, decode::TraceDecoderKind};
 use libc::c_void;
 #[cfg(unix)]
-use std::os::unix::io::AsRawFd;
+use std::os::unix::{ffi::OsStrExt, io::AsRawFd};
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::HashMap,
-    env,
     error::Error,
     ffi::{c_char, c_int, CStr, CString},
-    ptr,
+    fmt, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, AtomicU16, AtomicU32, AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
 };
 pub mod hwt;
+pub mod swt;
 use std::arch::asm;
 use tempfile::NamedTempFile;
 use ykutil::obj::llvmbc_section;
 
+pub use cache::trace_cache_dir;
 pub use errors::InvalidTraceError;
+pub use options::{CompileOptions, PrintIR};
+pub use saved::SavedTrace;
 
 thread_local! {
     // When `Some`, contains the `ThreadTracer` for the current thread. When `None`, the current
@@ -33,6 +44,8 @@ thread_local! {
     // We hide the `ThreadTracer` in a thread local (rather than returning it to the consumer of
     // yk). This ensures that the `ThreadTracer` itself cannot appear in traces.
     pub static THREAD_TRACER: RefCell<Option<ThreadTracer>> = const { RefCell::new(None) };
+    // The most recent guard failure in the current thread.
+    static FAILED_GUARD: Cell<Option<FailedGuard>> = const { Cell::new(None) };
 }
 
 /// The different ways by which we can collect a trace.
@@ -44,12 +57,132 @@ pub enum TracingKind {
     HardwareTracing,
 }
 
-impl Default for TracingKind {
-    /// Returns the default tracing kind.
-    fn default() -> Self {
-        // FIXME this should query the hardware for a suitable hardware tracer and failing that
-        // fall back on software tracing.
-        TracingKind::HardwareTracing
+/// The tracing backend that has been selected for the current platform.
+#[derive(Debug)]
+pub struct TracingBackend {
+    /// The kind of tracing to use.
+    kind: TracingKind,
+    /// The decoder to use for hardware traces. `None` if `kind` is not
+    /// `TracingKind::HardwareTracing`.
+    decoder: Option<TraceDecoderKind>,
+    /// Human-readable reasons why more preferable backends were rejected.
+    rejections: Vec<String>,
+}
+
+impl TracingBackend {
+    /// Probe the current platform for the most preferable tracing backend. Hardware tracing is
+    /// used if the CPU, the OS, and the process' permissions allow a trace collector to be used,
+    /// and if a decoder for the collected traces is available. Otherwise we fall back on software
+    /// tracing, which is always available (though it requires the interpreter to have been built
+    /// with software tracing instrumentation).
+    pub fn probe() -> Self {
+        let mut rejections = Vec::new();
+        match TraceCollectorKind::probe_platform() {
+            Ok(_) => match TraceDecoderKind::probe_platform() {
+                Ok(decoder) => {
+                    return Self {
+                        kind: TracingKind::HardwareTracing,
+                        decoder: Some(decoder),
+                        rejections,
+                    }
+                }
+                Err(rejected) => {
+                    for (kind, e) in rejected {
+                        rejections.push(format!("hardware tracing: {kind:?} decoder: {e}"));
+                    }
+                }
+            },
+            Err(rejected) => {
+                for (kind, e) in rejected {
+                    rejections.push(format!("hardware tracing: {kind:?} collector: {e}"));
+                }
+            }
+        }
+        Self {
+            kind: TracingKind::SoftwareTracing,
+            decoder: None,
+            rejections,
+        }
+    }
+
+    /// Use tracing of kind `kind`, with the decoder `decoder` if hardware tracing is requested (if
+    /// `decoder` is `None`, the most preferable decoder for the platform is used). Returns an
+    /// error if the requested backend is not available on the current platform.
+    pub fn new(kind: TracingKind, decoder: Option<TraceDecoderKind>) -> Result<Self, String> {
+        match kind {
+            TracingKind::SoftwareTracing => {
+                if let Some(decoder) = decoder {
+                    return Err(format!(
+                        "the {decoder:?} decoder can only be used with hardware tracing"
+                    ));
+                }
+                Ok(Self {
+                    kind,
+                    decoder: None,
+                    rejections: Vec::new(),
+                })
+            }
+            TracingKind::HardwareTracing => {
+                if let Err(rejected) = TraceCollectorKind::probe_platform() {
+                    let reasons = rejected
+                        .iter()
+                        .map(|(kind, e)| format!("{kind:?} collector: {e}"))
+                        .collect::<Vec<_>>();
+                    return Err(format!(
+                        "hardware tracing is not available: {}",
+                        reasons.join(", ")
+                    ));
+                }
+                let decoder = match decoder {
+                    Some(decoder) => {
+                        decoder.match_platform().map_err(|e| {
+                            format!("the {decoder:?} decoder is not available: {e}")
+                        })?;
+                        decoder
+                    }
+                    None => TraceDecoderKind::probe_platform().map_err(|rejected| {
+                        let reasons = rejected
+                            .iter()
+                            .map(|(kind, e)| format!("{kind:?} decoder: {e}"))
+                            .collect::<Vec<_>>();
+                        format!("hardware tracing is not available: {}", reasons.join(", "))
+                    })?,
+                };
+                Ok(Self {
+                    kind,
+                    decoder: Some(decoder),
+                    rejections: Vec::new(),
+                })
+            }
+        }
+    }
+
+    /// The kind of tracing that was selected.
+    pub fn kind(&self) -> TracingKind {
+        self.kind
+    }
+
+    /// If hardware tracing was selected, the decoder to be used for its traces.
+    pub fn decoder(&self) -> Option<TraceDecoderKind> {
+        self.decoder
+    }
+
+    /// The reasons why more preferable backends were rejected (if any).
+    pub fn rejections(&self) -> &[String] {
+        &self.rejections
+    }
+}
+
+impl fmt::Display for TracingBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.decoder {
+            Some(decoder) => write!(f, "{:?} (decoder: {decoder:?})", self.kind)?,
+            None => write!(f, "{:?}", self.kind)?,
+        }
+        for r in &self.rejections {
+            write!(f, "\n  rejected {r}")?;
+        }
+        Ok(())
     }
 }
 
@@ -132,6 +265,8 @@ pub struct IRTrace {
     blocks: Vec<IRBlock>,
     /// Function addresses discovered dynamically via the trace. symbol-name -> address.
     faddrs: HashMap<CString, *const c_void>,
+    /// The values passed to `yk_promote` while tracing, in the order they were passed.
+    promotions: Vec<usize>,
 }
 
 unsafe impl Send for IRTrace {}
@@ -140,7 +275,11 @@ unsafe impl Sync for IRTrace {}
 impl IRTrace {
     pub fn new(blocks: Vec<IRBlock>, faddrs: HashMap<CString, *const c_void>) -> Self {
         debug_assert!(blocks.len() < usize::MAX);
-        Self { blocks, faddrs }
+        Self {
+            blocks,
+            faddrs,
+            promotions: Vec::new(),
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -172,28 +311,59 @@ impl IRTrace {
     // into. Elsewhere, the JIT module will have `DebugLoc`s inserted into it which will point to
     // lines in this temporary file.
     //
-    // If the `YKD_TRACE_DEBUGINFO` environment variable is set to "1", then this function returns
-    // a `NamedTempFile`, a non-negative file descriptor, and a path to the file.
+    // If `opts` asks for trace debugging information, then this function returns a
+    // `NamedTempFile`, a non-negative file descriptor, and a path to the file.
     //
-    // If the `YKD_TRACE_DEBUGINFO` environment variable is *not* set to "1", then no file is
-    // created and this function returns `(None, -1, ptr::null())`.
+    // Otherwise no file is created and this function returns `(None, -1, ptr::null())`.
     #[cfg(unix)]
-    fn create_debuginfo_temp_file() -> (Option<NamedTempFile>, c_int, *const c_char) {
+    fn create_debuginfo_temp_file(
+        opts: &CompileOptions,
+    ) -> (Option<NamedTempFile>, c_int, *const c_char) {
         let mut di_tmp = None;
         let mut di_fd = -1;
         let mut di_tmpname_c = ptr::null() as *const c_char;
-        if let Ok(di_val) = env::var("YKD_TRACE_DEBUGINFO") {
-            if di_val == "1" {
-                let tmp = NamedTempFile::new().unwrap();
-                di_tmpname_c = tmp.path().to_str().unwrap().as_ptr() as *const c_char;
-                di_fd = tmp.as_raw_fd();
-                di_tmp = Some(tmp);
-            }
+        if opts.trace_debuginfo {
+            let tmp = NamedTempFile::new().unwrap();
+            di_tmpname_c = tmp.path().to_str().unwrap().as_ptr() as *const c_char;
+            di_fd = tmp.as_raw_fd();
+            di_tmp = Some(tmp);
         }
         (di_tmp, di_fd, di_tmpname_c)
     }
 
-    pub fn compile(&self) -> Result<(*const c_void, Option<NamedTempFile>), Box<dyn Error>> {
+    /// Compile this trace, with the options `opts`, as a root trace for the `Location` at address
+    /// `loc`. If `YKD_SAVE_TRACES` is set, the trace is saved first (side traces can't be compiled
+    /// without their parent, so aren't saved).
+    pub fn compile(
+        &self,
+        loc: *const c_void,
+        opts: &CompileOptions,
+    ) -> Result<(*const c_void, Option<NamedTempFile>), Box<dyn Error>> {
+        saved::save_trace(self);
+        self.compile_impl(loc, None, opts)
+    }
+
+    /// Compile this trace, with the options `opts`, as a side trace for the guard with ID
+    /// `guard_idx` in `parent`. `root_loc` is the address of the `Location` for which the root
+    /// trace of `parent` (i.e. either `parent` itself, or the trace that `parent` is ultimately a
+    /// side trace of) was compiled.
+    pub fn compile_side(
+        &self,
+        parent: &CompiledTrace,
+        guard_idx: usize,
+        root_loc: *const c_void,
+        opts: &CompileOptions,
+    ) -> Result<(*const c_void, Option<NamedTempFile>), Box<dyn Error>> {
+        debug_assert!(guard_idx < parent.guards.len());
+        self.compile_impl(root_loc, Some((parent, guard_idx)), opts)
+    }
+
+    fn compile_impl(
+        &self,
+        root_loc: *const c_void,
+        side: Option<(&CompiledTrace, usize)>,
+        opts: &CompileOptions,
+    ) -> Result<(*const c_void, Option<NamedTempFile>), Box<dyn Error>> {
         let (func_names, bbs, trace_len) = self.encode_trace();
 
         let mut faddr_keys = Vec::new();
@@ -204,21 +374,58 @@ impl IRTrace {
         }
 
         let (llvmbc_data, llvmbc_len) = llvmbc_section();
-        let (di_tmp, di_fd, di_tmpname_c) = Self::create_debuginfo_temp_file();
-
-        let ret = unsafe {
-            ykllvmwrap::__ykllvmwrap_irtrace_compile(
-                func_names.as_ptr(),
-                bbs.as_ptr(),
-                trace_len,
-                faddr_keys.as_ptr(),
-                faddr_vals.as_ptr(),
-                faddr_keys.len(),
-                llvmbc_data,
-                llvmbc_len,
-                di_fd,
-                di_tmpname_c,
-            )
+        let (di_tmp, di_fd, di_tmpname_c) = Self::create_debuginfo_temp_file(opts);
+        let trace_cache = opts
+            .trace_cache
+            .as_ref()
+            .map(|p| CString::new(p.as_os_str().as_bytes()))
+            .transpose()?;
+        let trace_cache_c = trace_cache.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+
+        let ret = match side {
+            None => unsafe {
+                ykllvmwrap::__ykllvmwrap_irtrace_compile(
+                    root_loc,
+                    func_names.as_ptr(),
+                    bbs.as_ptr(),
+                    trace_len,
+                    faddr_keys.as_ptr(),
+                    faddr_vals.as_ptr(),
+                    faddr_keys.len(),
+                    self.promotions.as_ptr(),
+                    self.promotions.len(),
+                    llvmbc_data,
+                    llvmbc_len,
+                    di_fd,
+                    di_tmpname_c,
+                    opts.print_ir.bits(),
+                    opts.perf_map,
+                    trace_cache_c,
+                )
+            },
+            Some((parent, guard_idx)) => unsafe {
+                ykllvmwrap::__ykllvmwrap_irtrace_compile_side(
+                    parent.info,
+                    guard_idx,
+                    parent.exec_info.aotvals,
+                    root_loc,
+                    func_names.as_ptr(),
+                    bbs.as_ptr(),
+                    trace_len,
+                    faddr_keys.as_ptr(),
+                    faddr_vals.as_ptr(),
+                    faddr_keys.len(),
+                    self.promotions.as_ptr(),
+                    self.promotions.len(),
+                    llvmbc_data,
+                    llvmbc_len,
+                    di_fd,
+                    di_tmpname_c,
+                    opts.print_ir.bits(),
+                    opts.perf_map,
+                    trace_cache_c,
+                )
+            },
         };
         if ret.is_null() {
             Err("Could not compile trace.".into())
@@ -228,9 +435,14 @@ impl IRTrace {
     }
 
     #[cfg(feature = "yk_testing")]
-    pub unsafe fn compile_for_tc_tests(&self, llvmbc_data: *const u8, llvmbc_len: u64) {
+    pub unsafe fn compile_for_tc_tests(
+        &self,
+        llvmbc_data: *const u8,
+        llvmbc_len: u64,
+        opts: &CompileOptions,
+    ) {
         let (func_names, bbs, trace_len) = self.encode_trace();
-        let (_di_tmp, di_fd, di_tmpname_c) = Self::create_debuginfo_temp_file();
+        let (_di_tmp, di_fd, di_tmpname_c) = Self::create_debuginfo_temp_file(opts);
 
         // These would only need to be populated if we were to load the resulting compiled code
         // into the address space, which for trace compiler tests, we don't.
@@ -248,28 +460,76 @@ impl IRTrace {
             llvmbc_len,
             di_fd,
             di_tmpname_c,
+            opts.print_ir.bits(),
         );
         assert_ne!(ret, ptr::null());
     }
+
+    /// Compile this trace, which was collected by another process running the binary whose
+    /// embedded bitcode is `llvmbc`, as a root trace with the options `opts` (except that the
+    /// trace cache is never used). The compiled trace is then discarded. The trace's function
+    /// addresses are those of the other process, so they aren't used: calls to idempotent
+    /// functions therefore aren't executed at compile-time.
+    #[cfg(feature = "yk_testing")]
+    pub fn compile_for_replay(
+        &self,
+        llvmbc: &[u8],
+        opts: &CompileOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        use std::convert::TryFrom;
+
+        let (func_names, bbs, trace_len) = self.encode_trace();
+        let (_di_tmp, di_fd, di_tmpname_c) = Self::create_debuginfo_temp_file(opts);
+
+        let ret = unsafe {
+            ykllvmwrap::__ykllvmwrap_irtrace_compile_for_replay(
+                func_names.as_ptr(),
+                bbs.as_ptr(),
+                trace_len,
+                self.promotions.as_ptr(),
+                self.promotions.len(),
+                llvmbc.as_ptr(),
+                u64::try_from(llvmbc.len()).unwrap(),
+                di_fd,
+                di_tmpname_c,
+                opts.print_ir.bits(),
+            )
+        };
+        if ret.is_null() {
+            Err("Could not compile trace.".into())
+        } else {
+            unsafe { ykllvmwrap::__ykllvmwrap_free_compiled_trace(ret) };
+            Ok(())
+        }
+    }
 }
 
-/// A trace compiled into machine code. Note that these are passed around as raw pointers and
-/// potentially referenced by multiple threads so, once created, instances of this struct can only
-/// be updated if a lock is held or a field is atomic.
+/// A trace compiled into machine code. Note that these are reference counted and potentially
+/// referenced by multiple threads so, once created, instances of this struct can only be updated if
+/// a lock is held or a field is atomic.
+///
+/// The machine code for the trace is freed when the `CompiledTrace` is dropped, so it is the
+/// responsibility of the owner to ensure that nothing can still be executing the trace at that
+/// point (e.g. by only executing traces through a reference-counted pointer).
 #[derive(Debug)]
 pub struct CompiledTrace {
-    /// A function which when called, executes the compiled trace.
-    ///
-    /// The argument to the function is a pointer to a struct containing the live variables at the
-    /// control point. The exact definition of this struct is not known to Rust: the struct is
-    /// generated at interpreter compile-time by ykllvm.
-    entry: *const c_void,
-    /// Pointer to the stackmap, required to parse the stackmap during a guard failure.
-    smptr: *const c_void,
-    /// The stackmaps size.
-    smsize: usize,
-    /// Pointer to heap allocated live AOT values.
-    aotvals: *const c_void,
+    /// The ID given to the trace by the meta-tracer when tracing started.
+    id: u64,
+    /// Everything the machine code of the trace needs at run-time.
+    exec_info: ExecInfo,
+    /// The ykllvmwrap object owning the trace's machine code, data, and stackmap, and describing
+    /// its guards. Null for `CompiledTrace`s created by `new_null`.
+    info: *const c_void,
+    /// How many times a guard in this trace has failed.
+    guard_failures: AtomicU64,
+    /// Has this trace been found not to be worth executing?
+    unsuitable: AtomicBool,
+    /// The state of each of this trace's guards, indexed by guard ID.
+    guards: Box<[Guard]>,
+    /// For each guard, indexed by guard ID, a pointer to the side trace to execute when the guard
+    /// fails (or null if the guard has no side trace). The compiled trace reads this array
+    /// directly. The side traces themselves are kept alive by `guards`.
+    side_traces: Box<[AtomicPtr<CompiledTrace>]>,
     /// If requested, a temporary file containing the "source code" for the trace, to be shown in
     /// debuggers when stepping over the JITted code.
     ///
@@ -283,22 +543,38 @@ use std::mem;
 use std::slice;
 impl CompiledTrace {
     /// Create a `CompiledTrace` from a pointer to an array containing: the pointer to the compiled
-    /// trace, the pointer to the stackmap and the size of the stackmap, and the pointer to the
-    /// live AOT values.
-    pub fn new(data: *const c_void, di_tmpfile: Option<NamedTempFile>) -> Self {
-        let slice = unsafe { slice::from_raw_parts(data as *const usize, 4) };
+    /// trace, the pointer to the stackmap and the size of the stackmap, the pointer to the live AOT
+    /// values, the pointer to the ykllvmwrap object which owns the trace's memory, and the number
+    /// of guards in the trace. `id` is the ID given to the trace by the meta-tracer.
+    pub fn new(id: u64, data: *const c_void, di_tmpfile: Option<NamedTempFile>) -> Self {
+        let slice = unsafe { slice::from_raw_parts(data as *const usize, 6) };
         let funcptr = slice[0] as *const c_void;
         let smptr = slice[1] as *const c_void;
         let smsize = slice[2];
         let aotvals = slice[3] as *mut c_void;
+        let info = slice[4] as *const c_void;
+        let num_guards = slice[5];
         // We heap allocated this array in ykllvmwrap to pass the data here. Now that we've
         // extracted it we no longer need to keep the array around.
         unsafe { libc::free(data as *mut c_void) };
+        let side_traces: Box<[AtomicPtr<CompiledTrace>]> = (0..num_guards)
+            .map(|_| AtomicPtr::new(ptr::null_mut()))
+            .collect();
         Self {
-            entry: funcptr,
-            smptr,
-            smsize,
-            aotvals,
+            id,
+            exec_info: ExecInfo {
+                func: funcptr,
+                smptr,
+                smsize,
+                aotvals,
+                side_traces: side_traces.as_ptr(),
+                executions: AtomicU64::new(0),
+            },
+            info,
+            guard_failures: AtomicU64::new(0),
+            unsuitable: AtomicBool::new(false),
+            guards: (0..num_guards).map(|_| Guard::default()).collect(),
+            side_traces,
             di_tmpfile,
         }
     }
@@ -309,15 +585,112 @@ impl CompiledTrace {
     /// purposes where a `CompiledTrace` instance is required, but cannot sensibly be constructed
     /// without overwhelming the test. The resulting instance must not be inspected or executed.
     pub unsafe fn new_null() -> Self {
+        Self::new_null_with_guards(0)
+    }
+
+    #[cfg(feature = "yk_testing")]
+    #[doc(hidden)]
+    /// As [CompiledTrace::new_null], but the trace has `num_guards` guards, which (unlike the
+    /// rest of the trace) may be inspected.
+    pub unsafe fn new_null_with_guards(num_guards: usize) -> Self {
         Self {
-            entry: std::ptr::null(),
-            smptr: std::ptr::null() as *const _,
-            smsize: 0,
-            aotvals: std::ptr::null() as *const _,
+            id: 0,
+            exec_info: ExecInfo {
+                func: std::ptr::null(),
+                smptr: std::ptr::null() as *const _,
+                smsize: 0,
+                aotvals: std::ptr::null() as *const _,
+                side_traces: std::ptr::null(),
+                executions: AtomicU64::new(0),
+            },
+            info: std::ptr::null(),
+            guard_failures: AtomicU64::new(0),
+            unsuitable: AtomicBool::new(false),
+            guards: (0..num_guards).map(|_| Guard::default()).collect(),
+            side_traces: (0..num_guards)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
             di_tmpfile: None,
         }
     }
 
+    /// The ID given to this trace by the meta-tracer when tracing started.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Record that this trace is about to be executed.
+    pub fn executing(&self) {
+        // The trace's machine code increments the count without a read-modify-write, so updates
+        // may be lost anyway: there's no point in us using one.
+        let executions = self.exec_info.executions.load(Ordering::Relaxed);
+        self.exec_info
+            .executions
+            .store(executions + 1, Ordering::Relaxed);
+    }
+
+    /// How many times has this trace been executed? This counts both the executions recorded by
+    /// [CompiledTrace::executing] and the iterations that the trace's machine code starts by
+    /// itself. Increments made concurrently by different threads may be lost, so this is only an
+    /// approximation. Notice that this value can be changed by other threads and is thus
+    /// potentially stale as soon as it is read.
+    pub fn executions(&self) -> u64 {
+        self.exec_info.executions.load(Ordering::Relaxed)
+    }
+
+    /// Return a pointer to the struct describing this trace at run-time, which compiled traces can
+    /// use to jump directly to this trace.
+    pub fn exec_info(&self) -> *const c_void {
+        &self.exec_info as *const ExecInfo as *const c_void
+    }
+
+    /// Record that a guard in this trace failed, returning the number of guard failures in this
+    /// trace so far (including this one).
+    pub fn guard_failed(&self) -> u64 {
+        self.guard_failures.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Record that this trace isn't worth executing, so that its `Location` can be reprofiled the
+    /// next time the `Location` is reached.
+    pub fn mark_unsuitable(&self) {
+        self.unsuitable.store(true, Ordering::Relaxed);
+    }
+
+    /// Has [CompiledTrace::mark_unsuitable] been called on this trace?
+    pub fn is_unsuitable(&self) -> bool {
+        self.unsuitable.load(Ordering::Relaxed)
+    }
+
+    /// Return the state of the guard with ID `guard_idx`.
+    pub fn guard(&self, guard_idx: usize) -> &Guard {
+        &self.guards[guard_idx]
+    }
+
+    /// If the guard that failed in `failed` is in `self` or one of its (possibly indirect) side
+    /// traces, return the compiled trace containing the guard and the guard's ID.
+    pub fn find_failed_guard(
+        self: &Arc<Self>,
+        failed: &FailedGuard,
+    ) -> Option<(Arc<CompiledTrace>, usize)> {
+        if ptr::eq(self.exec_info(), failed.trace) {
+            return Some((Arc::clone(self), failed.guard_idx));
+        }
+        self.guards
+            .iter()
+            .filter_map(|g| g.side_trace.get())
+            .find_map(|x| x.find_failed_guard(failed))
+    }
+
+    /// Install `side_trace` as the side trace for the guard with ID `guard_idx`: from now on, when
+    /// the guard fails, `side_trace` is executed instead of deoptimising.
+    pub fn install_side_trace(&self, guard_idx: usize, side_trace: Arc<CompiledTrace>) {
+        let ptr = Arc::as_ptr(&side_trace) as *mut CompiledTrace;
+        if self.guards[guard_idx].side_trace.set(side_trace).is_err() {
+            panic!("Guard {} already has a side trace.", guard_idx);
+        }
+        self.side_traces[guard_idx].store(ptr, Ordering::Release);
+    }
+
     #[cfg(target_arch = "x86_64")]
     #[naked]
     #[no_mangle]
@@ -326,10 +699,14 @@ impl CompiledTrace {
     /// Until we've figured out how to restore only the used registers we take the sledge hammer
     /// approach and save and restore all CSRs here.
     /// OPT: Find a way to only restore needed registers (ideally right within the deopt code).
+    ///
+    /// `guard_live_vars` must be null, unless `self` is a side trace, in which case it must point
+    /// to the variables live at the guard at which the side trace starts.
     pub extern "C" fn exec(
         &self,
         ctrlp_vars: *mut c_void,
         frameaddr: *mut c_void,
+        guard_live_vars: *const c_void,
     ) -> *const c_void {
         unsafe {
             asm!(
@@ -359,31 +736,178 @@ impl CompiledTrace {
         &self,
         ctrlp_vars: *mut c_void,
         frameaddr: *mut c_void,
+        guard_live_vars: *const c_void,
     ) -> *const c_void {
         #[cfg(feature = "yk_testing")]
-        assert_ne!(self.entry as *const (), std::ptr::null());
+        assert_ne!(self.exec_info.func as *const (), std::ptr::null());
         unsafe {
             let f = mem::transmute::<
                 _,
                 unsafe extern "C" fn(
                     *mut c_void,
-                    *const c_void,
-                    usize,
+                    *const ExecInfo,
                     *mut c_void,
                     *const c_void,
                 ) -> *const c_void,
-            >(self.entry);
-            f(ctrlp_vars, self.smptr, self.smsize, frameaddr, self.aotvals)
+            >(self.exec_info.func);
+            f(ctrlp_vars, &self.exec_info, frameaddr, guard_live_vars)
         }
     }
 }
 
+/// Execute the side trace `ctr`. Compiled traces call this when a guard which has a side trace
+/// fails, passing in the variables live at the guard.
+#[no_mangle]
+pub unsafe extern "C" fn __yktrace_exec_side_trace(
+    ctr: *const CompiledTrace,
+    ctrlp_vars: *mut c_void,
+    frameaddr: *mut c_void,
+    guard_live_vars: *const c_void,
+) -> *const c_void {
+    let ctr = &*ctr;
+    ctr.executing();
+    ctr.exec(ctrlp_vars, frameaddr, guard_live_vars)
+}
+
+/// Everything the machine code of a compiled trace needs at run-time. A pointer to this struct is
+/// passed to the compiled trace, which reads its fields directly: its layout must therefore match
+/// the `EXECINFO_*` definitions in ykllvmwrap/jitmodbuilder.cc.
+#[derive(Debug)]
+#[repr(C)]
+struct ExecInfo {
+    /// The function which, when called, executes the compiled trace.
+    ///
+    /// The first argument to the function is a pointer to a struct containing the live variables
+    /// at the control point. The exact definition of this struct is not known to Rust: the struct
+    /// is generated at interpreter compile-time by ykllvm.
+    func: *const c_void,
+    /// Pointer to the stackmap, required to parse the stackmap during a guard failure.
+    smptr: *const c_void,
+    /// The stackmaps size.
+    smsize: usize,
+    /// Pointer to heap allocated live AOT values.
+    aotvals: *const c_void,
+    /// Pointer to the first element of [CompiledTrace::side_traces].
+    side_traces: *const AtomicPtr<CompiledTrace>,
+    /// How many times this trace has been executed. This is incremented without a
+    /// read-modify-write, so concurrent increments may be lost.
+    executions: AtomicU64,
+}
+
+/// A guard failure, as recorded by [record_guard_failure].
+#[derive(Clone, Copy, Debug)]
+pub struct FailedGuard {
+    /// The [ExecInfo] of the compiled trace containing the guard.
+    trace: *const c_void,
+    /// The ID of the guard.
+    guard_idx: usize,
+    /// How long deoptimising took.
+    stopgap_time: Duration,
+}
+
+impl FailedGuard {
+    /// How long did deoptimising after this guard failure take?
+    pub fn stopgap_time(&self) -> Duration {
+        self.stopgap_time
+    }
+}
+
+/// Record that the guard with ID `guard_idx` in the compiled trace described by the [ExecInfo]
+/// `trace` has failed, and that deoptimising took `stopgap_time`. This is called when
+/// deoptimising.
+pub fn record_guard_failure(trace: *const c_void, guard_idx: usize, stopgap_time: Duration) {
+    FAILED_GUARD.with(|fg| {
+        fg.set(Some(FailedGuard {
+            trace,
+            guard_idx,
+            stopgap_time,
+        }))
+    });
+}
+
+/// Return the guard failure most recently recorded in the current thread, if there is one that
+/// hasn't already been taken.
+pub fn take_failed_guard() -> Option<FailedGuard> {
+    FAILED_GUARD.with(|fg| fg.take())
+}
+
+/// The state of a guard in a [CompiledTrace], used to decide whether to compile a side trace for
+/// the guard.
+#[derive(Debug, Default)]
+pub struct Guard {
+    /// How many times this guard has failed since a side trace was last attempted for it.
+    failures: AtomicU32,
+    /// Is a thread currently tracing, or waiting for the compilation of, a side trace for this
+    /// guard?
+    side_tracing: AtomicBool,
+    /// How many times tracing or compiling a side trace for this guard has failed.
+    side_trace_failures: AtomicU16,
+    /// The side trace for this guard, once it has been compiled.
+    side_trace: OnceLock<Arc<CompiledTrace>>,
+}
+
+impl Guard {
+    /// Record that this guard has failed, returning how many times it has failed since a side
+    /// trace was last attempted for it (including this failure).
+    pub fn failed(&self) -> u32 {
+        self.failures
+            .fetch_add(1, Ordering::Relaxed)
+            .saturating_add(1)
+    }
+
+    /// Return how many times this guard has failed since a side trace was last attempted for it.
+    pub fn failures(&self) -> u32 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Try to claim this guard for side tracing, returning `true` if the caller should start
+    /// tracing a side trace for it. At most one thread at a time can claim a guard, and a guard
+    /// which already has a side trace can't be claimed.
+    pub fn claim_side_trace(&self) -> bool {
+        self.side_trace.get().is_none()
+            && self
+                .side_tracing
+                .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    /// Record that tracing or compiling the side trace for this guard failed, releasing the claim
+    /// on the guard so that it can be side traced again once it has failed often enough.
+    pub fn side_trace_failed(&self) {
+        self.side_trace_failures.fetch_add(1, Ordering::Relaxed);
+        self.release_side_trace();
+    }
+
+    /// Release the claim on this guard without recording a failure (e.g. because the interpreter
+    /// aborted the side trace), so that it can be side traced again once it has failed often
+    /// enough.
+    pub fn release_side_trace(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        self.side_tracing.store(false, Ordering::Relaxed);
+    }
+
+    /// How many times has tracing or compiling a side trace for this guard failed? Notice that
+    /// this value can be changed by other threads and is thus potentially stale as soon as it is
+    /// read.
+    pub fn side_trace_failures(&self) -> u16 {
+        self.side_trace_failures.load(Ordering::Relaxed)
+    }
+
+    /// Does this guard have a side trace?
+    pub fn has_side_trace(&self) -> bool {
+        self.side_trace.get().is_some()
+    }
+}
+
 impl Drop for CompiledTrace {
     fn drop(&mut self) {
         // The memory holding the AOT live values needs to live as long as the trace. Now that we
         // no longer need the trace, this can be freed too.
-        // FIXME: Free the memory for the stackmap which was allocated in ykllvmwrap/memman.cc.
-        unsafe { libc::free(self.aotvals as *mut c_void) };
+        unsafe { libc::free(self.exec_info.aotvals as *mut c_void) };
+        // Free the machine code, data, and stackmap of the trace.
+        if !self.info.is_null() {
+            unsafe { ykllvmwrap::__ykllvmwrap_free_compiled_trace(self.info) };
+        }
     }
 }
 
@@ -394,15 +918,53 @@ unsafe impl Sync for CompiledTrace {}
 pub struct ThreadTracer {
     /// The tracing implementation.
     t_impl: Box<dyn ThreadTracerImpl>,
+    /// The values passed to `yk_promote` so far.
+    promotions: Vec<usize>,
 }
 
 impl ThreadTracer {
     /// Stops tracing on the current thread, returning a IR trace on success.
     pub fn stop_tracing(mut self) -> Result<Box<dyn UnmappedTrace>, InvalidTraceError> {
-        self.t_impl.stop_tracing()
+        let trace = self.t_impl.stop_tracing()?;
+        if self.promotions.is_empty() {
+            Ok(trace)
+        } else {
+            Ok(Box::new(PromotedTrace {
+                trace,
+                promotions: self.promotions,
+            }))
+        }
+    }
+}
+
+/// An unmapped trace during whose collection values were passed to `yk_promote`. The values are
+/// independent of the tracing backend, so they are attached to the trace once it has been mapped.
+struct PromotedTrace {
+    trace: Box<dyn UnmappedTrace>,
+    promotions: Vec<usize>,
+}
+
+impl UnmappedTrace for PromotedTrace {
+    fn map(
+        self: Box<Self>,
+        decoder: Option<TraceDecoderKind>,
+    ) -> Result<IRTrace, InvalidTraceError> {
+        let mut irtrace = self.trace.map(decoder)?;
+        irtrace.promotions = self.promotions;
+        Ok(irtrace)
     }
 }
 
+/// Record that `val` was passed to `yk_promote`, so that the trace compiler can specialise the
+/// trace being collected on it. Does nothing if the current thread is not tracing.
+pub fn record_promotion(val: usize) {
+    THREAD_TRACER.with(|tt| {
+        if let Some(tt) = tt.borrow_mut().as_mut() {
+            tt.promotions.push(val);
+        }
+    });
+}
+
 // An generic interface which tracing backends must fulfill.
 trait ThreadTracerImpl {
     /// Stops tracing on the current thread, returning the IR trace on success.
@@ -414,7 +976,19 @@ trait ThreadTracerImpl {
 /// there is already an active tracer leads to undefined behaviour.
 pub fn start_tracing(kind: TracingKind) {
     let tt = match kind {
-        TracingKind::SoftwareTracing => todo!(),
+        TracingKind::SoftwareTracing => swt::start_tracing(true),
+        TracingKind::HardwareTracing => hwt::start_tracing(),
+    };
+    THREAD_TRACER.with(|tl| *tl.borrow_mut() = Some(tt));
+}
+
+/// Start tracing a side trace on the current thread using the specified tracing kind. Unlike
+/// [start_tracing], which is called from a control point, this must be called after a guard has
+/// failed and immediately before execution resumes in the AOT code, so that the trace starts where
+/// the guard failed. The same caveats apply as for [start_tracing].
+pub fn start_side_tracing(kind: TracingKind) {
+    let tt = match kind {
+        TracingKind::SoftwareTracing => swt::start_tracing(false),
         TracingKind::HardwareTracing => hwt::start_tracing(),
     };
     THREAD_TRACER.with(|tl| *tl.borrow_mut() = Some(tt));
@@ -432,5 +1006,10 @@ pub fn stop_tracing() -> Result<Box<dyn UnmappedTrace>, InvalidTraceError> {
 }
 
 pub trait UnmappedTrace: Send {
-    fn map(self: Box<Self>, decoder: TraceDecoderKind) -> Result<IRTrace, InvalidTraceError>;
+    /// Map this trace into an [IRTrace]. `decoder` is the decoder to use for hardware traces: it
+    /// is ignored by other tracing backends.
+    fn map(
+        self: Box<Self>,
+        decoder: Option<TraceDecoderKind>,
+    ) -> Result<IRTrace, InvalidTraceError>;
 }
@@ -0,0 +1,87 @@
+//! Options controlling how traces are compiled.
+
+use std::{ffi::c_uint, path::Path, str::FromStr, sync::Arc};
+
+/// The stages of the JIT pipeline at which the trace compiler prints LLVM IR to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrintIR {
+    /// The IR embedded in the ahead-of-time compiled binary.
+    pub aot: bool,
+    /// The IR of a trace before it is optimised. A trace loaded from the trace cache has no IR, so
+    /// nothing is printed for it.
+    pub jit_pre_opt: bool,
+    /// The IR of a trace after it is optimised. As with `jit_pre_opt`, nothing is printed for a
+    /// trace loaded from the trace cache.
+    pub jit_post_opt: bool,
+}
+
+impl PrintIR {
+    /// Encode the stages as bits for ykllvmwrap's `DebugIRPrinter`, whose `DebugIR` enum gives the
+    /// index of each stage's bit.
+    pub(crate) fn bits(&self) -> c_uint {
+        c_uint::from(self.aot)
+            | c_uint::from(self.jit_pre_opt) << 1
+            | c_uint::from(self.jit_post_opt) << 2
+    }
+}
+
+impl FromStr for PrintIR {
+    type Err = String;
+
+    /// Parse a comma-separated list of the stages `aot`, `jit-pre-opt`, and `jit-post-opt`. An
+    /// empty string selects no stages.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut print_ir = Self::default();
+        for stage in s.split(',').filter(|x| !x.is_empty()) {
+            match stage {
+                "aot" => print_ir.aot = true,
+                "jit-pre-opt" => print_ir.jit_pre_opt = true,
+                "jit-post-opt" => print_ir.jit_post_opt = true,
+                _ => {
+                    return Err(format!(
+                        "'{}' is not one of 'aot', 'jit-pre-opt', or 'jit-post-opt'",
+                        stage
+                    ))
+                }
+            }
+        }
+        Ok(print_ir)
+    }
+}
+
+/// Options controlling how traces are compiled.
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    /// The stages at which to print LLVM IR.
+    pub print_ir: PrintIR,
+    /// Add debugging information to compiled traces, so that debuggers can show a trace's IR
+    /// as its source code.
+    pub trace_debuginfo: bool,
+    /// Add an entry for each compiled trace to the perf map `/tmp/perf-<pid>.map`.
+    pub perf_map: bool,
+    /// The directory (as returned by [crate::trace_cache_dir]) of a cache from which a trace's
+    /// machine code is taken, without building the trace's JIT module, if it has been compiled
+    /// before, and to which it is added otherwise.
+    pub trace_cache: Option<Arc<Path>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrintIR;
+
+    #[test]
+    fn print_ir() {
+        assert_eq!("".parse::<PrintIR>().unwrap(), PrintIR::default());
+        let print_ir = "jit-post-opt,aot".parse::<PrintIR>().unwrap();
+        assert_eq!(
+            print_ir,
+            PrintIR {
+                aot: true,
+                jit_pre_opt: false,
+                jit_post_opt: true
+            }
+        );
+        assert_eq!(print_ir.bits(), 0b101);
+        assert!("aot,jit".parse::<PrintIR>().is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Saving mapped traces to disk, with `YKD_SAVE_TRACES=<dir>`, so that they can be replayed
+//! through the trace compiler by another process.
+//!
+//! A saved trace is a text file. Its first line identifies the format, and each further line is a
+//! keyword followed by space-separated values:
+//!
+//! ```text
+//! yk-saved-trace 1
+//! binary <path of the binary the trace was collected from>
+//! bitcode <hash of the binary's embedded bitcode, in hex>
+//! block <function name> <block index>
+//! unmappable <stack adjustment>
+//! faddr <function name> <address, in hex>
+//! promotion <value passed to yk_promote>
+//! ```
+//!
+//! `block` and `unmappable` lines are in trace order, as are `promotion` lines. `faddr` lines are
+//! only for information: their addresses are those of the process which saved the trace, and mean
+//! nothing (not least because of ASLR) to the process replaying it, so they aren't used on replay.
+
+use crate::{IRBlock, IRTrace};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    error::Error,
+    ffi::{c_void, CString},
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process, slice,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+};
+use ykutil::obj::{llvmbc_section, SELF_BIN_PATH};
+
+/// The first line of a saved trace.
+const MAGIC: &str = "yk-saved-trace 1";
+
+/// The directory to save traces in, if `YKD_SAVE_TRACES` is set.
+static SAVE_DIR: LazyLock<Option<PathBuf>> =
+    LazyLock::new(|| env::var_os("YKD_SAVE_TRACES").map(PathBuf::from));
+
+/// How many traces this process has saved so far.
+static SAVED: AtomicU64 = AtomicU64::new(0);
+
+/// The hash of the bitcode embedded in the current binary.
+pub(crate) static SELF_BITCODE_HASH: LazyLock<u64> = LazyLock::new(|| {
+    let (data, len) = llvmbc_section();
+    bitcode_hash(unsafe { slice::from_raw_parts(data, usize::try_from(len).unwrap()) })
+});
+
+/// Hash a binary's embedded bitcode. A saved trace records the hash of the bitcode it was
+/// collected against, so that it isn't replayed against a different binary.
+fn bitcode_hash(bitcode: &[u8]) -> u64 {
+    fxhash::hash64(bitcode)
+}
+
+/// If `YKD_SAVE_TRACES` is set, save `trace` in the directory it names. Errors are reported on
+/// stderr, but otherwise ignored: a trace which couldn't be saved can still be compiled.
+pub(crate) fn save_trace(trace: &IRTrace) {
+    if let Some(dir) = &*SAVE_DIR {
+        let path = dir.join(format!(
+            "{}-{}.yktrace",
+            process::id(),
+            SAVED.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = fs::write(&path, serialise(trace, &SELF_BIN_PATH, *SELF_BITCODE_HASH)) {
+            eprintln!("Can't save trace to {:?}: {}", path, e);
+        }
+    }
+}
+
+fn serialise(trace: &IRTrace, binary: &Path, bitcode_hash: u64) -> String {
+    let mut s = String::new();
+    writeln!(s, "{}", MAGIC).unwrap();
+    writeln!(s, "binary {}", binary.display()).unwrap();
+    writeln!(s, "bitcode {:x}", bitcode_hash).unwrap();
+    for blk in &trace.blocks {
+        match blk {
+            IRBlock::Mapped { func_name, bb } => {
+                writeln!(s, "block {} {}", func_name.to_string_lossy(), bb).unwrap()
+            }
+            IRBlock::Unmappable { stack_adjust } => {
+                writeln!(s, "unmappable {}", stack_adjust).unwrap()
+            }
+        }
+    }
+    for (func_name, addr) in &trace.faddrs {
+        writeln!(
+            s,
+            "faddr {} {:x}",
+            func_name.to_string_lossy(),
+            *addr as usize
+        )
+        .unwrap();
+    }
+    for val in &trace.promotions {
+        writeln!(s, "promotion {}", val).unwrap();
+    }
+    s
+}
+
+/// A trace saved with `YKD_SAVE_TRACES`.
+pub struct SavedTrace {
+    /// The path of the binary the trace was collected from.
+    binary: PathBuf,
+    /// The hash of the bitcode embedded in `binary`.
+    bitcode_hash: u64,
+    trace: IRTrace,
+}
+
+impl SavedTrace {
+    /// Load the saved trace at `path`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let s = fs::read_to_string(path)?;
+        Self::parse(&s).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut lines = s.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err("Not a saved trace".to_owned());
+        }
+        let mut binary = None;
+        let mut bitcode_hash = None;
+        let mut blocks = Vec::new();
+        let mut faddrs = HashMap::new();
+        let mut promotions = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let err = |msg: &str| format!("line {}: {}", i + 2, msg);
+            let (key, val) = line.split_once(' ').ok_or_else(|| err("Missing value"))?;
+            match key {
+                "binary" => binary = Some(PathBuf::from(val)),
+                "bitcode" => {
+                    bitcode_hash =
+                        Some(u64::from_str_radix(val, 16).map_err(|e| err(&e.to_string()))?)
+                }
+                "block" => {
+                    let (func_name, bb) =
+                        val.split_once(' ').ok_or_else(|| err("Missing block"))?;
+                    let func_name = CString::new(func_name).map_err(|e| err(&e.to_string()))?;
+                    let bb = bb.parse().map_err(|_| err("Invalid block"))?;
+                    blocks.push(IRBlock::new_mapped(func_name, bb));
+                }
+                "unmappable" => {
+                    let stack_adjust = val.parse().map_err(|_| err("Invalid stack adjustment"))?;
+                    blocks.push(IRBlock::new_unmappable(stack_adjust));
+                }
+                "faddr" => {
+                    let (func_name, addr) =
+                        val.split_once(' ').ok_or_else(|| err("Missing address"))?;
+                    let func_name = CString::new(func_name).map_err(|e| err(&e.to_string()))?;
+                    let addr =
+                        usize::from_str_radix(addr, 16).map_err(|_| err("Invalid address"))?;
+                    faddrs.insert(func_name, addr as *const c_void);
+                }
+                "promotion" => promotions.push(val.parse().map_err(|_| err("Invalid value"))?),
+                _ => return Err(err(&format!("Unknown keyword '{}'", key))),
+            }
+        }
+        if blocks.is_empty() {
+            return Err("Empty trace".to_owned());
+        }
+        let mut trace = IRTrace::new(blocks, faddrs);
+        trace.promotions = promotions;
+        Ok(Self {
+            binary: binary.ok_or("Missing binary")?,
+            bitcode_hash: bitcode_hash.ok_or("Missing bitcode hash")?,
+            trace,
+        })
+    }
+
+    /// The path of the binary the trace was collected from.
+    pub fn binary(&self) -> &Path {
+        &self.binary
+    }
+
+    pub fn trace(&self) -> &IRTrace {
+        &self.trace
+    }
+
+    /// Read the bitcode embedded in `binary`, checking that it's the same as that of the binary
+    /// the trace was collected from.
+    pub fn bitcode(&self, binary: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        let elf = elf::File::open_path(binary)
+            .map_err(|e| format!("Can't read {}: {:?}", binary.display(), e))?;
+        let sec = elf
+            .get_section(".llvmbc")
+            .ok_or_else(|| format!("{} has no embedded bitcode", binary.display()))?;
+        // The section starts with the length of the bitcode (see `ykutil::obj::llvmbc_section`).
+        let malformed = || format!("{} has a malformed .llvmbc section", binary.display());
+        let len = sec
+            .data
+            .get(..8)
+            .map(|b| u64::from_ne_bytes(<[u8; 8]>::try_from(b).unwrap()))
+            .ok_or_else(malformed)?;
+        let bitcode = usize::try_from(len)
+            .ok()
+            .and_then(|len| sec.data.get(8..8 + len))
+            .ok_or_else(malformed)?;
+        if bitcode_hash(bitcode) != self.bitcode_hash {
+            return Err(format!(
+                "{} is not the binary the trace was collected from",
+                binary.display()
+            )
+            .into());
+        }
+        Ok(bitcode.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bitcode_hash, serialise, SavedTrace};
+    use crate::{IRBlock, IRTrace};
+    use std::{collections::HashMap, ffi::CString, path::Path};
+
+    fn blocks() -> Vec<IRBlock> {
+        vec![
+            IRBlock::new_mapped(CString::new("main").unwrap(), 1),
+            IRBlock::new_mapped(CString::new("main").unwrap(), 2),
+            IRBlock::new_unmappable(-1),
+            IRBlock::new_mapped(CString::new("main").unwrap(), 1),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut faddrs = HashMap::new();
+        faddrs.insert(CString::new("f").unwrap(), 0x1234 as *const _);
+        let mut trace = IRTrace::new(blocks(), faddrs.clone());
+        trace.promotions = vec![3, 4];
+
+        let hash = bitcode_hash(b"bitcode");
+        let s = serialise(&trace, Path::new("/bin/interp"), hash);
+        let saved = SavedTrace::parse(&s).unwrap();
+        assert_eq!(saved.binary(), Path::new("/bin/interp"));
+        assert_eq!(saved.bitcode_hash, hash);
+        assert_eq!(saved.trace.blocks, blocks());
+        assert_eq!(saved.trace.faddrs, faddrs);
+        assert_eq!(saved.trace.promotions, vec![3, 4]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(SavedTrace::parse("").is_err());
+        assert!(SavedTrace::parse("yk-saved-trace 1\nbinary /bin/interp\nbitcode 1\n").is_err());
+        assert_eq!(
+            SavedTrace::parse("yk-saved-trace 1\nblock main x\n")
+                .err()
+                .unwrap(),
+            "line 2: Invalid block"
+        );
+    }
+}
@@ -0,0 +1,490 @@
+//! Software tracing via ykllvm instrumentation.
+//!
+//! When the interpreter is built with software tracing instrumentation, ykllvm inserts a call to
+//! `__yk_trace_basicblock()` at the start of every IR block, and again immediately after every
+//! (non-inlined) call returns. Each call passes the name of the containing function and the index
+//! of the block within that function. While a thread is being software traced, these calls are
+//! recorded in a thread-local buffer; otherwise they are ignored.
+//!
+//! Since no hardware is involved, this backend works on any platform, at the cost of slowing down
+//! the interpreter even when not tracing.
+
+use super::{IRBlock, IRTrace, ThreadTracer, ThreadTracerImpl, UnmappedTrace};
+use crate::errors::InvalidTraceError;
+use hwtracer::decode::TraceDecoderKind;
+use libc::{c_void, dlsym};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    convert::TryFrom,
+    ffi::{c_char, CStr, CString},
+    ptr,
+    sync::LazyLock,
+};
+use ykutil::obj::{llvmbc_section, PHDR_OBJECT_CACHE, SELF_BIN_PATH};
+
+thread_local! {
+    // Is the current thread currently being software traced?
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    // The blocks recorded so far for the current thread's trace.
+    static RECORDED: RefCell<Vec<RecordedBlock>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A block as recorded by the instrumentation.
+#[derive(Clone, Copy, Debug)]
+struct RecordedBlock {
+    /// The name of the function containing the block. This points to a string constant in the
+    /// interpreter binary, so it lives for as long as the process does.
+    func_name: *const c_char,
+    /// The index of the block within the function.
+    bb: usize,
+}
+
+/// Record that the current thread entered (or returned into) the IR block with index `bb` of the
+/// function named `func_name`. This is called by the instrumentation inserted by ykllvm, and is a
+/// no-op unless the current thread is being software traced.
+#[no_mangle]
+pub extern "C" fn __yk_trace_basicblock(func_name: *const c_char, bb: usize) {
+    if ACTIVE.with(|a| a.get()) {
+        RECORDED.with(|r| r.borrow_mut().push(RecordedBlock { func_name, bb }));
+    }
+}
+
+/// Software thread tracer.
+struct SWTThreadTracer {
+    active: bool,
+    /// Did tracing start at a control point (rather than at a guard failure)?
+    from_control_point: bool,
+}
+
+impl ThreadTracerImpl for SWTThreadTracer {
+    fn stop_tracing(&mut self) -> Result<Box<dyn UnmappedTrace>, InvalidTraceError> {
+        self.active = false;
+        ACTIVE.with(|a| a.set(false));
+        let recorded = RECORDED.with(|r| r.take());
+        Ok(Box::new(SWTrace {
+            recorded,
+            from_control_point: self.from_control_point,
+        }))
+    }
+}
+
+impl Drop for SWTThreadTracer {
+    fn drop(&mut self) {
+        if self.active {
+            ACTIVE.with(|a| a.set(false));
+            RECORDED.with(|r| r.borrow_mut().clear());
+        }
+    }
+}
+
+pub(crate) fn start_tracing(from_control_point: bool) -> ThreadTracer {
+    RECORDED.with(|r| r.borrow_mut().clear());
+    ACTIVE.with(|a| a.set(true));
+    ThreadTracer {
+        t_impl: Box::new(SWTThreadTracer {
+            active: true,
+            from_control_point,
+        }),
+        promotions: Vec::new(),
+    }
+}
+
+struct SWTrace {
+    recorded: Vec<RecordedBlock>,
+    /// Did tracing start at a control point (rather than at a guard failure)?
+    from_control_point: bool,
+}
+
+/// The function name pointers inside a `SWTrace` point to immutable string constants, so it is
+/// safe to send them to another thread for mapping.
+unsafe impl Send for SWTrace {}
+
+impl UnmappedTrace for SWTrace {
+    fn map(
+        self: Box<Self>,
+        _decoder: Option<TraceDecoderKind>,
+    ) -> Result<IRTrace, InvalidTraceError> {
+        let recorded = self
+            .recorded
+            .iter()
+            .map(|rb| (unsafe { CStr::from_ptr(rb.func_name) }.to_owned(), rb.bb))
+            .collect::<Vec<_>>();
+
+        // The instrumentation only tells us about function names, so we have to look up the
+        // addresses of functions ourselves.
+        let mut faddrs = HashMap::new();
+        for (func_name, _) in &recorded {
+            if !faddrs.contains_key(func_name) {
+                if let Some(vaddr) = func_vaddr(func_name) {
+                    faddrs.insert(func_name.to_owned(), vaddr as *const c_void);
+                }
+            }
+        }
+
+        let mapped = map_recorded(recorded, self.from_control_point, &AOTModule);
+        if mapped.is_empty() {
+            return Err(InvalidTraceError::EmptyTrace);
+        }
+        Ok(IRTrace::new(mapped, faddrs))
+    }
+}
+
+/// The addresses, relative to the binary's load address, of the functions in the current binary's
+/// symbol table. Unlike `dlsym`, this includes functions with internal linkage.
+static SELF_FUNCS: LazyLock<HashMap<CString, u64>> = LazyLock::new(|| {
+    let mut funcs = HashMap::new();
+    let elf = match elf::File::open_path(&*SELF_BIN_PATH) {
+        Ok(x) => x,
+        Err(_) => return funcs,
+    };
+    if let Some(symtab) = elf.get_section(".symtab") {
+        for sym in elf.get_symbols(symtab).unwrap_or_default() {
+            if sym.symtype == elf::types::STT_FUNC && sym.shndx != 0 {
+                if let Ok(name) = CString::new(sym.name) {
+                    funcs.insert(name, sym.value);
+                }
+            }
+        }
+    }
+    funcs
+});
+
+/// Find the address of the function `func_name`. As the hardware tracing mapper does, we prefer a
+/// function in the current binary; failing that, we ask the dynamic linker.
+fn func_vaddr(func_name: &CStr) -> Option<usize> {
+    if let Some(off) = SELF_FUNCS.get(func_name) {
+        // The main binary is the object with an empty name.
+        if let Some(obj) = PHDR_OBJECT_CACHE
+            .iter()
+            .find(|o| o.name().to_bytes().is_empty())
+        {
+            return Some(usize::try_from(obj.addr() + off).unwrap());
+        }
+    }
+    let vaddr = unsafe { dlsym(ptr::null_mut(), func_name.as_ptr()) };
+    if vaddr.is_null() {
+        None
+    } else {
+        Some(vaddr as usize)
+    }
+}
+
+/// What the mapper needs to know about the IR blocks of the interpreter.
+trait AOTBlocks {
+    /// Might block `bb` of `func_name` call `callee` directly (i.e. other than via foreign code)?
+    fn may_call(&self, func_name: &CStr, bb: usize, callee: &CStr) -> bool;
+    /// Does block `bb` of `func_name` return from `func_name`?
+    fn returns(&self, func_name: &CStr, bb: usize) -> bool;
+}
+
+/// The IR embedded in the current binary.
+struct AOTModule;
+
+impl AOTBlocks for AOTModule {
+    fn may_call(&self, func_name: &CStr, bb: usize, callee: &CStr) -> bool {
+        let (llvmbc_data, llvmbc_len) = llvmbc_section();
+        unsafe {
+            ykllvmwrap::__ykllvmwrap_block_may_call(
+                func_name.as_ptr(),
+                bb,
+                callee.as_ptr(),
+                llvmbc_data,
+                llvmbc_len,
+            )
+        }
+    }
+
+    fn returns(&self, func_name: &CStr, bb: usize) -> bool {
+        let (llvmbc_data, llvmbc_len) = llvmbc_section();
+        unsafe {
+            ykllvmwrap::__ykllvmwrap_block_returns(func_name.as_ptr(), bb, llvmbc_data, llvmbc_len)
+        }
+    }
+}
+
+/// A frame of the call stack, as reconstructed by [map_recorded].
+#[derive(Debug)]
+enum Frame {
+    /// An instrumented function, whose most recently recorded block is `bb`.
+    Mapped { func_name: CString, bb: usize },
+    /// Foreign (uninstrumented) code which called back into instrumented code.
+    Foreign,
+}
+
+/// If the nearest instrumented frame below the top of `stack` is `func_name` executing `bb` (i.e.
+/// the top frame has returned to it), pop the frames above it, returning how many of them were
+/// foreign.
+fn pop_to_caller(stack: &mut Vec<Frame>, func_name: &CStr, bb: usize) -> Option<isize> {
+    let mut foreign = 0;
+    for (i, frame) in stack.iter().enumerate().rev().skip(1) {
+        match frame {
+            Frame::Foreign => foreign += 1,
+            Frame::Mapped {
+                func_name: caller,
+                bb: caller_bb,
+            } => {
+                if caller.as_c_str() != func_name || *caller_bb != bb {
+                    return None;
+                }
+                stack.truncate(i + 1);
+                return Some(foreign);
+            }
+        }
+    }
+    None
+}
+
+/// Convert the blocks recorded by the instrumentation into the same form of trace that the
+/// hardware tracing mapper produces. `from_control_point` says whether tracing started at a
+/// control point.
+///
+/// The instrumentation doesn't record foreign code, so we reconstruct the call stack to find
+/// where the hardware mapper would have seen an unmappable region. Since the IR blocks entered
+/// by an unmappable region tell the trace compiler how the stack changed, we also reconstruct
+/// those changes, assuming that foreign code calls back into instrumented code directly.
+fn map_recorded(
+    recorded: Vec<(CString, usize)>,
+    from_control_point: bool,
+    aot: &dyn AOTBlocks,
+) -> Vec<IRBlock> {
+    let mut ret = Vec::with_capacity(recorded.len() + 1);
+    let mut stack = Vec::new();
+    // Tracing is stopped by a call to the control point, so the last block recorded is the block
+    // containing the control point call. If tracing started at the same control point, the trace
+    // really starts part-way through that same block, but the instrumentation couldn't have
+    // recorded it because tracing wasn't yet active when the block was entered. A trace started
+    // at a guard failure starts part-way through the block containing the guard, but the trace
+    // compiler doesn't need to see that block again.
+    match recorded.last() {
+        Some((func_name, bb)) => {
+            if from_control_point {
+                ret.push(IRBlock::new_mapped(func_name.to_owned(), *bb));
+                stack.push(Frame::Mapped {
+                    func_name: func_name.to_owned(),
+                    bb: *bb,
+                });
+            }
+        }
+        None => return ret,
+    }
+    for (func_name, bb) in recorded {
+        let (cur, cur_bb) = match stack.last() {
+            Some(Frame::Mapped {
+                func_name: cur,
+                bb: cur_bb,
+            }) => (cur.clone(), *cur_bb),
+            Some(Frame::Foreign) => unreachable!(),
+            None => {
+                stack.push(Frame::Mapped {
+                    func_name: func_name.clone(),
+                    bb,
+                });
+                ret.push(IRBlock::new_mapped(func_name, bb));
+                continue;
+            }
+        };
+        if cur == func_name && cur_bb == bb {
+            // The `BlockDisambiguate` pass in ykllvm ensures that no IR block ever branches
+            // straight back to itself, so seeing the same block twice in a row means that the
+            // block called a function for which no blocks were recorded, and that function then
+            // returned. That can only be foreign code, which the hardware mapper would have
+            // reported as an unmappable region that returns to its caller.
+            ret.push(IRBlock::new_unmappable(-1));
+        } else if let Some(foreign) = aot
+            .returns(&cur, cur_bb)
+            .then(|| pop_to_caller(&mut stack, &func_name, bb))
+            .flatten()
+        {
+            // The current function returned to its caller, perhaps via foreign code.
+            if foreign > 0 {
+                ret.push(IRBlock::new_unmappable(-foreign));
+            }
+        } else if bb == 0 {
+            // A function was called. If the current block can't have called it directly, it must
+            // have been called back by foreign code, which either the current block called, or
+            // (if the current block returned) which called the current function.
+            if !aot.may_call(&cur, cur_bb, &func_name) {
+                if aot.returns(&cur, cur_bb)
+                    && matches!(stack.iter().rev().nth(1), Some(Frame::Foreign))
+                {
+                    stack.pop();
+                } else {
+                    stack.push(Frame::Foreign);
+                }
+                ret.push(IRBlock::new_unmappable(1));
+            }
+            stack.push(Frame::Mapped {
+                func_name: func_name.clone(),
+                bb,
+            });
+        } else if cur != func_name {
+            // Control left the current function other than by a return we could follow (e.g. by
+            // `longjmp`). All we can do is to start reconstructing the stack afresh.
+            if pop_to_caller(&mut stack, &func_name, bb).is_none() {
+                stack.clear();
+                stack.push(Frame::Mapped {
+                    func_name: func_name.clone(),
+                    bb,
+                });
+            }
+        }
+        if let Some(Frame::Mapped { bb: top_bb, .. }) = stack.last_mut() {
+            *top_bb = bb;
+        }
+        ret.push(IRBlock::new_mapped(func_name, bb));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map_recorded, AOTBlocks};
+    use crate::IRBlock;
+    use std::ffi::{CStr, CString};
+
+    /// A stand-in for the interpreter's IR: `calls` lists the blocks which call a function
+    /// directly, and `returns` the blocks which return.
+    struct Stub {
+        calls: &'static [(&'static str, usize, &'static str)],
+        returns: &'static [(&'static str, usize)],
+    }
+
+    impl AOTBlocks for Stub {
+        fn may_call(&self, func_name: &CStr, bb: usize, callee: &CStr) -> bool {
+            self.calls.iter().any(|(f, b, c)| {
+                f.as_bytes() == func_name.to_bytes()
+                    && *b == bb
+                    && c.as_bytes() == callee.to_bytes()
+            })
+        }
+
+        fn returns(&self, func_name: &CStr, bb: usize) -> bool {
+            self.returns
+                .iter()
+                .any(|(f, b)| f.as_bytes() == func_name.to_bytes() && *b == bb)
+        }
+    }
+
+    const NO_CALLS: Stub = Stub {
+        calls: &[],
+        returns: &[],
+    };
+
+    fn mapped(func_name: &str, bb: usize) -> IRBlock {
+        IRBlock::new_mapped(CString::new(func_name).unwrap(), bb)
+    }
+
+    fn recorded(blocks: &[(&str, usize)]) -> Vec<(CString, usize)> {
+        blocks
+            .iter()
+            .map(|(f, bb)| (CString::new(*f).unwrap(), *bb))
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert!(map_recorded(Vec::new(), true, &NO_CALLS).is_empty());
+        assert!(map_recorded(Vec::new(), false, &NO_CALLS).is_empty());
+    }
+
+    #[test]
+    fn starts_with_control_point_block() {
+        let trace = map_recorded(
+            recorded(&[("main", 2), ("main", 3), ("main", 1)]),
+            true,
+            &NO_CALLS,
+        );
+        assert_eq!(
+            trace,
+            vec![
+                mapped("main", 1),
+                mapped("main", 2),
+                mapped("main", 3),
+                mapped("main", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn side_trace_starts_with_first_recorded_block() {
+        let trace = map_recorded(
+            recorded(&[("main", 2), ("main", 3), ("main", 1)]),
+            false,
+            &NO_CALLS,
+        );
+        assert_eq!(
+            trace,
+            vec![mapped("main", 2), mapped("main", 3), mapped("main", 1)]
+        );
+    }
+
+    #[test]
+    fn foreign_calls() {
+        let trace = map_recorded(
+            recorded(&[
+                ("main", 2),
+                ("main", 2),
+                ("f", 0),
+                ("f", 1),
+                ("main", 2),
+                ("main", 1),
+            ]),
+            true,
+            &Stub {
+                calls: &[("main", 2, "f")],
+                returns: &[("f", 1)],
+            },
+        );
+        assert_eq!(
+            trace,
+            vec![
+                mapped("main", 1),
+                mapped("main", 2),
+                IRBlock::new_unmappable(-1),
+                mapped("main", 2),
+                mapped("f", 0),
+                mapped("f", 1),
+                mapped("main", 2),
+                mapped("main", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn foreign_callbacks() {
+        // `main` calls foreign code (e.g. `qsort`), which calls `cmp` back twice before returning.
+        let trace = map_recorded(
+            recorded(&[
+                ("main", 2),
+                ("cmp", 0),
+                ("cmp", 1),
+                ("cmp", 0),
+                ("cmp", 1),
+                ("main", 2),
+                ("main", 1),
+            ]),
+            true,
+            &Stub {
+                calls: &[],
+                returns: &[("cmp", 1)],
+            },
+        );
+        assert_eq!(
+            trace,
+            vec![
+                mapped("main", 1),
+                mapped("main", 2),
+                IRBlock::new_unmappable(1),
+                mapped("cmp", 0),
+                mapped("cmp", 1),
+                IRBlock::new_unmappable(1),
+                mapped("cmp", 0),
+                mapped("cmp", 1),
+                IRBlock::new_unmappable(-1),
+                mapped("main", 2),
+                mapped("main", 1)
+            ]
+        );
+    }
+}